@@ -1,6 +1,5 @@
 use std::iter::Sum;
-use std::ops::{Add, Sub, Mul, Div, Range, Neg};
-use rand::Rng;
+use std::ops::{Add, Sub, Mul, Div, Neg, Index};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(crate) struct Vec3d {
@@ -43,6 +42,44 @@ impl Vec3d {
     }
 }
 
+impl Vec3d {
+    pub(crate) fn min(&self, other: &Vec3d) -> Vec3d {
+        Self{
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    pub(crate) fn max(&self, other: &Vec3d) -> Vec3d {
+        Self{
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    pub(crate) fn abs(&self) -> Vec3d {
+        Self{
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+}
+
+impl Vec3d {
+    //two unit vectors that form a right-handed orthonormal basis with this unit vector (Duff et al. 2017)
+    pub(crate) fn orthonormal_basis(&self) -> (Vec3d, Vec3d) {
+        let sign = 1f64.copysign(self.z);
+        let a = -1. / (sign + self.z);
+        let b = self.x * self.y * a;
+        let tangent = Vec3d::new(1. + sign * self.x * self.x * a, sign * b, -sign * self.x);
+        let bitangent = Vec3d::new(b, sign + self.y * self.y * a, -self.y);
+        return (tangent, bitangent);
+    }
+}
+
 impl Vec3d{
     pub(crate) fn new(x:f64, y:f64, z:f64) -> Self{
         Self{
@@ -82,7 +119,7 @@ impl Vec3d{
         return self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    fn length(self) -> f64{
+    pub(crate) fn length(self) -> f64{
         return f64::sqrt(self.length_squared());
     }
 
@@ -90,7 +127,7 @@ impl Vec3d{
         return self.x * other.x + self.y * other.y + self.z * other.z;
     }
 
-    fn cross(self, other: Vec3d) -> Self{
+    pub(crate) fn cross(self, other: Vec3d) -> Self{
         Self{
             x: self.y * other.z - self.z * other.y,
             y: self.z * other.x - self.x * other.z,
@@ -127,40 +164,6 @@ impl Vec3d{
     //     return f64::abs(self.x) < s && f64::abs(self.y) < s && f64::abs(self.z) < s;
     // }
 
-    pub(crate) fn random_01() -> Vec3d{
-        let mut rng = rand::thread_rng();
-        return Vec3d::new(rng.gen(), rng.gen(), rng.gen());
-    }
-
-    pub(crate) fn random_from_range(range: Range<f64>) -> Vec3d{
-        let mut rng = rand::thread_rng();
-        return Vec3d::new(rng.gen_range(range.clone()), rng.gen_range(range.clone()), rng.gen_range(range));
-    }
-
-    pub(crate) fn random_in_unit_sphere() -> Vec3d{
-        loop{
-            let p = Vec3d::random_from_range(-1.0..1.0);
-            if p.length_squared() < 1.0{
-                return p;
-            }
-        }
-    }
-
-    pub(crate) fn random_unit_vector() -> Vec3d{
-        return Self::random_in_unit_sphere().unit();
-    }
-
-    pub(crate) fn random_on_hemisphere(normal: &Vec3d) -> Vec3d{
-        let on_unit_sphere = Self::random_in_unit_sphere();
-        //Todo replace with vvvv ?
-        //return on_unit_sphere * f64::signum(on_unit_sphere.dot(normal));
-        return if(on_unit_sphere.dot(normal)>0.){
-            on_unit_sphere
-        }else{
-            -on_unit_sphere
-        }
-    }
-
 }
 
 impl Add for Vec3d{
@@ -236,6 +239,18 @@ impl<'a> Mul<&'a Vec3d> for f64{
 
 }
 
+impl<'a> Mul<f64> for &'a Vec3d {
+    type Output = Vec3d;
+
+    fn mul(self, rhs: f64) -> Vec3d {
+        return Vec3d {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
 impl Div<f64> for Vec3d{
     type Output = Self;
 
@@ -280,6 +295,20 @@ impl<'a> Neg for &'a Vec3d{
     }
 }
 
+//access by axis index, 0 = x, 1 = y, 2 = z
+impl Index<usize> for Vec3d {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3d axis index out of range: {}", axis),
+        }
+    }
+}
+
 impl Sum for Vec3d {
     fn sum<I: Iterator<Item=Self>>(iter: I) -> Self {
         iter.fold(Vec3d{x:0.,y:0.,z:0.}, Add::add)
@@ -296,6 +325,16 @@ impl<'a> Sum<&'a Vec3d> for Vec3d {
 mod tests{
     use super::*;
 
+    #[test]
+    fn test_orthonormal_basis(){
+        for n in [Vec3d::up(), Vec3d::forward(), Vec3d::backward(), Vec3d::new(1., 2., -3.).unit()] {
+            let (t, b) = n.orthonormal_basis();
+            assert!(t.dot(&n).abs() < 1e-12 && b.dot(&n).abs() < 1e-12 && t.dot(&b).abs() < 1e-12);
+            assert!((t.length() - 1.).abs() < 1e-12 && (b.length() - 1.).abs() < 1e-12);
+            assert!((t.cross(b) - n).length() < 1e-12);
+        }
+    }
+
     #[test]
     fn test_squared_length(){
         assert_eq!((4 + 9 + 16) as f64, Vec3d {x:2., y:3., z:4.}.length_squared());
@@ -341,6 +380,20 @@ mod tests{
         assert_eq!(Vec3d {x:-8., y:-8., z:12.}, Vec3d::new(3.,0.,2.).cross(Vec3d::new(-1.,4.,2.)))
     }
 
+    #[test]
+    fn test_min_max(){
+        let a = Vec3d::new(1.,5.,-2.);
+        let b = Vec3d::new(3.,-1.,-2.);
+        assert_eq!(Vec3d::new(1.,-1.,-2.), a.min(&b));
+        assert_eq!(Vec3d::new(3.,5.,-2.), a.max(&b));
+    }
+
+    #[test]
+    fn test_index(){
+        let v = Vec3d::new(1.,2.,3.);
+        assert_eq!((1.,2.,3.), (v[0], v[1], v[2]));
+    }
+
     #[test]
     fn test_unit(){
         assert_eq!(Vec3d {x:0., y:0., z:1.}, Vec3d::new(0.,0.,9.).unit())
@@ -0,0 +1,132 @@
+use std::ops::Range;
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+//axis-aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vec3d,
+    pub(crate) max: Vec3d,
+}
+
+impl Aabb {
+    pub(crate) fn new(a: Vec3d, b: Vec3d) -> Self {
+        Self{
+            min: a.min(&b),
+            max: a.max(&b),
+        }
+    }
+
+    //box that contains nothing, neutral element for surrounding
+    pub(crate) fn empty() -> Self {
+        Self{
+            min: Vec3d::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vec3d::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    //box around unbounded shapes like infinite planes
+    pub(crate) fn infinite() -> Self {
+        Self{
+            min: Vec3d::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            max: Vec3d::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        }
+    }
+
+    pub(crate) fn surrounding(&self, other: &Aabb) -> Aabb {
+        Self{
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    pub(crate) fn expand(&self, point: &Vec3d) -> Aabb {
+        Self{
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z;
+    }
+
+    pub(crate) fn is_finite(&self) -> bool {
+        return [self.min, self.max].iter().all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite());
+    }
+
+    pub(crate) fn centroid(&self) -> Vec3d {
+        return 0.5 * (self.min + self.max);
+    }
+
+    pub(crate) fn extent(&self) -> Vec3d {
+        return self.max - self.min;
+    }
+
+    pub(crate) fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let d = self.extent();
+        return 2. * (d.x * d.y + d.y * d.z + d.z * d.x);
+    }
+
+    //slab test, returns the entry distance if the box is hit inside the interval
+    pub(crate) fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<f64> {
+        let mut t_min = interval.start;
+        let mut t_max = interval.end;
+        for axis in 0..3 {
+            let inv_d = 1. / ray.direction_no_unit[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            //written so that NaN (0 * inf) keeps the current bound
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return None;
+            }
+        }
+        return Some(t_min);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surrounding(){
+        let a = Aabb::new(Vec3d::new(0.,0.,0.), Vec3d::new(1.,1.,1.));
+        let b = Aabb::new(Vec3d::new(-1.,0.5,0.), Vec3d::new(0.,2.,0.5));
+        let s = a.surrounding(&b);
+        assert_eq!(Vec3d::new(-1.,0.,0.), s.min);
+        assert_eq!(Vec3d::new(1.,2.,1.), s.max);
+        assert_eq!(s, Aabb::empty().surrounding(&s));
+    }
+
+    #[test]
+    fn test_surface_area(){
+        let a = Aabb::new(Vec3d::new(0.,0.,0.), Vec3d::new(1.,2.,3.));
+        assert_eq!(22., a.surface_area());
+        assert_eq!(0., Aabb::empty().surface_area());
+    }
+
+    #[test]
+    fn test_hit(){
+        let a = Aabb::new(Vec3d::new(-1.,-1.,-3.), Vec3d::new(1.,1.,-2.));
+        let towards = Ray::new(Vec3d::zero(), Vec3d::forward());
+        let away = Ray::new(Vec3d::zero(), Vec3d::backward());
+        let parallel = Ray::new(Vec3d::new(0.,2.,0.), Vec3d::forward());
+        assert_eq!(Some(2.), a.hit(&towards, 0.001..f64::INFINITY));
+        assert_eq!(None, a.hit(&towards, 0.001..1.5));
+        assert_eq!(None, a.hit(&away, 0.001..f64::INFINITY));
+        assert_eq!(None, a.hit(&parallel, 0.001..f64::INFINITY));
+    }
+}
@@ -0,0 +1,429 @@
+use std::f64::consts::PI;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+use crate::hit::HitRecord;
+use crate::texture::{Constant, Texture};
+use crate::vec3d::Vec3d;
+
+//scattering functions, directions are unit vectors pointing away from the surface:
+//wo towards the previous path vertex (the viewer), wi towards the next one (the light)
+
+pub(crate) struct BsdfSample {
+    pub(crate) direction: Vec3d,
+    //bsdf * |cos| / pdf
+    pub(crate) weight: Vec3d,
+    //solid angle density, only meaningful if not specular
+    pub(crate) pdf: f64,
+    //sampled from a dirac delta, cannot be found by light sampling
+    pub(crate) specular: bool,
+}
+
+pub(crate) trait Bsdf {
+    //bsdf * |cos| of wi with the shading normal, 0 for delta lobes
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d;
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample>;
+
+    //solid angle density of sample returning wi, 0 for delta lobes
+    fn pdf(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> f64;
+
+    //true if every lobe is a delta distribution, light sampling is skipped for those
+    fn is_delta(&self) -> bool {
+        return false;
+    }
+}
+
+pub(crate) struct Lambertian {
+    albedo: Arc<dyn Texture + Send + Sync>,
+    scale: f64,
+}
+
+impl Lambertian {
+    pub(crate) fn new(albedo: Vec3d) -> Self {
+        return Self::textured(Arc::new(Constant::new(albedo)), 1.);
+    }
+
+    //albedo looked up at the hit, multiplied by scale
+    pub(crate) fn textured(albedo: Arc<dyn Texture + Send + Sync>, scale: f64) -> Self {
+        Self{
+            albedo,
+            scale,
+        }
+    }
+
+    fn albedo(&self, hit: &HitRecord) -> Vec3d {
+        return self.albedo.value(hit.uv, hit.pos) * self.scale;
+    }
+}
+
+impl Bsdf for Lambertian {
+    fn eval(&self, hit: &HitRecord, _wo: Vec3d, wi: Vec3d) -> Vec3d {
+        let cos = wi.dot(&hit.normal);
+        if cos <= 0. {
+            return Vec3d::zero();
+        }
+        return self.albedo(hit) * (cos / PI);
+    }
+
+    fn sample(&self, hit: &HitRecord, _wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let direction = cosine_hemisphere(hit.normal, rng);
+        let cos = direction.dot(&hit.normal);
+        if cos <= 0. {
+            return None;
+        }
+        return Some(BsdfSample{
+            direction,
+            weight: self.albedo(hit),
+            pdf: cos / PI,
+            specular: false,
+        });
+    }
+
+    fn pdf(&self, hit: &HitRecord, _wo: Vec3d, wi: Vec3d) -> f64 {
+        return wi.dot(&hit.normal).max(0.) / PI;
+    }
+}
+
+//perfect specular reflection
+pub(crate) struct Mirror {
+    fresnel: ConductorFresnel,
+    tint: Vec3d,
+}
+
+impl Mirror {
+    //smooth metal with angle dependent reflectance
+    pub(crate) fn conductor(fresnel: ConductorFresnel, tint: Vec3d) -> Self {
+        Self{
+            fresnel,
+            tint,
+        }
+    }
+}
+
+impl Bsdf for Mirror {
+    fn eval(&self, _hit: &HitRecord, _wo: Vec3d, _wi: Vec3d) -> Vec3d {
+        return Vec3d::zero();
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, _rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let reflectance = self.fresnel.eval(wo.dot(&hit.normal));
+        return Some(BsdfSample{
+            direction: (-wo).reflect(&hit.normal),
+            weight: self.tint.comp_vise(reflectance),
+            pdf: 1.,
+            specular: true,
+        });
+    }
+
+    fn pdf(&self, _hit: &HitRecord, _wo: Vec3d, _wi: Vec3d) -> f64 {
+        return 0.;
+    }
+
+    fn is_delta(&self) -> bool {
+        return true;
+    }
+}
+
+//reflectance of a metal surface per color channel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ConductorFresnel {
+    //schlick's approximation with the given reflectance at normal incidence
+    Schlick(Vec3d),
+    //exact fresnel equations for a complex index of refraction eta + i k
+    Complex{eta: Vec3d, k: Vec3d},
+}
+
+impl ConductorFresnel {
+    pub(crate) const PRESETS: [&'static str; 4] = ["gold", "silver", "copper", "aluminium"];
+
+    //measured indices sampled at roughly 650, 550 and 450 nm
+    pub(crate) fn preset(name: &str) -> Option<Self> {
+        let (eta, k) = match name {
+            "gold" => (Vec3d::new(0.143, 0.374, 1.442), Vec3d::new(3.983, 2.385, 1.603)),
+            "silver" => (Vec3d::new(0.155, 0.117, 0.138), Vec3d::new(4.828, 3.122, 2.147)),
+            "copper" => (Vec3d::new(0.200, 0.924, 1.102), Vec3d::new(3.912, 2.452, 2.142)),
+            "aluminium" => (Vec3d::new(1.657, 0.880, 0.521), Vec3d::new(9.224, 6.270, 4.837)),
+            _ => return None,
+        };
+        return Some(ConductorFresnel::Complex{eta, k});
+    }
+
+    pub(crate) fn eval(&self, cos: f64) -> Vec3d {
+        let cos = cos.clamp(0., 1.);
+        return match self {
+            ConductorFresnel::Schlick(f0) => *f0 + (Vec3d::new(1., 1., 1.) - *f0) * (1. - cos).powi(5),
+            ConductorFresnel::Complex{eta, k} => Vec3d::new(
+                fresnel_conductor(cos, eta.x, k.x),
+                fresnel_conductor(cos, eta.y, k.y),
+                fresnel_conductor(cos, eta.z, k.z),
+            ),
+        };
+    }
+}
+
+//unpolarized reflectance of a conductor with index eta + i k for one channel
+fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos * cos;
+    let sin2 = 1. - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f64::sqrt(t0 * t0 + 4. * eta2 * k2);
+    let t1 = a2_plus_b2 + cos2;
+    let a = f64::sqrt((0.5 * (a2_plus_b2 + t0)).max(0.));
+    let t2 = 2. * cos * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    return 0.5 * (rp + rs);
+}
+
+//smooth glass, reflection or refraction chosen by the fresnel term
+pub(crate) struct Dielectric {
+    refraction_index: f64,
+    tint: Vec3d,
+}
+
+impl Dielectric {
+    pub(crate) fn new(refraction_index: f64, tint: Vec3d) -> Self {
+        Self{
+            refraction_index,
+            tint,
+        }
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn eval(&self, _hit: &HitRecord, _wo: Vec3d, _wi: Vec3d) -> Vec3d {
+        return Vec3d::zero();
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let refraction_ratio = if hit.front_face {1. / self.refraction_index} else {self.refraction_index};
+        let unit_direction = -wo;
+        let cos_theta = wo.dot(&hit.normal).min(1.);
+        let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
+        let cannot_refract = refraction_ratio * sin_theta > 1.;
+        let direction = if cannot_refract || schlick_reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>() {
+            unit_direction.reflect(&hit.normal)
+        } else {
+            unit_direction.refract(&hit.normal, refraction_ratio)
+        };
+        return Some(BsdfSample{
+            direction: direction.unit(),
+            weight: self.tint,
+            pdf: 1.,
+            specular: true,
+        });
+    }
+
+    fn pdf(&self, _hit: &HitRecord, _wo: Vec3d, _wi: Vec3d) -> f64 {
+        return 0.;
+    }
+
+    fn is_delta(&self) -> bool {
+        return true;
+    }
+}
+
+//weighted sum of lobes, the weights should add up to at most 1
+pub(crate) struct MixtureBsdf {
+    lobes: Vec<(f64, Box<dyn Bsdf + Send + Sync>)>,
+}
+
+impl MixtureBsdf {
+    pub(crate) fn new(lobes: Vec<(f64, Box<dyn Bsdf + Send + Sync>)>) -> Self {
+        Self{
+            lobes: lobes.into_iter().filter(|(weight, _)| *weight > 0.).collect(),
+        }
+    }
+}
+
+impl Bsdf for MixtureBsdf {
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d {
+        return self.lobes.iter().map(|(weight, lobe)| *weight * lobe.eval(hit, wo, wi)).sum();
+    }
+
+    //the lobe is picked with probability proportional to its weight
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let total = self.lobes.iter().map(|(weight, _)| weight).sum::<f64>();
+        if total <= 0. {
+            return None;
+        }
+        let mut chance = rng.gen::<f64>() * total;
+        let index = self.lobes.iter().position(|(weight, _)| {
+            chance -= weight;
+            chance < 0.
+        }).unwrap_or(self.lobes.len() - 1);
+        let (weight, lobe) = &self.lobes[index];
+        let mut sample = lobe.sample(hit, wo, rng)?;
+        if sample.specular {
+            //f * cos / pdf of the lobe, scaled by weight / selection probability
+            sample.weight = sample.weight * total;
+            sample.pdf *= weight / total;
+            return Some(sample);
+        }
+        //the other smooth lobes could have produced the same direction
+        let pdf = self.pdf(hit, wo, sample.direction);
+        if pdf <= 0. {
+            return None;
+        }
+        sample.weight = self.eval(hit, wo, sample.direction) / pdf;
+        sample.pdf = pdf;
+        return Some(sample);
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> f64 {
+        let total = self.lobes.iter().map(|(weight, _)| weight).sum::<f64>();
+        if total <= 0. {
+            return 0.;
+        }
+        return self.lobes.iter().map(|(weight, lobe)| weight * lobe.pdf(hit, wo, wi)).sum::<f64>() / total;
+    }
+
+    fn is_delta(&self) -> bool {
+        return self.lobes.iter().all(|(_, lobe)| lobe.is_delta());
+    }
+}
+
+//cosine weighted direction in the hemisphere around the unit normal
+pub(crate) fn cosine_hemisphere(normal: Vec3d, rng: &mut dyn RngCore) -> Vec3d {
+    let r = f64::sqrt(rng.gen::<f64>());
+    let phi = 2. * PI * rng.gen::<f64>();
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let z = f64::sqrt((1. - r * r).max(0.));
+    return (r * phi.cos()) * tangent + (r * phi.sin()) * bitangent + z * normal;
+}
+
+//schlick's approximation of the fresnel reflectance
+pub(crate) fn schlick_reflectance(cosine: f64, refraction_index: f64) -> f64 {
+    let r0 = (1. - refraction_index) / (1. + refraction_index);
+    let r0_squared = r0 * r0;
+    return r0_squared + (1. - r0_squared) * (1. - cosine).powi(5);
+}
+
+//unpolarized fresnel reflectance of a dielectric interface, eta is the index behind the surface relative to the one in front
+pub(crate) fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0., 1.);
+    let sin2_t = (1. - cos * cos) / (eta * eta);
+    if sin2_t >= 1. {
+        //total internal reflection
+        return 1.;
+    }
+    let cos_t = f64::sqrt(1. - sin2_t);
+    let rs = (cos - eta * cos_t) / (cos + eta * cos_t);
+    let rp = (eta * cos - cos_t) / (eta * cos + cos_t);
+    return 0.5 * (rs * rs + rp * rp);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::material::Material;
+
+    fn hit_from_above() -> HitRecord {
+        let material = Arc::new(Material::builder().build());
+        return HitRecord::with_unit_normal(Vec3d::zero(), Vec3d::up(), 1., Vec3d::down(), material);
+    }
+
+    #[test]
+    fn test_lambertian_sample_matches_eval_and_pdf(){
+        let hit = hit_from_above();
+        let bsdf = Lambertian::new(Vec3d::new(0.5, 0.25, 1.));
+        let wo = Vec3d::new(1., 1., 0.).unit();
+        let mut rng = StdRng::from_seed([3; 32]);
+        for _ in 0..100 {
+            let sample = bsdf.sample(&hit, wo, &mut rng).unwrap();
+            let expected = bsdf.eval(&hit, wo, sample.direction) / bsdf.pdf(&hit, wo, sample.direction);
+            assert!((expected - sample.weight).length() < 1e-9);
+            assert!((sample.pdf - bsdf.pdf(&hit, wo, sample.direction)).abs() < 1e-12);
+        }
+        assert_eq!(Vec3d::zero(), bsdf.eval(&hit, wo, Vec3d::down()));
+    }
+
+    #[test]
+    fn test_lambertian_conserves_energy(){
+        //integral of eval over the hemisphere is the albedo, estimated with uniform sphere samples
+        let hit = hit_from_above();
+        let bsdf = Lambertian::new(Vec3d::new(1., 1., 1.));
+        let mut rng = StdRng::from_seed([5; 32]);
+        let samples = 100000;
+        let sum = (0..samples).map(|_| {
+            let z = 2. * rng.gen::<f64>() - 1.;
+            let phi = 2. * PI * rng.gen::<f64>();
+            let r = f64::sqrt(1. - z * z);
+            let wi = Vec3d::new(r * phi.cos(), z, r * phi.sin());
+            bsdf.eval(&hit, Vec3d::up(), wi).x * 4. * PI
+        }).sum::<f64>();
+        assert!((sum / samples as f64 - 1.).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_mirror_and_dielectric(){
+        let hit = hit_from_above();
+        let wo = Vec3d::new(1., 1., 0.).unit();
+        let mut rng = StdRng::from_seed([3; 32]);
+        let sample = Mirror::conductor(ConductorFresnel::Schlick(Vec3d::new(1., 1., 1.)), Vec3d::new(1., 1., 1.)).sample(&hit, wo, &mut rng).unwrap();
+        assert!((sample.direction - Vec3d::new(-1., 1., 0.).unit()).length() < 1e-12);
+        assert!(sample.specular);
+
+        //glass with index 1 never reflects and does not bend rays
+        let sample = Dielectric::new(1., Vec3d::new(1., 1., 1.)).sample(&hit, wo, &mut rng).unwrap();
+        assert!((sample.direction + wo).length() < 1e-9);
+        assert!(Dielectric::new(1.5, Vec3d::new(1., 1., 1.)).is_delta());
+    }
+
+    #[test]
+    fn test_fresnel(){
+        //reflectance at normal incidence of glass is ((n - 1) / (n + 1))^2
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert_eq!(1., fresnel_dielectric(0.2, 1. / 1.5));
+        assert!((fresnel_dielectric(0., 1.5) - 1.).abs() < 1e-12);
+        //a conductor without absorption behaves like a dielectric
+        let conductor = ConductorFresnel::Complex{eta: Vec3d::new(1.5, 1.5, 1.5), k: Vec3d::zero()};
+        for cos in [1., 0.7, 0.3] {
+            assert!((conductor.eval(cos).x - fresnel_dielectric(cos, 1.5)).abs() < 1e-9);
+        }
+        let gold = ConductorFresnel::preset("gold").unwrap().eval(1.);
+        assert!(gold.x > gold.z);
+        assert!(ConductorFresnel::preset("wood").is_none());
+        let schlick = ConductorFresnel::Schlick(Vec3d::new(0.5, 0.5, 0.5));
+        assert_eq!(0.5, schlick.eval(1.).x);
+        assert_eq!(1., schlick.eval(0.).x);
+    }
+
+    #[test]
+    fn test_mixture(){
+        let hit = hit_from_above();
+        let mixture = MixtureBsdf::new(vec![
+            (0.25, Box::new(Mirror::conductor(ConductorFresnel::Schlick(Vec3d::new(1., 1., 1.)), Vec3d::new(1., 1., 1.))) as Box<dyn Bsdf + Send + Sync>),
+            (0.75, Box::new(Lambertian::new(Vec3d::new(0.8, 0.8, 0.8)))),
+        ]);
+        assert!(!mixture.is_delta());
+        let wo = Vec3d::up();
+        let wi = Vec3d::new(0., 1., 1.).unit();
+        assert!((mixture.pdf(&hit, wo, wi) - 0.75 * wi.y / PI).abs() < 1e-12);
+        let mut rng = StdRng::from_seed([9; 32]);
+        for _ in 0..100 {
+            let sample = mixture.sample(&hit, wo, &mut rng).unwrap();
+            //mirror: weight / selection probability, diffuse: 0.75 * albedo / 0.75
+            let expected = if sample.specular { 1. } else { 0.8 };
+            assert!((sample.weight.x - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_cosine_hemisphere(){
+        let mut rng = StdRng::from_seed([1; 32]);
+        let normal = Vec3d::new(1., 2., 3.).unit();
+        let mean_cos = (0..10000).map(|_| {
+            let d = cosine_hemisphere(normal, &mut rng);
+            assert!((d.length() - 1.).abs() < 1e-9);
+            d.dot(&normal)
+        }).sum::<f64>() / 10000.;
+        //the mean cosine of a cosine weighted hemisphere is 2/3
+        assert!((mean_cos - 2. / 3.).abs() < 0.01);
+    }
+}
@@ -0,0 +1,273 @@
+use std::ops::Range;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+//cost of visiting a node relative to intersecting one object
+const TRAVERSAL_COST: f64 = 1.;
+//traversal uses a fixed size stack, deeper subtrees are turned into leaves
+const MAX_DEPTH: usize = 60;
+
+//bounding volume hierarchy over a list of hittables, nodes are stored flattened in depth-first order
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    //objects without a finite bounding box, like infinite planes, are tested on every ray
+    unbounded: Vec<Box<dyn Hittable + Send + Sync>>,
+}
+
+struct BvhNode {
+    bbox: Aabb,
+    //leaf: index of the first object, interior: index of the second child (the first child follows directly)
+    offset: usize,
+    //number of objects in a leaf, 0 for interior nodes
+    count: usize,
+    //split axis of interior nodes, used to visit the nearer child first
+    axis: usize,
+}
+
+struct PrimitiveInfo {
+    index: usize,
+    bbox: Aabb,
+    centroid: Vec3d,
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    bbox: Aabb,
+    count: usize,
+}
+
+impl Bvh {
+    pub(crate) fn new(objects: Vec<Box<dyn Hittable + Send + Sync>>) -> Self {
+        let (objects, unbounded): (Vec<_>, Vec<_>) = objects.into_iter().partition(|object| object.bounding_box().is_finite());
+        let mut prims: Vec<PrimitiveInfo> = objects.iter().enumerate().map(|(index, object)| {
+            let bbox = object.bounding_box();
+            PrimitiveInfo{
+                index,
+                bbox,
+                centroid: bbox.centroid(),
+            }
+        }).collect();
+
+        let mut nodes = Vec::with_capacity(2 * prims.len());
+        if !prims.is_empty() {
+            Self::build_recursive(&mut nodes, &mut prims, 0, 0);
+        }
+
+        //reorder objects so that every leaf references a contiguous range
+        let mut slots: Vec<Option<Box<dyn Hittable + Send + Sync>>> = objects.into_iter().map(Some).collect();
+        let objects = prims.iter().map(|prim| slots[prim.index].take().unwrap()).collect();
+
+        Self{
+            nodes,
+            objects,
+            unbounded,
+        }
+    }
+
+    fn build_recursive(nodes: &mut Vec<BvhNode>, prims: &mut [PrimitiveInfo], offset: usize, depth: usize) -> usize {
+        let bbox = prims.iter().fold(Aabb::empty(), |acc, prim| acc.surrounding(&prim.bbox));
+        let node_index = nodes.len();
+        nodes.push(BvhNode{
+            bbox,
+            offset,
+            count: prims.len(),
+            axis: 0,
+        });
+
+        if prims.len() == 1 || depth >= MAX_DEPTH {
+            return node_index;
+        }
+
+        let centroid_bounds = prims.iter().fold(Aabb::empty(), |acc, prim| acc.expand(&prim.centroid));
+        let split = match Self::find_sah_split(prims, &bbox, &centroid_bounds) {
+            Some(split) => split,
+            None => return node_index,
+        };
+        let (axis, split_bin, cost) = split;
+        let leaf_cost = prims.len() as f64;
+        if cost >= leaf_cost && prims.len() <= MAX_LEAF_SIZE {
+            return node_index;
+        }
+
+        //partition objects by the side of the split bin their centroid falls into
+        let mut mid = 0;
+        for i in 0..prims.len() {
+            if Self::bin_index(&prims[i].centroid, &centroid_bounds, axis) < split_bin {
+                prims.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        let (left, right) = prims.split_at_mut(mid);
+        Self::build_recursive(nodes, left, offset, depth + 1);
+        let right_index = Self::build_recursive(nodes, right, offset + mid, depth + 1);
+
+        let node = &mut nodes[node_index];
+        node.offset = right_index;
+        node.count = 0;
+        node.axis = axis;
+        return node_index;
+    }
+
+    //binned surface area heuristic, returns axis, first bin of the right side and the estimated cost
+    fn find_sah_split(prims: &[PrimitiveInfo], bbox: &Aabb, centroid_bounds: &Aabb) -> Option<(usize, usize, f64)> {
+        let parent_area = bbox.surface_area();
+        let mut best: Option<(usize, usize, f64)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.extent()[axis] <= 0. {
+                continue;
+            }
+            let mut bins = [Bin{ bbox: Aabb::empty(), count: 0 }; BIN_COUNT];
+            for prim in prims {
+                let b = Self::bin_index(&prim.centroid, centroid_bounds, axis);
+                bins[b].count += 1;
+                bins[b].bbox = bins[b].bbox.surrounding(&prim.bbox);
+            }
+
+            //sweep from the right to get the area and count of every right side
+            let mut right_area = [0.; BIN_COUNT];
+            let mut right_count = [0; BIN_COUNT];
+            let mut acc = Bin{ bbox: Aabb::empty(), count: 0 };
+            for b in (1..BIN_COUNT).rev() {
+                acc.bbox = acc.bbox.surrounding(&bins[b].bbox);
+                acc.count += bins[b].count;
+                right_area[b] = acc.bbox.surface_area();
+                right_count[b] = acc.count;
+            }
+
+            let mut left = Bin{ bbox: Aabb::empty(), count: 0 };
+            for split in 1..BIN_COUNT {
+                left.bbox = left.bbox.surrounding(&bins[split - 1].bbox);
+                left.count += bins[split - 1].count;
+                if left.count == 0 || right_count[split] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST + (left.bbox.surface_area() * left.count as f64 + right_area[split] * right_count[split] as f64) / parent_area;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+        return best;
+    }
+
+    fn bin_index(centroid: &Vec3d, centroid_bounds: &Aabb, axis: usize) -> usize {
+        let relative = (centroid[axis] - centroid_bounds.min[axis]) / centroid_bounds.extent()[axis];
+        return ((relative * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1);
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let mut closest_so_far = interval.end;
+        let mut temp_rec = None;
+        for hittable in &self.unbounded {
+            if let Some(rec) = hittable.hit(ray, interval.start..closest_so_far) {
+                closest_so_far = rec.t;
+                temp_rec = Some(rec);
+            }
+        }
+        if self.nodes.is_empty() {
+            return temp_rec;
+        }
+        let dir_is_neg = [ray.direction_no_unit.x < 0., ray.direction_no_unit.y < 0., ray.direction_no_unit.z < 0.];
+
+        let mut stack = [0usize; MAX_DEPTH + 2];
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+            if node.bbox.hit(ray, interval.start..closest_so_far).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for hittable in &self.objects[node.offset..node.offset + node.count] {
+                    if let Some(rec) = hittable.hit(ray, interval.start..closest_so_far) {
+                        closest_so_far = rec.t;
+                        temp_rec = Some(rec);
+                    }
+                }
+            } else {
+                //push the far child first so the near child is visited next
+                let first_child = stack[stack_size] + 1;
+                let (near, far) = if dir_is_neg[node.axis] { (node.offset, first_child) } else { (first_child, node.offset) };
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
+            }
+        }
+        return temp_rec;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bbox = self.nodes.first().map_or(Aabb::empty(), |node| node.bbox);
+        return self.unbounded.iter().fold(bbox, |acc, hittable| acc.surrounding(&hittable.bounding_box()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use rand::{Rng, SeedableRng};
+    use crate::material::Material;
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+    use super::*;
+
+    fn random_spheres(count: usize) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        let mut rng = rand::prelude::StdRng::from_seed([7; 32]);
+        let material = Arc::new(Material::builder().build());
+        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+        for _ in 0..count {
+            let center = Vec3d::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+            objects.push(Box::new(Sphere::new(center, rng.gen_range(0.1..1.0), material.clone())));
+        }
+        return objects;
+    }
+
+    #[test]
+    fn test_matches_linear_search(){
+        let list = random_spheres(300);
+        let bvh = Bvh::new(random_spheres(300));
+        assert_eq!((&list).bounding_box(), bvh.bounding_box());
+
+        let mut rng = rand::prelude::StdRng::from_seed([3; 32]);
+        for _ in 0..1000 {
+            let origin = Vec3d::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+            let direction = Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let ray = Ray::new(origin, direction);
+            let expected = (&list).hit(&ray, 0.001..f64::INFINITY).map(|rec| rec.t);
+            let actual = bvh.hit(&ray, 0.001..f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_unbounded_objects(){
+        let material = Arc::new(Material::builder().build());
+        let mut objects = random_spheres(50);
+        objects.push(Box::new(Plane::new(Vec3d::new(0., -20., 0.), Vec3d::up(), material)));
+        let bvh = Bvh::new(objects);
+        assert!(!bvh.bounding_box().is_finite());
+        //straight down past every sphere onto the plane, and nearer spheres still win over the plane
+        let rec = bvh.hit(&Ray::new(Vec3d::new(100., 0., 0.), Vec3d::down()), 0.001..f64::INFINITY).unwrap();
+        assert_eq!(20., rec.t);
+        let list = random_spheres(50);
+        let ray = Ray::new(Vec3d::new(0., 15., 0.), Vec3d::new(0.1, -1., 0.2));
+        let expected = (&list).hit(&ray, 0.001..f64::INFINITY).map(|rec| rec.t).unwrap_or(35.);
+        assert_eq!(expected, bvh.hit(&ray, 0.001..f64::INFINITY).unwrap().t);
+    }
+
+    #[test]
+    fn test_empty(){
+        let bvh = Bvh::new(Vec::new());
+        assert!(bvh.hit(&Ray::new(Vec3d::zero(), Vec3d::forward()), 0.001..f64::INFINITY).is_none());
+        assert!(bvh.bounding_box().is_empty());
+    }
+}
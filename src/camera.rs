@@ -1,25 +1,29 @@
 use indicatif::ParallelProgressIterator;
 use itertools::Itertools;
-use rand::{random, Rng, thread_rng};
-use crate::hit::Hittable;
-use crate::{Image, lerp_vec3d};
+use rand::{Rng, thread_rng};
+use crate::light::power_heuristic;
+use crate::{lerp, lerp_vec3d};
+use crate::image::Image;
 use crate::ray::Ray;
 use crate::vec3d::Vec3d;
+use crate::world::{World, SHADOW_EPSILON};
 use rayon::prelude::*;
 
 pub(crate) struct Camera {
     image_width: usize,
     image_height: usize,
-    focal_length: f64,
 
     camera_origin: Vec3d,
-    camera_direction: Vec3d,
-    viewport_pos: Vec3d,
-    aspect_ratio: f64,
-    viewport_height: f64,
-    viewport_width: f64,
-    viewport_u: Vec3d,
-    viewport_v: Vec3d,
+    //camera basis vectors spanning the lens
+    lens_u: Vec3d,
+    lens_v: Vec3d,
+    defocus_radius: f64,
+    //0 for a circular aperture, otherwise the number of polygon edges
+    aperture_blades: usize,
+    blade_rotation: f64,
+    //times the rays are spread over for motion blur
+    shutter_open: f64,
+    shutter_close: f64,
     pixel_delta_u: Vec3d,
     pixel_delta_v: Vec3d,
     pixel00_pos: Vec3d,
@@ -27,51 +31,37 @@ pub(crate) struct Camera {
     samples_per_pixel: usize,
     max_bounces: usize,
     sky_lerp: bool,
+    //camera rays that miss every object leave the pixel transparent instead of showing the background
+    transparent_background: bool,
 }
 
 impl Camera {
-    pub(crate) fn new(image_width: usize, image_height: usize, focal_length: f64, samples_per_pixel: usize, max_bounces: usize, sky: bool) -> Self {
-
-        let camera_origin = Vec3d::zero();
-        let camera_direction = Vec3d::forward();
-        let viewport_pos = camera_origin + camera_direction * focal_length;
-
-        let aspect_ratio = image_width as f64 / image_height as f64;
-        let viewport_height = 2f64;
-        let viewport_width = viewport_height * aspect_ratio;
-        let viewport_u = Vec3d::right() * viewport_width;
-        let viewport_v = Vec3d::down() * viewport_height;
-        let pixel_delta_u = viewport_u / image_width as f64;
-        let pixel_delta_v = viewport_v / image_height as f64;
-
-        let viewport_upper_left = viewport_pos - viewport_u/2. - viewport_v/2.;
-        let pixel00_pos = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
-
-        Self{
-            image_width,
-            image_height,
-            focal_length,
-            camera_origin,
-            camera_direction,
-            aspect_ratio,
-            viewport_height,
-            viewport_width,
-            viewport_u,
-            viewport_v,
-            pixel_delta_u,
-            pixel_delta_v,
-            viewport_pos,
-            pixel00_pos,
-            samples_per_pixel,
-            max_bounces,
-            sky_lerp: sky,
+    pub(crate) fn builder() -> CameraBuilder {
+        CameraBuilder{
+            image_width: 400,
+            image_height: 225,
+            samples_per_pixel: 100,
+            max_bounces: 7,
+            sky: false,
+            transparent_background: false,
+            look_from: Vec3d::zero(),
+            look_at: Vec3d::forward(),
+            vup: Vec3d::up(),
+            vertical_fov: 90.,
+            aperture_radius: 0.,
+            f_stop: None,
+            focus_distance: None,
+            aperture_blades: 0,
+            blade_rotation: 0.,
+            shutter_open: 0.,
+            shutter_close: 0.,
         }
     }
 
-    pub(crate) fn render<T>(&self, world: T) -> Image where T: Hittable +Sync{
+    pub(crate) fn render(&self, world: &World) -> Image {
         let mut image: Image = Image::new_with_color(self.image_height, self.image_width, Vec3d::new(0.,1.,0.));
         println!("Rendering image with width {} and height {} ...",self.image_width, self.image_height);
-        let pixels: Vec<Vec3d> = (0..image.height)
+        let samples: Vec<(Vec3d, f64)> = (0..image.height)
             //.flat_map(|row| (0..image.width).map(move |col| (row, col)))
             .cartesian_product(0..image.width)
             .collect::<Vec<(usize, usize)>>()
@@ -81,106 +71,124 @@ impl Camera {
 
             //sampling inside a pixel
             let scale = (self.samples_per_pixel as f64).recip();
-            let multisample_color = (0..self.samples_per_pixel)
+            let (multisample_color, coverage) = (0..self.samples_per_pixel)
                 .into_iter().map(|_|{
                 let ray = &self.generate_rng_offset_ray(row, col);
-                return Self::ray_color(&self, ray, self.max_bounces+1, &world) * scale;
-            }).sum::<Vec3d>();
-                return multisample_color;
+                let (color, coverage) = Self::ray_color(&self, ray, world, &mut thread_rng());
+                return (color * scale, coverage * scale);
+            }).fold((Vec3d::zero(), 0.), |sum, sample| (sum.0 + sample.0, sum.1 + sample.1));
+                return (multisample_color, coverage);
 
-        }).collect::<Vec<Vec3d>>();
+        }).collect::<Vec<(Vec3d, f64)>>();
 
+        let (pixels, alpha): (Vec<Vec3d>, Vec<f64>) = samples.into_iter().unzip();
         image.set_pixels(pixels);
+        if self.transparent_background {
+            image.set_alpha(alpha);
+        }
 
         return image;
     }
 
-    pub(crate) fn ray_color<T>(&self, ray: &Ray, bounces_left: usize, hittable: &T) -> Vec3d where T:Hittable {
-        if bounces_left == 0{
-            return Vec3d::zero();
-        }
-        if let Some(hit_record) = hittable.hit(ray, (0.001)..f64::INFINITY){
-            //let diffuse_direction_random = Vec3d::random_on_hemisphere(&hit_record.normal);
-            let mut color: Vec3d = Vec3d::zero();
-
-            //trace diffuse and reflective rays
-            let attenuation = hit_record.material.albedo_color;
-
-            // if(hit_record.material.reflectivity > 0.){
-            //     let reflect_direction = ray.direction_no_unit.unit().reflect(hit_record.normal);
-            //     let ray_bounced = Ray::new(hit_record.pos, reflect_direction);
-            //     let bounced_color = Self::ray_color(&ray_bounced, bounces_left -1, hittable);
-            //     color = color + hit_record.material.reflectivity * bounced_color;
-            // }
-            //
-            // if(hit_record.material.reflectivity < 1.){
-            //     let diffuse_direction_lambertian= hit_record.normal + Vec3d::random_unit_vector().near_zero_alt(hit_record.normal);
-            //     let ray_bounced = Ray::new(hit_record.pos, diffuse_direction_lambertian);
-            //     let bounced_color = Self::ray_color(&ray_bounced, bounces_left -1, hittable);
-            //     color = color + (1. - hit_record.material.reflectivity) * 0.7 * attenuation.comp_vise(bounced_color);
-            // }
-
-            //decision which ray to trace
-            let sum = (hit_record.material.absorption + hit_record.material.reflectivity + hit_record.material.refractioness + hit_record.material.emission_intensity);
-            let chance = random::<f64>() * sum;
-            //reflective
-            if chance < hit_record.material.reflectivity{
-                let reflect_direction = ray.direction_no_unit.unit().reflect(&hit_record.normal);
-                let mut fuzz_vector = Vec3d::zero();
-                if(hit_record.material.reflection_fuzz > 0.) {
-                    fuzz_vector = hit_record.material.reflection_fuzz * Vec3d::random_in_unit_sphere();
-                }
-                let ray_bounced = Ray::new(hit_record.pos, reflect_direction + fuzz_vector);
-                let bounced_color = Self::ray_color(&self, &ray_bounced, bounces_left -1, hittable);
-                if(ray_bounced.direction_no_unit.dot(&hit_record.normal) > 0.){
-                    color = color + hit_record.material.reflectivity * bounced_color;
-                }
-                //color = color + hit_record.material.reflectivity * bounced_color;
+    //iterative path tracer, light hit by bsdf sampling and light sampled with next event estimation are combined with mis,
+    //also returns the coverage, 0 if the background is transparent and the camera ray misses every object
+    pub(crate) fn ray_color(&self, ray: &Ray, world: &World, rng: &mut dyn rand::RngCore) -> (Vec3d, f64) {
+        let mut ray = *ray;
+        let mut color = Vec3d::zero();
+        let mut throughput = Vec3d::new(1., 1., 1.);
+        //origin and solid angle pdf of the last non-specular bounce, None for camera rays and after specular bounces
+        let mut previous_bounce: Option<(Vec3d, f64)> = None;
+
+        for depth in 0..=self.max_bounces {
+            let hit = world.hit(&ray, (SHADOW_EPSILON)..f64::INFINITY);
+            if depth == 0 && hit.is_none() && self.transparent_background {
+                return (Vec3d::zero(), 0.);
             }
-            //refractive
-            else if (chance < hit_record.material.reflectivity + hit_record.material.refractioness){
-                let refraction_ratio = if hit_record.front_face {1. / hit_record.material.refraction_index} else {hit_record.material.refraction_index};
-                let unit_direction = ray.direction_no_unit.unit();
-                let cos_theta = (-unit_direction).dot(&hit_record.normal).min(1.);
-                let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
-                let cannot_refract = refraction_ratio * sin_theta > 1.;
-                let mut direction = Vec3d::zero();
-                if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random::<f64>(){
-                    direction = unit_direction.reflect(&hit_record.normal);
+            let unit_direction = ray.direction_no_unit.unit();
+
+            //scattering in the media on the way to the surface
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |rec| rec.t);
+            let medium_sample = world.media.sample(&ray, t_max, rng);
+            color = color + throughput.comp_vise(medium_sample.emitted);
+            throughput = throughput.comp_vise(medium_sample.weight);
+            if let Some((t, phase)) = medium_sample.scatter {
+                if depth == self.max_bounces {
+                    break;
                 }
-                else{
-                    direction = unit_direction.refract(&hit_record.normal, refraction_ratio);
+                let pos = ray.at(t);
+                if let Some(light) = world.lights.sample(pos, rng) {
+                    let transmittance = world.transmittance(pos, light.direction, light.distance, ray.time, rng);
+                    if transmittance != Vec3d::zero() {
+                        let phase_pdf = phase.eval(unit_direction, light.direction);
+                        let weight = if light.delta { 1. } else { power_heuristic(light.pdf, phase_pdf) };
+                        color = color + (weight * phase_pdf / light.pdf) * throughput.comp_vise(transmittance.comp_vise(light.radiance));
+                    }
                 }
-                let ray_bounced = Ray::new(hit_record.pos, direction);
-                let bounced_color = Self::ray_color(&self, &ray_bounced, bounces_left -1, hittable);
-                color = color + (1. -hit_record.material.absorption) * bounced_color;
-            }
-            //diffuse
-            else if(chance < hit_record.material.reflectivity + hit_record.material.refractioness + hit_record.material.absorption){
-                let diffuse_direction_lambertian= hit_record.normal + Vec3d::random_unit_vector().near_zero_alt(hit_record.normal);
-                let ray_bounced = Ray::new(hit_record.pos, diffuse_direction_lambertian);
-                let bounced_color = Self::ray_color(&self, &ray_bounced, bounces_left -1, hittable);
-                color = color + (1.-hit_record.material.absorption) * attenuation.comp_vise(bounced_color);
+                //the phase function is sampled exactly, its value and pdf cancel
+                let (direction, pdf) = phase.sample(unit_direction, rng);
+                ray = Ray::new(pos, direction).with_time(ray.time);
+                previous_bounce = Some((pos, pdf));
+                if throughput == Vec3d::zero() {
+                    break;
+                }
+                continue;
             }
-            else if(chance < hit_record.material.reflectivity + hit_record.material.refractioness + hit_record.material.absorption + hit_record.material.emission_intensity){
-                color = color + hit_record.material.emission_color * hit_record.material.emission_intensity;
+
+            let hit_record = match hit {
+                Some(hit_record) => hit_record,
+                None => {
+                    //lights at infinity replace the sky gradient
+                    let background = match world.lights.background(unit_direction, previous_bounce.map(|(_, pdf)| pdf)) {
+                        Some(radiance) => radiance,
+                        None => Self::background_color(&self, &ray),
+                    };
+                    color = color + throughput.comp_vise(background);
+                    break;
+                }
+            };
+            let material = &hit_record.material;
+
+            let emitted = material.emitted();
+            if emitted != Vec3d::zero() {
+                let weight = match previous_bounce {
+                    Some((origin, pdf)) => {
+                        let distance = (hit_record.pos - origin).length();
+                        power_heuristic(pdf, world.lights.pdf(origin, unit_direction, distance))
+                    }
+                    None => 1.,
+                };
+                color = color + weight * throughput.comp_vise(emitted);
             }
-            else {
-                println!("Error: no ray was traced");
+            if depth == self.max_bounces {
+                break;
             }
 
+            let bsdf = material.bsdf();
+            let wo = -unit_direction;
+            //next event estimation
+            if !bsdf.is_delta() {
+                if let Some(light) = world.lights.sample(hit_record.pos, rng) {
+                    let f = bsdf.eval(&hit_record, wo, light.direction);
+                    let transmittance = if f == Vec3d::zero() { Vec3d::zero() } else { world.transmittance(hit_record.pos, light.direction, light.distance, ray.time, rng) };
+                    if transmittance != Vec3d::zero() {
+                        let weight = if light.delta { 1. } else { power_heuristic(light.pdf, bsdf.pdf(&hit_record, wo, light.direction)) };
+                        color = color + (weight / light.pdf) * throughput.comp_vise(f.comp_vise(transmittance.comp_vise(light.radiance)));
+                    }
+                }
+            }
 
-            return color;
+            let sample = match bsdf.sample(&hit_record, wo, rng) {
+                Some(sample) => sample,
+                None => break,
+            };
+            ray = Ray::new(hit_record.pos, sample.direction).with_time(ray.time);
+            throughput = throughput.comp_vise(sample.weight);
+            previous_bounce = if sample.specular { None } else { Some((hit_record.pos, sample.pdf)) };
+            if throughput == Vec3d::zero() {
+                break;
+            }
         }
-
-        return Self::background_color(&self, ray);
-
-    }
-
-    pub(crate) fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
-        let r0 = ((1. - refraction_index) / (1. + refraction_index));
-        let r0_squared = r0 * r0;
-        return r0_squared + (1. - r0_squared) * (1. - cosine).powi(5);
+        return (color, 1.);
     }
 
     fn generate_rng_offset_ray(&self, row: usize, col: usize) -> Ray {
@@ -191,10 +199,41 @@ impl Camera {
         //let py: f64 = 0.;
 
         let pixel_center = self.pixel00_pos + ((col as f64 + px) * (self.pixel_delta_u)) + ((row as f64 + py) * self.pixel_delta_v);
-        let ray_direction_no_unit = pixel_center - self.camera_origin;
-        let ray = Ray::new(self.camera_origin, ray_direction_no_unit);
+        //the viewport lies in the focus plane, points on it stay sharp for every lens position
+        let ray_origin = if self.defocus_radius > 0. {
+            let (lens_x, lens_y) = self.sample_aperture(&mut rng);
+            self.camera_origin + self.defocus_radius * (lens_x * self.lens_u + lens_y * self.lens_v)
+        } else {
+            self.camera_origin
+        };
+        let ray_direction_no_unit = pixel_center - ray_origin;
+        let time = lerp(self.shutter_open, self.shutter_close, rng.gen::<f64>());
+        let ray = Ray::new(ray_origin, ray_direction_no_unit).with_time(time);
         return ray;
     }
+
+    //uniform point on the unit aperture, a disk or a regular polygon with one vertex per blade
+    fn sample_aperture<R: Rng>(&self, rng: &mut R) -> (f64, f64) {
+        if self.aperture_blades < 3 {
+            loop {
+                let (x, y) = (rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                if x * x + y * y < 1. {
+                    return (x, y);
+                }
+            }
+        }
+        //all sectors have the same area, pick one and sample the triangle center, vertex, next vertex
+        let sector_angle = std::f64::consts::TAU / self.aperture_blades as f64;
+        let sector = rng.gen_range(0..self.aperture_blades) as f64;
+        let angle0 = self.blade_rotation + sector * sector_angle;
+        let angle1 = angle0 + sector_angle;
+        let (mut a, mut b) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if a + b > 1. {
+            a = 1. - a;
+            b = 1. - b;
+        }
+        return (a * angle0.cos() + b * angle1.cos(), a * angle0.sin() + b * angle1.sin());
+    }
     fn background_color(&self, ray: &Ray) -> Vec3d {
         if self.sky_lerp{
             let t = 0.5*(ray.direction_unit().y + 1.0);
@@ -204,4 +243,357 @@ impl Camera {
             return Vec3d::zero();
         }
     }
-}
\ No newline at end of file
+}
+
+pub(crate) struct CameraBuilder {
+    image_width: usize,
+    image_height: usize,
+    samples_per_pixel: usize,
+    max_bounces: usize,
+    sky: bool,
+    transparent_background: bool,
+    look_from: Vec3d,
+    look_at: Vec3d,
+    vup: Vec3d,
+    vertical_fov: f64,
+    aperture_radius: f64,
+    f_stop: Option<f64>,
+    focus_distance: Option<f64>,
+    aperture_blades: usize,
+    blade_rotation: f64,
+    shutter_open: f64,
+    shutter_close: f64,
+}
+
+//height of a full frame sensor in mm, used to convert f-stops to an aperture size
+const SENSOR_HEIGHT_MM: f64 = 24.;
+
+impl CameraBuilder {
+    pub(crate) fn resolution(mut self, image_width: usize, image_height: usize) -> CameraBuilder {
+        self.image_width = image_width;
+        self.image_height = image_height;
+        self
+    }
+
+    pub(crate) fn samples(mut self, samples_per_pixel: usize, max_bounces: usize) -> CameraBuilder {
+        self.samples_per_pixel = samples_per_pixel;
+        self.max_bounces = max_bounces;
+        self
+    }
+
+    pub(crate) fn sky(mut self, sky: bool) -> CameraBuilder {
+        self.sky = sky;
+        self
+    }
+
+    //renders an alpha channel with the coverage of each pixel, colors are premultiplied by it
+    pub(crate) fn transparent_background(mut self, transparent_background: bool) -> CameraBuilder {
+        self.transparent_background = transparent_background;
+        self
+    }
+
+    pub(crate) fn look_from(mut self, look_from: Vec3d) -> CameraBuilder {
+        self.look_from = look_from;
+        self
+    }
+
+    pub(crate) fn look_at(mut self, look_at: Vec3d) -> CameraBuilder {
+        self.look_at = look_at;
+        self
+    }
+
+    pub(crate) fn vup(mut self, vup: Vec3d) -> CameraBuilder {
+        self.vup = vup;
+        self
+    }
+
+    //in degrees
+    pub(crate) fn vertical_fov(mut self, vertical_fov: f64) -> CameraBuilder {
+        self.vertical_fov = vertical_fov;
+        self
+    }
+
+    //lens radius in scene units, 0 is a pinhole camera without defocus blur
+    pub(crate) fn aperture_radius(mut self, aperture_radius: f64) -> CameraBuilder {
+        self.aperture_radius = aperture_radius;
+        self.f_stop = None;
+        self
+    }
+
+    //aperture from the f-number of a full frame camera with the same field of view, scene units are meters
+    pub(crate) fn f_stop(mut self, f_stop: f64) -> CameraBuilder {
+        self.f_stop = Some(f_stop);
+        self
+    }
+
+    //distance of the plane in perfect focus, defaults to the distance between look_from and look_at
+    pub(crate) fn focus_distance(mut self, focus_distance: f64) -> CameraBuilder {
+        self.focus_distance = Some(focus_distance);
+        self
+    }
+
+    //polygonal aperture for bokeh, fewer than 3 blades give a circular aperture, rotation in degrees
+    pub(crate) fn aperture_blades(mut self, aperture_blades: usize, blade_rotation: f64) -> CameraBuilder {
+        self.aperture_blades = aperture_blades;
+        self.blade_rotation = blade_rotation;
+        self
+    }
+
+    //moving objects are at their start at time 0 and their end at time 1, equal times give a still image
+    pub(crate) fn shutter(mut self, open: f64, close: f64) -> CameraBuilder {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub(crate) fn build(self) -> Camera {
+        assert!(self.image_width > 0 && self.image_height > 0);
+        assert!(self.vertical_fov > 0. && self.vertical_fov < 180.);
+        let view_direction = self.look_at - self.look_from;
+        assert!(view_direction.length_squared() > 0., "look_from and look_at must differ");
+        assert!(self.vup.cross(view_direction).length_squared() > 0., "vup must not be parallel to the view direction");
+
+        //orthonormal basis, w points opposite to the view direction
+        let camera_origin = self.look_from;
+        let w = -view_direction.unit();
+        let u = self.vup.cross(w).unit();
+        let v = w.cross(u);
+
+        let focus_distance = self.focus_distance.unwrap_or(view_direction.length());
+        assert!(focus_distance > 0.);
+        let half_height = f64::tan(self.vertical_fov.to_radians() / 2.);
+        let defocus_radius = match self.f_stop {
+            Some(f_stop) => {
+                let focal_length_mm = SENSOR_HEIGHT_MM / 2. / half_height;
+                focal_length_mm / f_stop / 2. / 1000.
+            }
+            None => self.aperture_radius,
+        };
+        assert!(defocus_radius >= 0.);
+        assert!(self.shutter_open <= self.shutter_close);
+
+        //viewport in the focus plane
+        let aspect_ratio = self.image_width as f64 / self.image_height as f64;
+        let viewport_height = 2. * half_height * focus_distance;
+        let viewport_width = viewport_height * aspect_ratio;
+        let viewport_u = u * viewport_width;
+        let viewport_v = -v * viewport_height;
+        let pixel_delta_u = viewport_u / self.image_width as f64;
+        let pixel_delta_v = viewport_v / self.image_height as f64;
+
+        let viewport_upper_left = camera_origin - focus_distance * w - viewport_u/2. - viewport_v/2.;
+        let pixel00_pos = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
+
+        Camera{
+            image_width: self.image_width,
+            image_height: self.image_height,
+            camera_origin,
+            lens_u: u,
+            lens_v: v,
+            defocus_radius,
+            aperture_blades: self.aperture_blades,
+            blade_rotation: self.blade_rotation.to_radians(),
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+            pixel_delta_u,
+            pixel_delta_v,
+            pixel00_pos,
+            samples_per_pixel: self.samples_per_pixel,
+            max_bounces: self.max_bounces,
+            sky_lerp: self.sky,
+            transparent_background: self.transparent_background,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::sync::Arc;
+    use crate::bvh::Bvh;
+    use crate::environment::EnvironmentLight;
+    use crate::light::{LightList, PointLight};
+    use crate::material::Material;
+    use crate::medium::{HomogeneousMedium, MediumRegion};
+    use crate::sphere::Sphere;
+    use crate::triangle::Triangle;
+
+    fn assert_near(expected: Vec3d, actual: Vec3d) {
+        assert!((expected - actual).length() < 1e-12, "expected {:?}, found {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_look_at_basis(){
+        let camera = Camera::builder()
+            .resolution(4, 2)
+            .look_from(Vec3d::new(1., 2., 3.))
+            .look_at(Vec3d::new(1., 2., -7.))
+            .vertical_fov(90.)
+            .focus_distance(1.)
+            .build();
+        //the viewport center is straight ahead at distance 1
+        let center = camera.pixel00_pos + 1.5 * camera.pixel_delta_u + 0.5 * camera.pixel_delta_v;
+        assert_near(Vec3d::new(1., 2., 2.), center);
+        //90 degrees vertical fov gives a viewport height of 2, u points right and v down
+        assert_near(Vec3d::new(1., 0., 0.), camera.pixel_delta_u);
+        assert_near(Vec3d::new(0., -1., 0.), camera.pixel_delta_v);
+
+        let looking_right = Camera::builder().resolution(4, 2).look_at(Vec3d::right()).build();
+        assert_near(Vec3d::new(0., 0., 1.), looking_right.pixel_delta_u);
+    }
+
+    #[test]
+    fn test_aperture_samples_inside_polygon(){
+        let camera = Camera::builder().aperture_radius(0.1).aperture_blades(6, 0.).build();
+        let mut rng = thread_rng();
+        //inradius of a regular hexagon with circumradius 1
+        let inradius = f64::cos(std::f64::consts::PI / 6.);
+        for _ in 0..1000 {
+            let (x, y) = camera.sample_aperture(&mut rng);
+            //distance to the edge between the vertices at 0 and 60 degrees, rotated into that sector
+            let angle = y.atan2(x).rem_euclid(std::f64::consts::PI / 3.) - std::f64::consts::PI / 6.;
+            let r = (x * x + y * y).sqrt();
+            assert!(r * angle.cos() <= inradius + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_aperture_disk_uses_the_given_rng(){
+        let camera = Camera::builder().aperture_radius(0.1).build();
+        let samples = |seed| {
+            let mut rng = StdRng::from_seed([seed; 32]);
+            (0..100).map(|_| camera.sample_aperture(&mut rng)).collect::<Vec<(f64, f64)>>()
+        };
+        assert_eq!(samples(4), samples(4));
+        assert!(samples(4).iter().all(|(x, y)| x * x + y * y < 1.));
+    }
+
+    #[test]
+    fn test_ray_times_fill_shutter(){
+        let still = Camera::builder().resolution(4, 2).build();
+        assert_eq!(0., still.generate_rng_offset_ray(0, 0).time);
+        let camera = Camera::builder().resolution(4, 2).shutter(0.2, 0.6).build();
+        let times: Vec<f64> = (0..1000).map(|_| camera.generate_rng_offset_ray(1, 3).time).collect();
+        assert!(times.iter().all(|time| (0.2..0.6).contains(time)));
+        let mean = times.iter().sum::<f64>() / times.len() as f64;
+        assert!((mean - 0.4).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_f_stop(){
+        //50mm lens at f/2 has a 25mm aperture diameter
+        let vertical_fov = 2. * f64::atan(12. / 50.).to_degrees();
+        let camera = Camera::builder().vertical_fov(vertical_fov).f_stop(2.).build();
+        assert!((camera.defocus_radius - 0.0125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_direct_light_converges(){
+        //diffuse floor lit by a sphere straight above, irradiance pi * radiance * sin^2 of the cone angle
+        let floor_material = Arc::new(Material::builder().albedo(Vec3d::new(1., 1., 1.), 0.5).build());
+        let light_material = Arc::new(Material::builder().emission(Vec3d::new(1., 1., 1.), 4.).build());
+        let sphere = Sphere::new(Vec3d::new(0., 2., 0.), 0.5, light_material);
+        let lights = LightList::new(vec![Box::new(sphere.light().unwrap())]);
+        let world = World::new(Bvh::new(vec![
+            Box::new(Triangle::new(Vec3d::new(-1000., 0., 1000.), Vec3d::new(1000., 0., 1000.), Vec3d::new(0., 0., -1000.), floor_material)),
+            Box::new(sphere),
+        ]), lights);
+        let camera = Camera::builder().samples(1, 1).build();
+
+        let ray = Ray::new(Vec3d::new(0., 1., 1.), Vec3d::new(0., -1., -1.));
+        let samples = 20000;
+        let mut rng = StdRng::from_seed([1; 32]);
+        let mean = (0..samples).map(|_| camera.ray_color(&ray, &world, &mut rng).0.y).sum::<f64>() / samples as f64;
+        //brdf 0.5 / pi, sin^2 = 0.5^2 / 2^2
+        let expected = 0.5 / PI * PI * 4. * 0.0625;
+        assert!((mean - expected).abs() < 0.02 * expected, "expected {}, found {}", expected, mean);
+    }
+
+    #[test]
+    fn test_point_light(){
+        //only the shadow ray reaches the light, so a single sample is exact
+        let floor_material = Arc::new(Material::builder().albedo(Vec3d::new(1., 1., 1.), 0.5).build());
+        let lights = LightList::new(vec![Box::new(PointLight::new(Vec3d::new(0., 2., 0.), Vec3d::new(8., 8., 8.)))]);
+        let world = World::new(Bvh::new(vec![
+            Box::new(Triangle::new(Vec3d::new(-1000., 0., 1000.), Vec3d::new(1000., 0., 1000.), Vec3d::new(0., 0., -1000.), floor_material)),
+        ]), lights);
+        let camera = Camera::builder().samples(1, 1).build();
+        let ray = Ray::new(Vec3d::new(0., 1., 1.), Vec3d::new(0., -1., -1.));
+        //brdf 0.5 / pi, irradiance 8 / 2^2
+        let expected = 0.5 / PI * 2.;
+        assert!((camera.ray_color(&ray, &world, &mut thread_rng()).0.y - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_transparent_background_coverage(){
+        let floor_material = Arc::new(Material::builder().albedo(Vec3d::new(1., 1., 1.), 0.5).build());
+        let lights = LightList::new(vec![Box::new(PointLight::new(Vec3d::new(0., 2., 0.), Vec3d::new(8., 8., 8.)))]);
+        let world = World::new(Bvh::new(vec![
+            Box::new(Triangle::new(Vec3d::new(-1000., 0., 1000.), Vec3d::new(1000., 0., 1000.), Vec3d::new(0., 0., -1000.), floor_material)),
+        ]), lights);
+        let camera = Camera::builder().samples(1, 1).transparent_background(true).build();
+        let (color, coverage) = camera.ray_color(&Ray::new(Vec3d::new(0., 1., 1.), Vec3d::new(0., -1., -1.)), &world, &mut thread_rng());
+        assert_eq!(1., coverage);
+        assert!(color.y > 0.);
+        assert_eq!((Vec3d::zero(), 0.), camera.ray_color(&Ray::new(Vec3d::new(0., 1., 1.), Vec3d::up()), &world, &mut thread_rng()));
+    }
+
+    #[test]
+    fn test_absorbing_atmosphere(){
+        //the point light setup in fog that only absorbs, attenuated along the camera ray and the shadow ray
+        let floor_material = Arc::new(Material::builder().albedo(Vec3d::new(1., 1., 1.), 0.5).build());
+        let lights = LightList::new(vec![Box::new(PointLight::new(Vec3d::new(0., 2., 0.), Vec3d::new(8., 8., 8.)))]);
+        let fog = HomogeneousMedium::new(Vec3d::new(0.2, 0.2, 0.2), Vec3d::zero(), 0.);
+        let world = World::new(Bvh::new(vec![
+            Box::new(Triangle::new(Vec3d::new(-1000., 0., 1000.), Vec3d::new(1000., 0., 1000.), Vec3d::new(0., 0., -1000.), floor_material)),
+        ]), lights).with_media(vec![MediumRegion::everywhere(Box::new(fog))]);
+        let camera = Camera::builder().samples(1, 1).build();
+        let ray = Ray::new(Vec3d::new(0., 1., 1.), Vec3d::new(0., -1., -1.));
+        let expected = 0.5 / PI * 2. * f64::exp(-0.2 * (2f64.sqrt() + 2.));
+        let n = 20000;
+        let mut rng = StdRng::from_seed([2; 32]);
+        let mean = (0..n).map(|_| camera.ray_color(&ray, &world, &mut rng).0.y).sum::<f64>() / n as f64;
+        assert!((mean - expected).abs() < 0.02 * expected, "mean {}, expected {}", mean, expected);
+    }
+
+    #[test]
+    fn test_environment_light_converges(){
+        //diffuse floor under a uniform environment reflects albedo times the radiance
+        let floor_material = Arc::new(Material::builder().albedo(Vec3d::new(1., 1., 1.), 0.5).build());
+        let mut image = Image::new_with_color(4, 8, Vec3d::new(2., 2., 2.));
+        //a bright spot above the horizon and one below it that the floor cannot see
+        image.set_pixel_color(1, 2, Vec3d::new(50., 50., 50.));
+        image.set_pixel_color(3, 5, Vec3d::new(500., 500., 500.));
+        let image = Arc::new(image);
+        let environment = EnvironmentLight::new(image.clone(), 1., 0.);
+        let lights = LightList::new(vec![Box::new(EnvironmentLight::new(image, 1., 0.))]);
+        let world = World::new(Bvh::new(vec![
+            Box::new(Triangle::new(Vec3d::new(-1000., 0., 1000.), Vec3d::new(1000., 0., 1000.), Vec3d::new(0., 0., -1000.), floor_material)),
+        ]), lights);
+        let camera = Camera::builder().samples(1, 1).build();
+
+        let mut rng = StdRng::from_seed([3; 32]);
+
+        //reference by integrating the environment over the upper hemisphere with cosine weighted directions
+        let reference_samples = 200000;
+        let irradiance = (0..reference_samples).map(|_| {
+            let p = loop {
+                let p = Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.);
+                if p.length_squared() < 1. {
+                    break p;
+                }
+            };
+            let direction = Vec3d::new(p.x, f64::sqrt((1. - p.x * p.x - p.y * p.y).max(0.)), p.y);
+            environment.radiance(direction).y
+        }).sum::<f64>() / reference_samples as f64;
+        let expected = 0.5 * irradiance;
+
+        let ray = Ray::new(Vec3d::new(0., 1., 1.), Vec3d::new(0., -1., -1.));
+        let samples = 20000;
+        let mean = (0..samples).map(|_| camera.ray_color(&ray, &world, &mut rng).0.y).sum::<f64>() / samples as f64;
+        assert!((mean - expected).abs() < 0.03 * expected, "expected {}, found {}", expected, mean);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::image::OutputFormat;
+use crate::scene::Scene;
+use crate::tonemap::ToneMapping;
+
+pub(crate) const DEFAULT_SCENE: &str = "scenes/default.scene";
+
+//exit codes
+pub(crate) const EXIT_RENDER_ERROR: i32 = 1;
+pub(crate) const EXIT_USAGE: i32 = 2;
+
+pub(crate) const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+Renders SCENE (default: scenes/default.scene). Options override the values in the scene file.
+
+Options:
+  -r, --resolution <WxH>   image size in pixels, e.g. 1920x1080
+  -s, --samples <N>        samples per pixel
+  -b, --max-bounces <N>    maximum number of bounces per path
+      --sky                enable the sky background
+      --no-sky             disable the sky background
+      --alpha              render a transparent background, stored as alpha channel in png files
+  -o, --output <PATH>      write the image to PATH instead of the outputs in the scene file
+  -f, --format <FORMAT>    format of --output: bmp, ppm, png, png16, pfm, hdr, exr, exr_uncompressed (default: from the file extension)
+  -e, --exposure <STOPS>   exposure of 8 and 16 bit outputs in stops
+  -t, --tone-mapping <NAME>
+                           tone mapping of 8 and 16 bit outputs: linear, reinhard, extended_reinhard, aces, agx
+  -j, --threads <N>        number of render threads (default: one per core)
+      --seed <N>           seed for randomly generated scene content
+  -h, --help               print this help
+
+Exit codes: 0 on success, 1 if the scene could not be loaded or rendered, 2 on invalid arguments.";
+
+pub(crate) enum Command {
+    Render(Options),
+    Help,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Options {
+    pub(crate) scene_path: String,
+    pub(crate) resolution: Option<(usize, usize)>,
+    pub(crate) samples_per_pixel: Option<usize>,
+    pub(crate) max_bounces: Option<usize>,
+    pub(crate) sky: Option<bool>,
+    pub(crate) alpha: bool,
+    pub(crate) output: Option<(OutputFormat, String)>,
+    pub(crate) exposure: Option<f64>,
+    pub(crate) tone_mapping: Option<ToneMapping>,
+    pub(crate) threads: Option<usize>,
+    pub(crate) seed: Option<u64>,
+}
+
+impl Options {
+    pub(crate) fn apply(&self, scene: &mut Scene) {
+        if let Some((width, height)) = self.resolution {
+            scene.render.image_width = width;
+            scene.render.image_height = height;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            scene.render.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(max_bounces) = self.max_bounces {
+            scene.render.max_bounces = max_bounces;
+        }
+        if let Some(sky) = self.sky {
+            scene.render.sky = sky;
+        }
+        if self.alpha {
+            scene.output.alpha = true;
+        }
+        if let Some(output) = &self.output {
+            scene.output.files = vec![output.clone()];
+        }
+        if let Some(exposure) = self.exposure {
+            scene.output.display_transform.exposure = exposure;
+        }
+        if let Some(tone_mapping) = self.tone_mapping {
+            //keep the white point from the scene file
+            if !matches!((tone_mapping, scene.output.display_transform.tone_mapping), (ToneMapping::ExtendedReinhard{..}, ToneMapping::ExtendedReinhard{..})) {
+                scene.output.display_transform.tone_mapping = tone_mapping;
+            }
+        }
+    }
+}
+
+//args without the program name
+pub(crate) fn parse_args<I>(args: I) -> Result<Command, String> where I: IntoIterator<Item = String> {
+    let mut options = Options::default();
+    let mut scene_path: Option<String> = None;
+    let mut output_path: Option<String> = None;
+    let mut format: Option<OutputFormat> = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        //--option=value is accepted as well as --option value
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            return match inline_value.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(format!("missing value for `{}`", name)),
+            };
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-r" | "--resolution" => options.resolution = Some(parse_resolution(&value()?)?),
+            "-s" | "--samples" => options.samples_per_pixel = Some(parse_positive(&name, &value()?)?),
+            "-b" | "--max-bounces" => options.max_bounces = Some(parse_number(&name, &value()?)?),
+            "--sky" => options.sky = Some(true),
+            "--no-sky" => options.sky = Some(false),
+            "--alpha" => options.alpha = true,
+            "-o" | "--output" => output_path = Some(value()?),
+            "-f" | "--format" => {
+                let value = value()?;
+                format = Some(OutputFormat::from_name(&value).ok_or(format!("unknown format `{}`", value))?);
+            }
+            "-e" | "--exposure" => {
+                let value = value()?;
+                options.exposure = Some(value.parse::<f64>().ok().filter(|e| e.is_finite()).ok_or(format!("`{}` expects a number, found `{}`", name, value))?);
+            }
+            "-t" | "--tone-mapping" => {
+                let value = value()?;
+                options.tone_mapping = Some(ToneMapping::from_name(&value).ok_or(format!("unknown tone mapping `{}`", value))?);
+            }
+            "-j" | "--threads" => options.threads = Some(parse_positive(&name, &value()?)?),
+            "--seed" => options.seed = Some(parse_number(&name, &value()?)?),
+            _ if name.starts_with('-') && name.len() > 1 => return Err(format!("unknown option `{}`", name)),
+            _ => {
+                if let Some(previous) = &scene_path {
+                    return Err(format!("more than one scene given: `{}` and `{}`", previous, arg));
+                }
+                scene_path = Some(arg);
+            }
+        }
+    }
+
+    options.output = match (output_path, format) {
+        (Some(path), Some(format)) => Some((format, path)),
+        (Some(path), None) => match OutputFormat::from_path(&path) {
+            Some(format) => Some((format, path)),
+            None => return Err(format!("cannot tell the format of `{}` from its extension, use --format", path)),
+        },
+        (None, Some(_)) => return Err("--format needs --output".to_string()),
+        (None, None) => None,
+    };
+    options.scene_path = scene_path.unwrap_or(DEFAULT_SCENE.to_string());
+    return Ok(Command::Render(options));
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    return value.parse::<T>().map_err(|_| format!("`{}` expects a non-negative integer, found `{}`", name, value));
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<usize, String> {
+    return match value.parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("`{}` expects a positive integer, found `{}`", name, value)),
+    };
+}
+
+fn parse_resolution(value: &str) -> Result<(usize, usize), String> {
+    let error = || format!("resolution expects WIDTHxHEIGHT, e.g. 1920x1080, found `{}`", value);
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(error)?;
+    return match (width.parse::<usize>(), height.parse::<usize>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(error()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        return match parse_args(args.iter().map(|a| a.to_string()))? {
+            Command::Render(options) => Ok(options),
+            Command::Help => Err("help".to_string()),
+        };
+    }
+
+    #[test]
+    fn test_defaults(){
+        let options = parse(&[]).unwrap();
+        assert_eq!(Options{ scene_path: DEFAULT_SCENE.to_string(), ..Options::default() }, options);
+    }
+
+    #[test]
+    fn test_all_options(){
+        let options = parse(&["my.scene", "-r", "640x360", "--samples=50", "-b", "3", "--no-sky", "--alpha", "-o", "out/a.ppm", "-e", "-0.5", "--tone-mapping=agx", "-j", "4", "--seed", "42"]).unwrap();
+        assert_eq!(Options{
+            scene_path: "my.scene".to_string(),
+            resolution: Some((640, 360)),
+            samples_per_pixel: Some(50),
+            max_bounces: Some(3),
+            sky: Some(false),
+            alpha: true,
+            output: Some((OutputFormat::Ppm, "out/a.ppm".to_string())),
+            exposure: Some(-0.5),
+            tone_mapping: Some(ToneMapping::Agx),
+            threads: Some(4),
+            seed: Some(42),
+        }, options);
+
+        let options = parse(&["-o", "out/image", "--format", "bmp"]).unwrap();
+        assert_eq!(Some((OutputFormat::Bmp, "out/image".to_string())), options.output);
+
+        let options = parse(&["-o", "out/image.png", "-f", "png16"]).unwrap();
+        assert_eq!(Some((OutputFormat::Png16, "out/image.png".to_string())), options.output);
+    }
+
+    #[test]
+    fn test_help(){
+        assert!(matches!(parse_args(vec!["-s".to_string(), "5".to_string(), "--help".to_string()]), Ok(Command::Help)));
+        assert!(USAGE.contains("-e, --exposure <STOPS>") && USAGE.contains("-t, --tone-mapping <NAME>"));
+        for name in ToneMapping::NAMES {
+            assert!(USAGE.contains(name));
+        }
+    }
+
+    #[test]
+    fn test_invalid(){
+        assert_eq!(Err("unknown option `--fast`".to_string()), parse(&["--fast"]));
+        assert_eq!(Err("missing value for `-s`".to_string()), parse(&["-s"]));
+        assert_eq!(Err("`--samples` expects a positive integer, found `0`".to_string()), parse(&["--samples", "0"]));
+        assert!(parse(&["-r", "1920"]).is_err());
+        assert!(parse(&["-o", "image.xyz"]).is_err());
+        assert!(parse(&["-f", "gif", "-o", "a.bmp"]).is_err());
+        assert!(parse(&["a.scene", "b.scene"]).is_err());
+        assert!(parse(&["-t", "filmic"]).is_err());
+        assert!(parse(&["-e", "bright"]).is_err());
+    }
+}
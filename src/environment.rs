@@ -0,0 +1,262 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use rand::Rng;
+use crate::image::Image;
+use crate::light::{Light, LightSample};
+use crate::tonemap::luminance;
+use crate::vec3d::Vec3d;
+
+//equirectangular environment map around the scene, seen by rays that escape and sampled as a light at infinity
+
+//piecewise constant density on 0..1 proportional to func
+pub(crate) struct Distribution1d {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1d {
+    pub(crate) fn new(func: Vec<f64>) -> Self {
+        assert!(!func.is_empty());
+        let count = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.);
+        for value in &func {
+            cdf.push(cdf[cdf.len() - 1] + value.max(0.) / count);
+        }
+        let integral = cdf[func.len()];
+        //uniform if the function is zero everywhere
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. { *c / integral } else { i as f64 / count };
+        }
+        Self{
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    fn density(&self, index: usize) -> f64 {
+        if self.integral > 0. {
+            return self.func[index].max(0.) / self.integral;
+        }
+        return 1.;
+    }
+
+    //x in 0..1, its density and the segment it lies in, for u in 0..1
+    pub(crate) fn sample(&self, u: f64) -> (f64, f64, usize) {
+        //the segment with cdf[index] <= u < cdf[index + 1] is never empty
+        let index = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.func.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0. { ((u - self.cdf[index]) / width).clamp(0., 1.) } else { 0.5 };
+        let x = (index as f64 + offset) / self.func.len() as f64;
+        return (x.min(1. - f64::EPSILON), self.density(index), index);
+    }
+
+    pub(crate) fn pdf(&self, x: f64) -> f64 {
+        let index = ((x * self.func.len() as f64) as usize).min(self.func.len() - 1);
+        return self.density(index);
+    }
+}
+
+//density on the unit square, rows along y are picked first and columns along x inside the row
+pub(crate) struct Distribution2d {
+    rows: Vec<Distribution1d>,
+    marginal: Distribution1d,
+}
+
+impl Distribution2d {
+    //values[row][col]
+    pub(crate) fn new(values: Vec<Vec<f64>>) -> Self {
+        let rows: Vec<Distribution1d> = values.into_iter().map(Distribution1d::new).collect();
+        let marginal = Distribution1d::new(rows.iter().map(|row| row.integral).collect());
+        Self{
+            rows,
+            marginal,
+        }
+    }
+
+    //(x, y) and its density
+    pub(crate) fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.rows[row].sample(u.0);
+        return ((x, y), pdf_x * pdf_y);
+    }
+
+    pub(crate) fn pdf(&self, point: (f64, f64)) -> f64 {
+        let row = ((point.1 * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        return self.rows[row].pdf(point.0) * self.marginal.pdf(point.1);
+    }
+}
+
+//unit direction of a point on an unrotated environment map
+pub(crate) fn equirectangular_direction(uv: (f64, f64)) -> Vec3d {
+    let phi = (uv.0 - 0.5) * 2. * PI;
+    let theta = uv.1 * PI;
+    return Vec3d::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+}
+
+//the top row of the image is straight up, the center of the image looks along -z
+pub(crate) struct EnvironmentLight {
+    image: Arc<Image>,
+    intensity: f64,
+    //around the y axis, in radians
+    rotation: f64,
+    //over (u, v) of the image, proportional to luminance and the solid angle of the pixels
+    distribution: Distribution2d,
+}
+
+impl EnvironmentLight {
+    //rotation in degrees
+    pub(crate) fn new(image: Arc<Image>, intensity: f64, rotation: f64) -> Self {
+        let values = (0..image.height).map(|row| {
+            let sin_theta = f64::sin(PI * (row as f64 + 0.5) / image.height as f64);
+            (0..image.width).map(|col| sin_theta * luminance(image.pixel_color(row, col))).collect()
+        }).collect();
+        Self{
+            distribution: Distribution2d::new(values),
+            image,
+            intensity,
+            rotation: rotation.to_radians(),
+        }
+    }
+
+    fn direction_to_uv(&self, direction: Vec3d) -> (f64, f64) {
+        let phi = f64::atan2(direction.x, -direction.z) - self.rotation;
+        let u = (phi / (2. * PI) + 0.5).rem_euclid(1.);
+        let v = direction.y.clamp(-1., 1.).acos() / PI;
+        return (u, v);
+    }
+
+    fn uv_to_direction(&self, uv: (f64, f64)) -> Vec3d {
+        return equirectangular_direction((uv.0 + self.rotation / (2. * PI), uv.1));
+    }
+
+    //radiance arriving from the unit direction, bilinearly filtered, wrapping around horizontally
+    pub(crate) fn radiance(&self, direction: Vec3d) -> Vec3d {
+        let (u, v) = self.direction_to_uv(direction);
+        let (width, height) = (self.image.width as i64, self.image.height as i64);
+        let x = u * width as f64 - 0.5;
+        let y = v * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |col: i64, row: i64| {
+            self.image.pixel_color(row.clamp(0, height - 1) as usize, col.rem_euclid(width) as usize)
+        };
+        let top = (1. - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+        let bottom = (1. - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+        return self.intensity * ((1. - fy) * top + fy * bottom);
+    }
+
+    //solid angle density from the density over the image
+    fn solid_angle_pdf(&self, uv: (f64, f64)) -> f64 {
+        let sin_theta = f64::sin(uv.1 * PI);
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        return self.distribution.pdf(uv) / (2. * PI * PI * sin_theta);
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        let (uv, pdf) = self.distribution.sample((rng.gen::<f64>(), rng.gen::<f64>()));
+        let sin_theta = f64::sin(uv.1 * PI);
+        if pdf == 0. || sin_theta <= 0. {
+            return None;
+        }
+        let direction = self.uv_to_direction(uv);
+        return Some(LightSample{
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance(direction),
+            pdf: pdf / (2. * PI * PI * sin_theta),
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, _origin: Vec3d, direction: Vec3d, distance: f64) -> f64 {
+        if distance.is_finite() {
+            return 0.;
+        }
+        return self.solid_angle_pdf(self.direction_to_uv(direction));
+    }
+
+    fn background(&self, direction: Vec3d) -> Option<Vec3d> {
+        return Some(self.radiance(direction));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_distribution_1d(){
+        let distribution = Distribution1d::new(vec![0., 3., 1.]);
+        let (x, pdf, index) = distribution.sample(0.25);
+        assert!((x - 4. / 9.).abs() < 1e-12);
+        assert_eq!((2.25, 1), (pdf, index));
+        assert!((distribution.sample(0.875).0 - 2.5 / 3.).abs() < 1e-12);
+        assert_eq!(0., distribution.pdf(0.2));
+        assert_eq!(0.75, distribution.pdf(0.9));
+        //zero everywhere falls back to uniform
+        let black = Distribution1d::new(vec![0., 0.]);
+        assert_eq!((0.25, 1., 0), black.sample(0.25));
+    }
+
+    fn environment(bright: (usize, usize)) -> EnvironmentLight {
+        let mut image = Image::new_with_color(8, 16, Vec3d::new(0.1, 0.1, 0.1));
+        image.set_pixel_color(bright.0, bright.1, Vec3d::new(1000., 1000., 1000.));
+        return EnvironmentLight::new(Arc::new(image), 2., 30.);
+    }
+
+    #[test]
+    fn test_directions_round_trip(){
+        let light = environment((2, 3));
+        for uv in [(0.1, 0.2), (0.5, 0.5), (0.95, 0.7)] {
+            let (u, v) = light.direction_to_uv(light.uv_to_direction(uv));
+            assert!((u - uv.0).abs() < 1e-12 && (v - uv.1).abs() < 1e-12);
+        }
+        //the image center is along -z before rotation
+        let unrotated = EnvironmentLight::new(light.image.clone(), 1., 0.);
+        assert!((unrotated.uv_to_direction((0.5, 0.5)) - Vec3d::forward()).length() < 1e-12);
+        assert!((unrotated.radiance(Vec3d::new(0.3, -0.01, -1.).unit()) - Vec3d::new(0.1, 0.1, 0.1)).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_sampling_matches_pdf(){
+        let light = environment((2, 3));
+        let mut rng = StdRng::from_seed([5; 32]);
+        let samples = 2000;
+        let mut bright = 0;
+        for _ in 0..samples {
+            let sample = light.sample(Vec3d::zero(), &mut rng).unwrap();
+            assert!((sample.direction.length() - 1.).abs() < 1e-12);
+            let pdf = light.pdf(Vec3d::zero(), sample.direction, f64::INFINITY);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
+            let (u, v) = light.direction_to_uv(sample.direction);
+            if (v * 8.) as usize == 2 && (u * 16.) as usize == 3 {
+                bright += 1;
+            }
+        }
+        //the bright pixel holds almost all of the power
+        assert!(bright > samples * 9 / 10);
+        assert_eq!(0., light.pdf(Vec3d::zero(), Vec3d::up(), 10.));
+
+        //the solid angle density integrates to 1 over the sphere
+        let count = 200000;
+        let integral = (0..count).flat_map(|_| {
+            let direction = Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            if direction.length_squared() > 1. || direction.length_squared() < 1e-6 {
+                return None;
+            }
+            Some(light.pdf(Vec3d::zero(), direction.unit(), f64::INFINITY))
+        }).collect::<Vec<f64>>();
+        let estimate = 4. * PI * integral.iter().sum::<f64>() / integral.len() as f64;
+        assert!((estimate - 1.).abs() < 0.1, "integral {}", estimate);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::image::Image;
+use crate::zlib;
+
+//single part scanline OpenEXR with 32 bit float R, G, B channels
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ExrCompression {
+    None,
+    //zlib compressed blocks of 16 scanlines
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        return match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        };
+    }
+
+    fn scanlines_per_block(&self) -> usize {
+        return match self {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        };
+    }
+}
+
+pub(crate) fn encode(image: &Image, compression: ExrCompression) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(MAGIC.to_le_bytes());
+    out.extend(VERSION.to_le_bytes());
+
+    //channels are stored in alphabetical order
+    let channels = ["B", "G", "R"];
+    let mut channel_list = Vec::new();
+    for name in channels {
+        channel_list.extend(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend(PIXEL_TYPE_FLOAT.to_le_bytes());
+        //pLinear and 3 reserved bytes
+        channel_list.extend([0, 0, 0, 0]);
+        //x and y sampling
+        channel_list.extend(1i32.to_le_bytes());
+        channel_list.extend(1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in [0, 0, image.width as i32 - 1, image.height as i32 - 1] {
+        window.extend(value.to_le_bytes());
+    }
+
+    write_attribute(&mut out, "channels", "chlist", &channel_list);
+    write_attribute(&mut out, "compression", "compression", &[compression.id()]);
+    write_attribute(&mut out, "dataWindow", "box2i", &window);
+    write_attribute(&mut out, "displayWindow", "box2i", &window);
+    //increasing y
+    write_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    write_attribute(&mut out, "screenWindowCenter", "v2f", &[0u8; 8]);
+    write_attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    out.push(0);
+
+    let lines_per_block = compression.scanlines_per_block();
+    let block_count = image.height.div_ceil(lines_per_block);
+    let blocks: Vec<Vec<u8>> = (0..block_count).map(|block| {
+        let first_line = block * lines_per_block;
+        let last_line = (first_line + lines_per_block).min(image.height);
+        let mut raw = Vec::with_capacity((last_line - first_line) * image.width * 12);
+        for row in first_line..last_line {
+            let colors: Vec<_> = (0..image.width).map(|col| image.pixel_color(row, col)).collect();
+            //B, G, R
+            for channel in [2, 1, 0] {
+                for color in &colors {
+                    raw.extend((color[channel] as f32).to_le_bytes());
+                }
+            }
+        }
+        let data = match compression {
+            ExrCompression::None => raw,
+            ExrCompression::Zip => {
+                let compressed = zlib::compress(&zip_predictor(&raw));
+                //readers treat blocks that did not shrink as uncompressed
+                if compressed.len() < raw.len() { compressed } else { raw }
+            }
+        };
+        let mut chunk = Vec::with_capacity(data.len() + 8);
+        chunk.extend((first_line as i32).to_le_bytes());
+        chunk.extend((data.len() as i32).to_le_bytes());
+        chunk.extend(data);
+        chunk
+    }).collect();
+
+    //offset table with the absolute file position of every block
+    let mut offset = (out.len() + 8 * block_count) as u64;
+    for block in &blocks {
+        out.extend(offset.to_le_bytes());
+        offset += block.len() as u64;
+    }
+    for block in blocks {
+        out.extend(block);
+    }
+    return out;
+}
+
+fn write_attribute(out: &mut Vec<u8>, name: &str, attribute_type: &str, value: &[u8]) {
+    out.extend(name.as_bytes());
+    out.push(0);
+    out.extend(attribute_type.as_bytes());
+    out.push(0);
+    out.extend((value.len() as i32).to_le_bytes());
+    out.extend(value);
+}
+
+//splits even and odd bytes into two halves and delta encodes the result, as required before zip compression
+fn zip_predictor(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut reordered = vec![0u8; raw.len()];
+    for (i, &byte) in raw.iter().enumerate() {
+        let target = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        reordered[target] = byte;
+    }
+    let mut previous = reordered.first().copied().unwrap_or(0);
+    for byte in reordered.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = (current as i32 - previous as i32 + 128) as u8;
+        previous = current;
+    }
+    return reordered;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3d::Vec3d;
+
+    #[test]
+    fn test_zip_predictor(){
+        assert_eq!(vec![1, 129, 129, 128], zip_predictor(&[1, 3, 2, 3]));
+    }
+
+    #[test]
+    fn test_uncompressed_layout(){
+        let mut image = Image::new(2, 1);
+        image.set_pixels(vec![Vec3d::new(1., 2., 3.), Vec3d::new(4., 5., 6.)]);
+        let data = encode(&image, ExrCompression::None);
+        assert_eq!(MAGIC.to_le_bytes(), data[0..4]);
+        //last block: y, size and B, G, R of the second scanline
+        let mut last_block = Vec::new();
+        last_block.extend(1i32.to_le_bytes());
+        last_block.extend(12i32.to_le_bytes());
+        for value in [6f32, 5., 4.] {
+            last_block.extend(value.to_le_bytes());
+        }
+        assert_eq!(last_block, data[data.len() - 20..]);
+    }
+}
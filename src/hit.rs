@@ -1,5 +1,6 @@
 use std::ops::Range;
 use std::sync::Arc;
+use crate::aabb::Aabb;
 use crate::ray::Ray;
 use crate::vec3d::Vec3d;
 use crate::material::Material;
@@ -10,15 +11,19 @@ pub(crate) trait Hittable {
         ray: &Ray,
         interval: Range<f64>,
     ) -> Option<HitRecord>;
+
+    fn bounding_box(&self) -> Aabb;
 }
 
 pub(crate) struct HitRecord {
     pub(crate) pos: Vec3d,
     pub(crate) normal: Vec3d,
-    t: f64,
+    pub(crate) t: f64,
     pub(crate) material: Arc<Material>,
     //front_face: bool,
     pub(crate) front_face: bool,
+    //texture coordinates, 0..1 over the surface of shapes that define them
+    pub(crate) uv: (f64, f64),
 }
 
 impl HitRecord {
@@ -31,11 +36,33 @@ impl HitRecord {
             front_face,
             t,
             material,
+            uv: (0., 0.),
+        }
+    }
+
+    //front face is decided by the geometric normal, the interpolated shading normal is flipped along with it
+    pub(crate) fn with_shading_normal(pos: Vec3d, unit_geometric_normal: Vec3d, unit_shading_normal: Vec3d, t: f64, in_dir: Vec3d, material: Arc<Material>) -> HitRecord {
+        let front_face = unit_geometric_normal.dot(&in_dir) < 0.;
+        let normal = if front_face {unit_shading_normal} else {-unit_shading_normal};
+        Self{
+            pos,
+            normal,
+            front_face,
+            t,
+            material,
+            uv: (0., 0.),
         }
     }
 }
 
-impl Hittable for &Vec<Box<dyn Hittable + Sync>> {
+impl HitRecord {
+    pub(crate) fn with_uv(mut self, uv: (f64, f64)) -> HitRecord {
+        self.uv = uv;
+        self
+    }
+}
+
+impl Hittable for &Vec<Box<dyn Hittable + Send + Sync>> {
     fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
         let mut closest_so_far = interval.end;
         let mut temp_rec = None;
@@ -47,4 +74,8 @@ impl Hittable for &Vec<Box<dyn Hittable + Sync>> {
         }
         return temp_rec;
     }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.iter().fold(Aabb::empty(), |acc, hittable| acc.surrounding(&hittable.bounding_box()));
+    }
 }
\ No newline at end of file
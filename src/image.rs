@@ -0,0 +1,816 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use itertools::Itertools;
+use indicatif::ProgressIterator;
+use crate::exr::{self, ExrCompression};
+use crate::png::{self, PngBitDepth};
+use crate::tonemap::{self, DisplayTransform};
+use crate::vec3d::Vec3d;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum OutputFormat {
+    Bmp,
+    Ppm,
+    Png,
+    Png16,
+    Pfm,
+    Hdr,
+    Exr,
+    ExrUncompressed,
+}
+
+impl OutputFormat {
+    pub(crate) const ALL: [OutputFormat; 8] = [OutputFormat::Bmp, OutputFormat::Ppm, OutputFormat::Png, OutputFormat::Png16, OutputFormat::Pfm, OutputFormat::Hdr, OutputFormat::Exr, OutputFormat::ExrUncompressed];
+
+    pub(crate) fn name(&self) -> &'static str {
+        return match self {
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Png => "png",
+            OutputFormat::Png16 => "png16",
+            OutputFormat::Pfm => "pfm",
+            OutputFormat::Hdr => "hdr",
+            OutputFormat::Exr => "exr",
+            OutputFormat::ExrUncompressed => "exr_uncompressed",
+        };
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<OutputFormat> {
+        return Self::ALL.into_iter().find(|format| format.name() == name.to_ascii_lowercase());
+    }
+
+    pub(crate) fn from_path(path: &str) -> Option<OutputFormat> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        return Self::from_name(extension);
+    }
+}
+
+//how 8 and 16 bit samples of image files are interpreted, floating point formats are always linear
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ColorSpace {
+    //color textures
+    Srgb,
+    //data like roughness or normal maps
+    Linear,
+}
+
+impl ColorSpace {
+    pub(crate) const NAMES: [&'static str; 2] = ["srgb", "linear"];
+
+    pub(crate) fn from_name(name: &str) -> Option<ColorSpace> {
+        return match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" => Some(ColorSpace::Linear),
+            _ => None,
+        };
+    }
+
+    //value in [0, 1] as stored in the file
+    pub(crate) fn to_linear(self, value: f64) -> f64 {
+        return match self {
+            ColorSpace::Srgb => tonemap::srgb_to_linear(value),
+            ColorSpace::Linear => value,
+        };
+    }
+}
+
+//8 bit output pixel
+pub(crate) struct Pixel {
+    pub(crate) r: u8,
+    pub(crate) g: u8,
+    pub(crate) b: u8,
+}
+
+//linear rgb radiance, quantised only when writing low dynamic range formats
+pub(crate) struct Image{
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    //row-major, starting top left
+    pixels: Vec<Vec3d>,
+    //fraction of each pixel covered by objects, only present for renders with a transparent background
+    alpha: Option<Vec<f64>>,
+    //used when converting to low dynamic range formats
+    display_transform: DisplayTransform,
+}
+
+impl Image {
+    pub fn new(height: usize, width: usize) -> Self{
+        assert!(height > 0 && width > 0);
+        Self {
+            width,
+            height,
+            //magenta marks pixels that were never set
+            pixels: vec![Vec3d::new(1., 0., 1.); height * width],
+            alpha: None,
+            display_transform: DisplayTransform::default(),
+        }
+    }
+
+    pub fn new_with_color(height: usize, width: usize, color: Vec3d) -> Self{
+        let mut image = Image::new(height,width);
+        for it in (0..image.height)
+            .cartesian_product(0..image.width)
+            .progress_count(image.height as u64 * image.width as u64){
+            let row = it.0;
+            let col = it.1;
+
+            image.set_pixel_color(row, col, color);
+        }
+        return image;
+    }
+
+    pub(crate) fn set_pixel_color(&mut self, row: usize, col: usize, color: Vec3d) {
+        assert!(row < self.height);
+        assert!(col < self.width);
+        self.pixels[row * self.width + col] = color;
+    }
+
+    pub(crate) fn pixel_color(&self, row: usize, col: usize) -> Vec3d {
+        return self.pixels[row * self.width + col];
+    }
+
+    pub(crate) fn set_pixels(&mut self, pixel_vector: Vec<Vec3d>) {
+        assert_eq!(pixel_vector.len(), self.height * self.width);
+        self.pixels = pixel_vector;
+    }
+
+    pub(crate) fn set_alpha(&mut self, alpha: Vec<f64>) {
+        assert_eq!(alpha.len(), self.height * self.width);
+        self.alpha = Some(alpha);
+    }
+
+    pub(crate) fn has_alpha(&self) -> bool {
+        return self.alpha.is_some();
+    }
+
+    //1 for images without alpha
+    pub(crate) fn pixel_alpha(&self, row: usize, col: usize) -> f64 {
+        return match &self.alpha {
+            Some(alpha) => alpha[row * self.width + col],
+            None => 1.,
+        };
+    }
+
+    pub(crate) fn set_display_transform(&mut self, display_transform: DisplayTransform) {
+        self.display_transform = display_transform;
+    }
+
+    //tone mapped, srgb encoded and quantised to 8 bit
+    pub(crate) fn pixel_u8(&self, row: usize, col: usize) -> Pixel {
+        let color = self.display_color(self.pixel_color(row, col));
+
+        let factor = 255.999;
+        Pixel{
+            r: (factor * color.x) as u8,
+            g: (factor * color.y) as u8,
+            b: (factor * color.z) as u8,
+        }
+    }
+
+    //srgb encoded values in [0, 1] for low dynamic range formats
+    pub(crate) fn display_color(&self, linear: Vec3d) -> Vec3d {
+        return self.display_transform.apply(linear);
+    }
+
+    //bmp, ppm, png, radiance hdr or pfm, detected from the content, alpha channels are dropped
+    pub(crate) fn read_from_file(path: &str, color_space: ColorSpace) -> std::io::Result<Image> {
+        let data = std::fs::read(path)?;
+        return Self::decode(&data, color_space).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)));
+    }
+
+    pub(crate) fn decode(data: &[u8], color_space: ColorSpace) -> std::io::Result<Image> {
+        return match data.get(..2) {
+            Some(b"BM") => decode_bmp(data, color_space),
+            Some(b"P3") | Some(b"P6") => decode_ppm(data, color_space),
+            Some(b"#?") => decode_hdr(data),
+            Some(b"PF") => decode_pfm(data),
+            _ if data.starts_with(b"\x89PNG") => png::decode(data, color_space),
+            _ => Err(invalid("unknown image format")),
+        };
+    }
+
+    //metadata is a list of (key, value) pairs, stored by formats that support text fields
+    pub(crate) fn write_to_file(&self, path: &str, format: OutputFormat, metadata: &[(String, String)]) -> std::io::Result<()> {
+        return match format {
+            OutputFormat::Bmp => self.write_to_file_bmp(path),
+            OutputFormat::Ppm => self.write_to_file_ppm(path),
+            OutputFormat::Png => self.write_to_file_png(path, PngBitDepth::Eight, metadata),
+            OutputFormat::Png16 => self.write_to_file_png(path, PngBitDepth::Sixteen, metadata),
+            OutputFormat::Pfm => self.write_to_file_pfm(path),
+            OutputFormat::Hdr => self.write_to_file_hdr(path),
+            OutputFormat::Exr => self.write_to_file_exr(path, ExrCompression::Zip),
+            OutputFormat::ExrUncompressed => self.write_to_file_exr(path, ExrCompression::None),
+        };
+    }
+
+    pub(crate) fn write_to_file_ppm(&self, path: &str) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+        buffer.write_all(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes()).unwrap();
+        for row in 0..self.height{
+            for col in 0..self.width {
+                let pixel = self.pixel_u8(row, col);
+                buffer.write_all(format!("{} {} {}\n", pixel.r, pixel.g, pixel.b).as_bytes()).unwrap();
+            }
+        }
+        buffer.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn write_to_file_bmp(&self, path: &str) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+
+        let padding_amount = (4 - (self.width * 3) % 4) % 4;
+        let file_header_size = 14;
+        let info_header_size = 40;
+        let file_size = (file_header_size + info_header_size + (3 * self.width + padding_amount) * self.height) as u32;
+        let reserved: u16 = 0;
+        let offset_to_pixel_array = (file_header_size + info_header_size) as u32;
+
+        buffer.write_all("BM".as_bytes()).unwrap();
+        buffer.write_all(&file_size.to_le_bytes()).unwrap();
+        buffer.write_all(&reserved.to_le_bytes()).unwrap();
+        buffer.write_all(&reserved.to_le_bytes()).unwrap();
+        buffer.write_all(&offset_to_pixel_array.to_le_bytes()).unwrap();
+
+        let info_header_size: u32 = 40;
+        let width: i32 = self.width as i32;
+        let height: i32 = self.height as i32;
+        let planes: u16 = 1;
+        let bits_per_pixel: u16 = 24;
+        let compression: u32 = 0;
+        let image_size: u32 = (3 * self.width + padding_amount) as u32 * self.height as u32;
+        let x_pixels_per_meter: i32 = 0;
+        let y_pixels_per_meter: i32 = 0;
+        let total_colors: u32 = 0;
+        let important_colors: u32 = 0;
+
+        buffer.write_all(&info_header_size.to_le_bytes()).unwrap();
+        buffer.write_all(&width.to_le_bytes()).unwrap();
+        buffer.write_all(&height.to_le_bytes()).unwrap();
+        buffer.write_all(&planes.to_le_bytes()).unwrap();
+        buffer.write_all(&bits_per_pixel.to_le_bytes()).unwrap();
+        buffer.write_all(&compression.to_le_bytes()).unwrap();
+        buffer.write_all(&image_size.to_le_bytes()).unwrap();
+        buffer.write_all(&x_pixels_per_meter.to_le_bytes()).unwrap();
+        buffer.write_all(&y_pixels_per_meter.to_le_bytes()).unwrap();
+        buffer.write_all(&total_colors.to_le_bytes()).unwrap();
+        buffer.write_all(&important_colors.to_le_bytes()).unwrap();
+
+        for row in 0..self.height{
+            for col in 0..self.width {
+                let reversed_row = self.height - row - 1;
+                let pixel = self.pixel_u8(reversed_row, col);
+                buffer.write_all(&pixel.b.to_le_bytes()).unwrap();
+                buffer.write_all(&pixel.g.to_le_bytes()).unwrap();
+                buffer.write_all(&pixel.r.to_le_bytes()).unwrap();
+            }
+            for _ in 0..padding_amount{
+                buffer.write_all(&0u8.to_le_bytes()).unwrap();
+            }
+        }
+        buffer.flush()?;
+        Ok(())
+    }
+
+    //portable float map, linear 32 bit floats, rows stored bottom to top
+    pub(crate) fn write_to_file_pfm(&self, path: &str) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+        //negative scale marks little endian data
+        buffer.write_all(format!("PF\n{} {}\n-1.0\n", self.width, self.height).as_bytes())?;
+        for row in (0..self.height).rev() {
+            for col in 0..self.width {
+                let color = self.pixel_color(row, col);
+                for channel in [color.x, color.y, color.z] {
+                    buffer.write_all(&(channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+        buffer.flush()?;
+        Ok(())
+    }
+
+    //radiance rgbe with run length encoded scanlines
+    pub(crate) fn write_to_file_hdr(&self, path: &str) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+        buffer.write_all(format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.height, self.width).as_bytes())?;
+        let mut scanline: Vec<[u8; 4]> = Vec::with_capacity(self.width);
+        for row in 0..self.height {
+            scanline.clear();
+            scanline.extend((0..self.width).map(|col| float_to_rgbe(self.pixel_color(row, col))));
+            buffer.write_all(&encode_rgbe_scanline(&scanline))?;
+        }
+        buffer.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn write_to_file_png(&self, path: &str, bit_depth: PngBitDepth, metadata: &[(String, String)]) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+        buffer.write_all(&png::encode(self, bit_depth, metadata))?;
+        buffer.flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn write_to_file_exr(&self, path: &str, compression: ExrCompression) -> std::io::Result<()> {
+        let mut buffer = create_file(path)?;
+        buffer.write_all(&exr::encode(self, compression))?;
+        buffer.flush()?;
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+}
+
+//bytes taken by width * height pixels, checked against the available data before allocating the image
+fn pixel_data_size(width: usize, height: usize, bytes_per_pixel: usize, available: usize, format: &str) -> std::io::Result<usize> {
+    let size = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(bytes_per_pixel)).ok_or(invalid(&format!("{} image is too large", format)))?;
+    if size > available {
+        return Err(invalid(&format!("{} pixel data is truncated", format)));
+    }
+    return Ok(size);
+}
+
+fn read_le<const N: usize>(data: &[u8], pos: usize) -> std::io::Result<[u8; N]> {
+    let bytes = data.get(pos..pos + N).ok_or(invalid("image file is truncated"))?;
+    return Ok(bytes.try_into().unwrap());
+}
+
+//uncompressed 24 bit or 32 bit (optionally with bit field masks), bottom up or top down
+fn decode_bmp(data: &[u8], color_space: ColorSpace) -> std::io::Result<Image> {
+    let offset = u32::from_le_bytes(read_le(data, 10)?) as usize;
+    let header_size = u32::from_le_bytes(read_le(data, 14)?);
+    if header_size < 40 {
+        return Err(invalid("unsupported bmp header"));
+    }
+    let width = i32::from_le_bytes(read_le(data, 18)?);
+    let height = i32::from_le_bytes(read_le(data, 22)?);
+    let bits_per_pixel = u16::from_le_bytes(read_le(data, 28)?);
+    let compression = u32::from_le_bytes(read_le(data, 30)?);
+    if width <= 0 || height == 0 {
+        return Err(invalid("invalid bmp size"));
+    }
+    //blue, green, red from the least significant byte unless masks are given
+    let masks = match (bits_per_pixel, compression) {
+        (24, 0) | (32, 0) => [0x00ff0000, 0x0000ff00, 0x000000ff],
+        //bit fields, the masks follow the 40 byte header
+        (32, 3) | (32, 6) => [
+            u32::from_le_bytes(read_le(data, 54)?),
+            u32::from_le_bytes(read_le(data, 58)?),
+            u32::from_le_bytes(read_le(data, 62)?),
+        ],
+        _ => return Err(invalid(&format!("unsupported bmp with {} bits per pixel and compression {}", bits_per_pixel, compression))),
+    };
+    let (width, top_down, height) = (width as usize, height < 0, height.unsigned_abs() as usize);
+    let bytes_per_pixel = bits_per_pixel as usize / 8;
+    let stride = (width * bytes_per_pixel).div_ceil(4) * 4;
+    let channel = |value: u32, mask: u32| {
+        if mask == 0 {
+            return 0.;
+        }
+        let shift = mask.trailing_zeros();
+        return color_space.to_linear(((value & mask) >> shift) as f64 / (mask >> shift) as f64);
+    };
+    //rows may lack the padding of the last one
+    pixel_data_size(width, height, bytes_per_pixel, data.len().saturating_sub(offset), "bmp")?;
+    let mut image = Image::new(height, width);
+    for file_row in 0..height {
+        let row = if top_down { file_row } else { height - 1 - file_row };
+        for col in 0..width {
+            let pos = offset + file_row * stride + col * bytes_per_pixel;
+            let bytes = data.get(pos..pos + bytes_per_pixel).ok_or(invalid("bmp pixel data is truncated"))?;
+            let value = bytes.iter().rev().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+            image.set_pixel_color(row, col, Vec3d::new(channel(value, masks[0]), channel(value, masks[1]), channel(value, masks[2])));
+        }
+    }
+    return Ok(image);
+}
+
+//whitespace separated header tokens with # comments, returns them and the position after the last one
+fn header_tokens(data: &[u8], count: usize) -> std::io::Result<(Vec<String>, usize)> {
+    let mut tokens = Vec::with_capacity(count);
+    let mut pos = 0;
+    while tokens.len() < count {
+        match data.get(pos) {
+            None => return Err(invalid("image header is truncated")),
+            Some(b'#') => {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                tokens.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+            }
+        }
+    }
+    return Ok((tokens, pos));
+}
+
+fn parse_dimension(token: &str) -> std::io::Result<usize> {
+    return match token.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(invalid(&format!("invalid image dimension `{}`", token))),
+    };
+}
+
+//ascii (P3) or binary (P6) with 8 or 16 bit samples
+fn decode_ppm(data: &[u8], color_space: ColorSpace) -> std::io::Result<Image> {
+    let (header, end) = header_tokens(data, 4)?;
+    let width = parse_dimension(&header[1])?;
+    let height = parse_dimension(&header[2])?;
+    let max_value = match header[3].parse::<u32>() {
+        Ok(value) if value > 0 && value < 65536 => value,
+        _ => return Err(invalid(&format!("invalid ppm maximum value `{}`", header[3]))),
+    };
+    let samples: Vec<u32> = if header[0] == "P3" {
+        //every sample takes at least one byte
+        let count = pixel_data_size(width, height, 3, data.len() - end, "ppm")?;
+        let (tokens, _) = header_tokens(&data[end..], count)?;
+        tokens.iter().map(|t| t.parse::<u32>().map_err(|_| invalid(&format!("invalid ppm sample `{}`", t)))).collect::<std::io::Result<_>>()?
+    } else {
+        //a single whitespace byte separates the header from the samples
+        let body = data.get(end + 1..).ok_or(invalid("ppm pixel data is truncated"))?;
+        let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+        let size = pixel_data_size(width, height, 3 * bytes_per_sample, body.len(), "ppm")?;
+        let body = &body[..size];
+        body.chunks_exact(bytes_per_sample).map(|b| b.iter().fold(0u32, |acc, &byte| (acc << 8) | byte as u32)).collect()
+    };
+    let mut image = Image::new(height, width);
+    for (index, rgb) in samples.chunks_exact(3).enumerate() {
+        let channel = |sample: u32| color_space.to_linear(sample.min(max_value) as f64 / max_value as f64);
+        image.set_pixel_color(index / width, index % width, Vec3d::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2])));
+    }
+    return Ok(image);
+}
+
+//radiance rgbe, flat or run length encoded scanlines
+fn decode_hdr(data: &[u8]) -> std::io::Result<Image> {
+    let mut pos = 0;
+    let mut next_line = || -> std::io::Result<String> {
+        let length = data[pos..].iter().position(|&b| b == b'\n').ok_or(invalid("radiance header is truncated"))?;
+        let line = String::from_utf8_lossy(&data[pos..pos + length]).trim_end().to_string();
+        pos += length + 1;
+        return Ok(line);
+    };
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid(&format!("unsupported radiance format {}", format)));
+            }
+        }
+    }
+    let resolution = next_line()?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (bottom_up, height, width) = match parts.as_slice() {
+        ["-Y", height, "+X", width] => (false, parse_dimension(height)?, parse_dimension(width)?),
+        ["+Y", height, "+X", width] => (true, parse_dimension(height)?, parse_dimension(width)?),
+        _ => return Err(invalid(&format!("unsupported radiance resolution `{}`", resolution))),
+    };
+    //run length encoding has no fixed size per pixel, the pixels grow with the decoded scanlines
+    //and the image is allocated once all of them were read
+    let mut pixels = Vec::new();
+    let mut scanline = Vec::new();
+    for _ in 0..height {
+        pos = decode_rgbe_scanline(data, pos, width, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_float(rgbe)));
+    }
+    if bottom_up {
+        pixels = pixels.chunks_exact(width).rev().flatten().copied().collect();
+    }
+    let mut image = Image::new(height, width);
+    image.set_pixels(pixels);
+    return Ok(image);
+}
+
+//replaces scanline with the width pixels read at pos, returns the position after them
+fn decode_rgbe_scanline(data: &[u8], mut pos: usize, width: usize, scanline: &mut Vec<[u8; 4]>) -> std::io::Result<usize> {
+    scanline.clear();
+    let mut byte = || -> std::io::Result<u8> {
+        let value = *data.get(pos).ok_or(invalid("radiance pixel data is truncated"))?;
+        pos += 1;
+        return Ok(value);
+    };
+    let start: [u8; 4] = [byte()?, byte()?, byte()?, byte()?];
+    let new_rle = start[0] == 2 && start[1] == 2 && start[2] < 128 && (8..=0x7fff).contains(&width);
+    if new_rle {
+        if ((start[2] as usize) << 8 | start[3] as usize) != width {
+            return Err(invalid("radiance scanline width does not match"));
+        }
+        scanline.resize(width, [0; 4]);
+        for channel in 0..4 {
+            let mut col = 0;
+            while col < width {
+                let count = byte()? as usize;
+                if count > 128 {
+                    let value = byte()?;
+                    let run = count - 128;
+                    if col + run > width {
+                        return Err(invalid("radiance run exceeds the scanline"));
+                    }
+                    scanline[col..col + run].iter_mut().for_each(|pixel| pixel[channel] = value);
+                    col += run;
+                } else {
+                    if count == 0 || col + count > width {
+                        return Err(invalid("invalid radiance literal run"));
+                    }
+                    for _ in 0..count {
+                        scanline[col][channel] = byte()?;
+                        col += 1;
+                    }
+                }
+            }
+        }
+        return Ok(pos);
+    }
+    //flat pixels, 1 1 1 n repeats the previous pixel with the count shifted by 8 for every consecutive repeat
+    let mut col = 0;
+    let mut shift = 0;
+    let mut pixel = start;
+    loop {
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 && col > 0 {
+            let count = (pixel[3] as usize).checked_shl(shift).filter(|&count| count <= width - col)
+                .ok_or(invalid("radiance run exceeds the scanline"))?;
+            let previous = scanline[col - 1];
+            scanline.extend(std::iter::repeat_n(previous, count));
+            col += count;
+            shift += 8;
+        } else {
+            scanline.push(pixel);
+            col += 1;
+            shift = 0;
+        }
+        if col >= width {
+            return Ok(pos);
+        }
+        pixel = [byte()?, byte()?, byte()?, byte()?];
+    }
+}
+
+//the mantissas are taken at the center of their interval
+fn rgbe_to_float(rgbe: [u8; 4]) -> Vec3d {
+    if rgbe[3] == 0 {
+        return Vec3d::zero();
+    }
+    let scale = 2f64.powi(rgbe[3] as i32 - 136);
+    return Vec3d::new((rgbe[0] as f64 + 0.5) * scale, (rgbe[1] as f64 + 0.5) * scale, (rgbe[2] as f64 + 0.5) * scale);
+}
+
+//color (PF) or gray (Pf) floats, the sign of the scale gives the byte order
+fn decode_pfm(data: &[u8]) -> std::io::Result<Image> {
+    let (header, end) = header_tokens(data, 4)?;
+    let channels = if header[0] == "PF" { 3 } else if header[0] == "Pf" { 1 } else { return Err(invalid("invalid pfm header")) };
+    let width = parse_dimension(&header[1])?;
+    let height = parse_dimension(&header[2])?;
+    let little_endian = match header[3].parse::<f64>() {
+        Ok(scale) if scale != 0. => scale < 0.,
+        _ => return Err(invalid(&format!("invalid pfm scale `{}`", header[3]))),
+    };
+    let body = data.get(end + 1..).ok_or(invalid("pfm pixel data is truncated"))?;
+    let body = &body[..pixel_data_size(width, height, channels * 4, body.len(), "pfm")?];
+    let values: Vec<f64> = body.chunks_exact(4).map(|b| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        (if little_endian { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }) as f64
+    }).collect();
+    let mut image = Image::new(height, width);
+    for (index, pixel) in values.chunks_exact(channels).enumerate() {
+        //rows are stored bottom to top
+        let row = height - 1 - index / width;
+        let color = if channels == 3 { Vec3d::new(pixel[0], pixel[1], pixel[2]) } else { Vec3d::new(pixel[0], pixel[0], pixel[0]) };
+        image.set_pixel_color(row, index % width, color);
+    }
+    return Ok(image);
+}
+
+//creates the parent directory if needed
+fn create_file(path: &str) -> std::io::Result<BufWriter<File>> {
+    if let Some(parent) = std::path::Path::new(&path).parent(){
+        std::fs::create_dir_all(parent)?;
+    }
+    return Ok(BufWriter::new(File::create(path)?));
+}
+
+//shared exponent, negative values are clamped to 0
+fn float_to_rgbe(color: Vec3d) -> [u8; 4] {
+    let max = color.x.max(color.y).max(color.z);
+    if max.is_nan() || max <= 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    //max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    let mut scale = 256. / 2f64.powi(exponent);
+    //rounding in log2 may put max * scale at 256
+    if max * scale >= 256. {
+        exponent += 1;
+        scale /= 2.;
+    }
+    let channel = |c: f64| (c.max(0.) * scale) as u8;
+    return [channel(color.x), channel(color.y), channel(color.z), (exponent + 128).clamp(0, 255) as u8];
+}
+
+//new style rle: every channel is encoded separately as runs or literal dumps of up to 127 bytes
+fn encode_rgbe_scanline(scanline: &[[u8; 4]]) -> Vec<u8> {
+    let width = scanline.len();
+    if !(8..=0x7fff).contains(&width) {
+        return scanline.iter().flatten().copied().collect();
+    }
+    let mut out = vec![2, 2, (width >> 8) as u8, (width & 0xff) as u8];
+    for channel in 0..4 {
+        let data: Vec<u8> = scanline.iter().map(|pixel| pixel[channel]).collect();
+        let mut pos = 0;
+        while pos < width {
+            //look for the next run of at least 3 equal bytes
+            let mut run_start = pos;
+            let mut run_length = 0;
+            while run_start < width {
+                run_length = data[run_start..].iter().take(127).take_while(|&&b| b == data[run_start]).count();
+                if run_length >= 3 {
+                    break;
+                }
+                run_start += run_length;
+                run_length = 0;
+            }
+            //literal bytes before the run
+            while pos < run_start {
+                let count = (run_start - pos).min(128);
+                out.push(count as u8);
+                out.extend(&data[pos..pos + count]);
+                pos += count;
+            }
+            if run_length >= 3 {
+                out.push(128 + run_length as u8);
+                out.push(data[run_start]);
+                pos = run_start + run_length;
+            }
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::ToneMapping;
+
+    #[test]
+    fn test_float_to_rgbe(){
+        assert_eq!([128, 64, 0, 129], float_to_rgbe(Vec3d::new(1., 0.5, 0.)));
+        assert_eq!([128, 0, 0, 136], float_to_rgbe(Vec3d::new(128., -1., 0.)));
+        assert_eq!([0, 0, 0, 0], float_to_rgbe(Vec3d::zero()));
+    }
+
+    #[test]
+    fn test_rle_scanline(){
+        let mut scanline = vec![[1, 2, 3, 4]; 10];
+        scanline[0] = [9, 2, 3, 4];
+        let encoded = encode_rgbe_scanline(&scanline);
+        assert_eq!(vec![2, 2, 0, 10,
+                        1, 9, 128 + 9, 1,
+                        128 + 10, 2,
+                        128 + 10, 3,
+                        128 + 10, 4], encoded);
+    }
+
+    #[test]
+    fn test_decode_bmp(){
+        //2x2, 24 bit bottom up: first stored row is the bottom one, rows padded to 8 bytes
+        let mut data = b"BM".to_vec();
+        data.extend([0u8; 8]);
+        data.extend(54u32.to_le_bytes());
+        data.extend(40u32.to_le_bytes());
+        data.extend(2i32.to_le_bytes());
+        data.extend(2i32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(24u16.to_le_bytes());
+        data.extend([0u8; 24]);
+        data.extend([0, 0, 255, 0, 255, 0, 0, 0]);
+        data.extend([255, 0, 0, 255, 255, 255, 0, 0]);
+        let image = Image::decode(&data, ColorSpace::Linear).unwrap();
+        assert_eq!([Vec3d::new(0., 0., 1.), Vec3d::new(1., 1., 1.), Vec3d::new(1., 0., 0.), Vec3d::new(0., 1., 0.)], [image.pixel_color(0, 0), image.pixel_color(0, 1), image.pixel_color(1, 0), image.pixel_color(1, 1)]);
+
+        //1x2, 32 bit top down with bit field masks
+        let mut data = b"BM".to_vec();
+        data.extend([0u8; 8]);
+        data.extend(66u32.to_le_bytes());
+        data.extend(40u32.to_le_bytes());
+        data.extend(1i32.to_le_bytes());
+        data.extend((-2i32).to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(32u16.to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend([0u8; 20]);
+        data.extend([0x000000ffu32, 0x0000ff00, 0x00ff0000].iter().flat_map(|m| m.to_le_bytes()));
+        data.extend([255, 0, 0, 255, 0, 0, 255, 255]);
+        let image = Image::decode(&data, ColorSpace::Srgb).unwrap();
+        assert_eq!((1, 2), (image.width, image.height));
+        assert_eq!(Vec3d::new(1., 0., 0.), image.pixel_color(0, 0));
+        assert_eq!(Vec3d::new(0., 0., 1.), image.pixel_color(1, 0));
+    }
+
+    #[test]
+    fn test_decode_ppm(){
+        let image = Image::decode(b"P3\n# comment\n2 1\n255\n255 0 0\n0 0 128\n", ColorSpace::Srgb).unwrap();
+        assert_eq!(Vec3d::new(1., 0., 0.), image.pixel_color(0, 0));
+        assert!((image.pixel_color(0, 1).z - 0.2158605).abs() < 1e-6);
+
+        let mut data = b"P6 1 2 65535\n".to_vec();
+        data.extend([0xff, 0xff, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0]);
+        let image = Image::decode(&data, ColorSpace::Linear).unwrap();
+        assert_eq!(Vec3d::new(1., 0., 32768. / 65535.), image.pixel_color(0, 0));
+        assert_eq!(Vec3d::zero(), image.pixel_color(1, 0));
+        assert!(Image::decode(b"P6 1 2 255\n\x01", ColorSpace::Linear).is_err());
+        //the header ends at the end of the file
+        assert_eq!("ppm pixel data is truncated", Image::decode(b"P6 1 1 255", ColorSpace::Linear).err().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_header_sizes_are_checked_against_the_data(){
+        //none of these allocate the image the header asks for
+        let huge = usize::MAX.to_string();
+        assert_eq!("ppm image is too large", Image::decode(format!("P6 {} {} 255\n", huge, huge).as_bytes(), ColorSpace::Linear).err().unwrap().to_string());
+        assert_eq!("ppm pixel data is truncated", Image::decode(b"P3 100000 100000 255\n0 0 0", ColorSpace::Linear).err().unwrap().to_string());
+        assert_eq!("pfm pixel data is truncated", Image::decode(b"PF 100000 100000 -1.0\n\0\0\0\0", ColorSpace::Linear).err().unwrap().to_string());
+        assert!(Image::decode(b"#?RADIANCE\n\n-Y 100000 +X 100000\n\0\0\0\0", ColorSpace::Linear).is_err());
+        //old style runs shifted past the width of usize
+        let mut data = b"#?RADIANCE\n\n-Y 1 +X 2\n\x80\x80\x80\x81".to_vec();
+        data.extend([1, 1, 1, 0].repeat(9));
+        assert!(Image::decode(&data, ColorSpace::Linear).is_err());
+
+        let mut data = b"BM".to_vec();
+        data.extend([0u8; 8]);
+        data.extend(54u32.to_le_bytes());
+        data.extend(40u32.to_le_bytes());
+        data.extend(i32::MAX.to_le_bytes());
+        data.extend(i32::MAX.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(24u16.to_le_bytes());
+        data.extend([0u8; 24]);
+        assert_eq!("bmp pixel data is truncated", Image::decode(&data, ColorSpace::Linear).err().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_decode_hdr(){
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 10\n".to_vec();
+        let bright = vec![float_to_rgbe(Vec3d::new(4., 2., 1.)); 10];
+        data.extend(encode_rgbe_scanline(&bright));
+        //flat scanline with an old style run
+        data.extend([128, 128, 128, 129]);
+        data.extend([1, 1, 1, 9]);
+        let image = Image::decode(&data, ColorSpace::Srgb).unwrap();
+        assert!((image.pixel_color(0, 9) - Vec3d::new(4., 2., 1.)).length() < 0.05);
+        assert!((image.pixel_color(1, 9) - Vec3d::new(1., 1., 1.)).length() < 0.01);
+        assert!(Image::decode(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0", ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn test_decode_pfm_and_png(){
+        let mut image = Image::new(2, 3);
+        image.set_pixels((0..6).map(|i| Vec3d::new(i as f64 / 5., 0.5, 1. - i as f64 / 5.)).collect());
+        let mut data = b"PF\n3 2\n-1.0\n".to_vec();
+        for row in (0..2).rev() {
+            for col in 0..3 {
+                let color = image.pixel_color(row, col);
+                data.extend([color.x, color.y, color.z].iter().flat_map(|&c| (c as f32).to_le_bytes()));
+            }
+        }
+        let decoded = Image::decode(&data, ColorSpace::Srgb).unwrap();
+        assert!((decoded.pixel_color(1, 2) - image.pixel_color(1, 2)).length() < 1e-6);
+
+        //srgb encoded by the writer and decoded back to linear
+        for bit_depth in [PngBitDepth::Eight, PngBitDepth::Sixteen] {
+            let decoded = Image::decode(&png::encode(&image, bit_depth, &[]), ColorSpace::Srgb).unwrap();
+            for (row, col) in (0..2).cartesian_product(0..3) {
+                assert!((decoded.pixel_color(row, col) - image.pixel_color(row, col)).length() < 0.01);
+            }
+        }
+        assert!(Image::decode(b"GIF89a", ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn test_quantisation_clips(){
+        let mut image = Image::new(1, 2);
+        image.set_pixels(vec![Vec3d::new(0.25, 4., -1.), Vec3d::new(1., 1., 1.)]);
+        let pixel = image.pixel_u8(0, 0);
+        assert_eq!((137, 255, 0), (pixel.r, pixel.g, pixel.b));
+        //radiance above 1 is still available in the float buffer
+        assert_eq!(4., image.pixel_color(0, 0).y);
+    }
+
+    #[test]
+    fn test_display_transform(){
+        let mut image = Image::new(1, 1);
+        image.set_pixels(vec![Vec3d::new(0.5, 0.5, 0.5)]);
+        image.set_display_transform(DisplayTransform{ exposure: 1., tone_mapping: ToneMapping::Reinhard });
+        let pixel = image.pixel_u8(0, 0);
+        //exposed to 1, mapped to 0.5, srgb encoded
+        assert_eq!((188, 188, 188), (pixel.r, pixel.g, pixel.b));
+    }
+}
@@ -0,0 +1,178 @@
+use std::ops::Range;
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::transform::{AnimatedTransform, Transform};
+
+//shared object placed in the world by a transform, the object itself stays in its own space
+//a bvh over instances of objects with their own bvh makes a two-level hierarchy, the geometry is stored once
+pub(crate) struct Instance {
+    object: Arc<dyn Hittable + Send + Sync>,
+    //object to world space
+    transform: Transform,
+    //replaces the transform for moving instances, boxed to keep still instances small
+    motion: Option<Box<AnimatedTransform>>,
+    bbox: Aabb,
+    //replaces the materials of the object
+    material: Option<Arc<Material>>,
+}
+
+impl Instance {
+    pub(crate) fn new(object: Arc<dyn Hittable + Send + Sync>, transform: Transform) -> Self {
+        let bbox = transform.bounding_box(&object.bounding_box());
+        Self{
+            object,
+            transform,
+            motion: None,
+            bbox,
+            material: None,
+        }
+    }
+
+    //placed by the transform at the time of each ray, the bounding box covers the whole motion
+    pub(crate) fn moving(object: Arc<dyn Hittable + Send + Sync>, motion: AnimatedTransform) -> Self {
+        let bbox = motion.bounding_box(&object.bounding_box());
+        Self{
+            object,
+            transform: motion.at(0.),
+            motion: Some(Box::new(motion)),
+            bbox,
+            material: None,
+        }
+    }
+
+    pub(crate) fn with_material(mut self, material: Arc<Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let transform = match &self.motion {
+            Some(motion) => motion.at(ray.time),
+            None => self.transform,
+        };
+        //the direction is not normalized after the transform so t is the same in both spaces
+        let inverse = transform.inverse();
+        let local = Ray::new(inverse.point(ray.origin), inverse.vector(ray.direction_no_unit)).with_time(ray.time);
+        let mut rec = self.object.hit(&local, interval)?;
+        //the normal already faces against the ray, the inverse transpose keeps the side
+        rec.pos = transform.point(rec.pos);
+        rec.normal = transform.normal(rec.normal).unit();
+        if let Some(material) = &self.material {
+            rec.material = material.clone();
+        }
+        return Some(rec);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return self.bbox;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::bvh::Bvh;
+    use crate::quad::Cuboid;
+    use crate::transform::Keyframe;
+    use crate::sphere::Sphere;
+    use crate::vec3d::Vec3d;
+
+    #[test]
+    fn test_scaled_and_moved_sphere(){
+        let material = Arc::new(Material::builder().build());
+        let sphere: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3d::zero(), 1., material));
+        let transform = Transform::translation(Vec3d::new(0., 0., -5.)) * Transform::scaling(Vec3d::new(1., 2., 1.));
+        let instance = Instance::new(sphere.clone(), transform);
+        //the sphere became an ellipsoid twice as tall
+        let rec = instance.hit(&Ray::new(Vec3d::new(0., 10., -5.), Vec3d::new(0., -2., 0.)), 0.001..f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-12);
+        assert!((rec.pos - Vec3d::new(0., 2., -5.)).length() < 1e-12);
+        assert!((rec.normal - Vec3d::up()).length() < 1e-12 && rec.front_face);
+        //normals of the stretched side lean towards the pole
+        let side = instance.hit(&Ray::new(Vec3d::new(10., 1., -5.), Vec3d::left()), 0.001..f64::INFINITY).unwrap();
+        assert!(side.normal.y > 0. && (side.normal.length() - 1.).abs() < 1e-12);
+        let bbox = instance.bounding_box();
+        assert!((bbox.max - Vec3d::new(1., 2., -4.)).length() < 1e-9 && (bbox.min - Vec3d::new(-1., -2., -6.)).length() < 1e-9);
+        assert!(instance.hit(&Ray::new(Vec3d::zero(), Vec3d::up()), 0.001..f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_rotated_box(){
+        let material = Arc::new(Material::builder().build());
+        let cuboid = Arc::new(Cuboid::new(Vec3d::new(-1., -1., -1.), Vec3d::new(1., 1., 1.), material));
+        let instance = Instance::new(cuboid, Transform::rotation(Vec3d::up(), 45.));
+        //the edge of the box points along x after a quarter turn
+        let rec = instance.hit(&Ray::new(Vec3d::new(5., 0., 0.), Vec3d::left()), 0.001..f64::INFINITY).unwrap();
+        assert!((rec.t - (5. - 2f64.sqrt())).abs() < 1e-9);
+        //from inside the back face is hit
+        let inside = instance.hit(&Ray::new(Vec3d::zero(), Vec3d::up()), 0.001..f64::INFINITY).unwrap();
+        assert!(!inside.front_face && (inside.normal - Vec3d::down()).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_moving_instance(){
+        let material = Arc::new(Material::builder().build());
+        let sphere: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3d::zero(), 0.5, material));
+        let start = Keyframe{
+            translation: Vec3d::new(-3., 0., -5.),
+            ..Keyframe::identity()
+        };
+        let end = Keyframe{
+            translation: Vec3d::new(3., 0., -5.),
+            ..start
+        };
+        let instance = Instance::moving(sphere, AnimatedTransform::new(start, end));
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        assert!(instance.hit(&ray, 0.001..f64::INFINITY).is_none());
+        let rec = instance.hit(&ray.with_time(0.5), 0.001..f64::INFINITY).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-12);
+        let bbox = instance.bounding_box();
+        assert!(bbox.min.x <= -3.5 && bbox.max.x >= 3.5 && bbox.max.x < 3.6);
+    }
+
+    #[test]
+    fn test_two_level_bvh(){
+        let mut rng = StdRng::from_seed([4; 32]);
+        let material = Arc::new(Material::builder().build());
+        let spheres: Vec<Box<dyn Hittable + Send + Sync>> = (0..20).map(|_| {
+            let center = Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            Box::new(Sphere::new(center, rng.gen_range(0.05..0.2), material.clone())) as Box<dyn Hittable + Send + Sync>
+        }).collect();
+        let cluster: Arc<dyn Hittable + Send + Sync> = Arc::new(Bvh::new(spheres));
+        let red = Arc::new(Material::builder().albedo(Vec3d::new(1., 0., 0.), 0.3).build());
+        let instances: Vec<Box<dyn Hittable + Send + Sync>> = (0..500).map(|index| {
+            let transform = Transform::translation(Vec3d::new(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0)))
+                * Transform::rotation(Vec3d::new(rng.gen_range(-1.0..1.0), 1., 0.), rng.gen_range(0.0..360.0))
+                * Transform::scaling(Vec3d::new(1., 1., 1.) * rng.gen_range(0.5..2.0));
+            let instance = Instance::new(cluster.clone(), transform);
+            let instance = if index % 2 == 0 { instance.with_material(red.clone()) } else { instance };
+            Box::new(instance) as Box<dyn Hittable + Send + Sync>
+        }).collect();
+        //every instance shares the bottom-level bvh
+        assert_eq!(501, Arc::strong_count(&cluster));
+
+        let linear = &instances;
+        let mut found_red = false;
+        let rays: Vec<Ray> = (0..300).map(|_| Ray::new(
+            Vec3d::new(rng.gen_range(-25.0..25.0), rng.gen_range(-25.0..25.0), rng.gen_range(-25.0..25.0)),
+            Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)),
+        )).collect();
+        let expected: Vec<Option<(f64, bool)>> = rays.iter()
+            .map(|ray| linear.hit(ray, 0.001..f64::INFINITY).map(|rec| (rec.t, Arc::ptr_eq(&rec.material, &red))))
+            .collect();
+        let top_level = Bvh::new(instances);
+        for (ray, expected) in rays.iter().zip(expected) {
+            let actual = top_level.hit(ray, 0.001..f64::INFINITY).map(|rec| (rec.t, Arc::ptr_eq(&rec.material, &red)));
+            assert_eq!(expected, actual);
+            found_red |= actual.is_some_and(|(_, red)| red);
+        }
+        assert!(found_red);
+    }
+}
@@ -0,0 +1,465 @@
+use std::f64::consts::PI;
+use rand::Rng;
+use crate::vec3d::Vec3d;
+
+//light sources that can be sampled directly for next event estimation
+
+pub(crate) struct LightSample {
+    //unit vector from the shaded point towards the light
+    pub(crate) direction: Vec3d,
+    //distance to the sampled point, infinite for lights at infinity
+    pub(crate) distance: f64,
+    pub(crate) radiance: Vec3d,
+    //solid angle density of the direction, for delta lights 1 and radiance is the irradiance they cause
+    pub(crate) pdf: f64,
+    //point, spot and directional lights can only be reached by shadow rays, not by bsdf sampling
+    pub(crate) delta: bool,
+}
+
+pub(crate) trait Light {
+    fn sample(&self, origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample>;
+
+    //solid angle density of sampling the point at distance along the unit direction, 0 if the point is not on this light
+    fn pdf(&self, origin: Vec3d, direction: Vec3d, distance: f64) -> f64;
+
+    //radiance seen by rays that escape the scene along the unit direction, None for lights that are not at infinity
+    fn background(&self, _direction: Vec3d) -> Option<Vec3d> {
+        return None;
+    }
+}
+
+//uniform direction inside the cone around the unit axis, one_minus_cos_max is 1 - cos of its half angle
+pub(crate) fn sample_cone(axis: Vec3d, one_minus_cos_max: f64, rng: &mut dyn rand::RngCore) -> Vec3d {
+    let one_minus_cos = rng.gen::<f64>() * one_minus_cos_max;
+    let cos_theta = 1. - one_minus_cos;
+    let sin_theta = f64::sqrt((one_minus_cos * (2. - one_minus_cos)).max(0.));
+    let phi = 2. * PI * rng.gen::<f64>();
+    let (tangent, bitangent) = axis.orthonormal_basis();
+    return (sin_theta * phi.cos()) * tangent + (sin_theta * phi.sin()) * bitangent + cos_theta * axis;
+}
+
+//emissive sphere, sampled uniformly inside the cone of directions it covers
+pub(crate) struct SphereLight {
+    center: Vec3d,
+    radius: f64,
+    radiance: Vec3d,
+}
+
+impl SphereLight {
+    pub(crate) fn new(center: Vec3d, radius: f64, radiance: Vec3d) -> Self {
+        Self{
+            center,
+            radius: radius.abs(),
+            radiance,
+        }
+    }
+
+    //1 - cos of the half angle of the cone, None inside the sphere
+    fn cone(&self, origin: Vec3d) -> Option<f64> {
+        let distance_squared = (self.center - origin).length_squared();
+        let sin_squared = self.radius * self.radius / distance_squared;
+        if sin_squared >= 1. {
+            return None;
+        }
+        let cos_max = f64::sqrt(1. - sin_squared);
+        //1 - cos_max without cancellation for small cones
+        return Some(sin_squared / (1. + cos_max));
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        let one_minus_cos_max = self.cone(origin)?;
+        let to_center = self.center - origin;
+        let center_distance = to_center.length();
+        let axis = to_center / center_distance;
+
+        let direction = sample_cone(axis, one_minus_cos_max, rng);
+        let cos_theta = direction.dot(&axis);
+        let sin_theta = f64::sqrt((1. - cos_theta * cos_theta).max(0.));
+
+        //nearest intersection of the direction with the sphere
+        let half_chord_squared = self.radius * self.radius - center_distance * center_distance * sin_theta * sin_theta;
+        let distance = center_distance * cos_theta - f64::sqrt(half_chord_squared.max(0.));
+        return Some(LightSample{
+            direction,
+            distance,
+            radiance: self.radiance,
+            pdf: 1. / (2. * PI * one_minus_cos_max),
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, origin: Vec3d, direction: Vec3d, distance: f64) -> f64 {
+        if !distance.is_finite() {
+            return 0.;
+        }
+        let point = origin + direction * distance;
+        if ((point - self.center).length() - self.radius).abs() > 1e-6 * self.radius.max(1.) {
+            return 0.;
+        }
+        return match self.cone(origin) {
+            Some(one_minus_cos_max) => 1. / (2. * PI * one_minus_cos_max),
+            None => 0.,
+        };
+    }
+}
+
+//emissive parallelogram, sampled uniformly by area, emits from both sides like the quads it belongs to
+pub(crate) struct QuadLight {
+    corner: Vec3d,
+    u: Vec3d,
+    v: Vec3d,
+    unit_normal: Vec3d,
+    area: f64,
+    radiance: Vec3d,
+}
+
+impl QuadLight {
+    pub(crate) fn new(corner: Vec3d, u: Vec3d, v: Vec3d, radiance: Vec3d) -> Self {
+        let n = u.cross(v);
+        Self{
+            corner,
+            u,
+            v,
+            unit_normal: n.unit(),
+            area: n.length(),
+            radiance,
+        }
+    }
+
+    //area density converted to solid angle, 0 seen edge-on
+    fn solid_angle_pdf(&self, direction: Vec3d, distance: f64) -> f64 {
+        let cos_theta = self.unit_normal.dot(&direction).abs();
+        if cos_theta < 1e-9 {
+            return 0.;
+        }
+        return distance * distance / (cos_theta * self.area);
+    }
+}
+
+impl Light for QuadLight {
+    fn sample(&self, origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        let point = self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        let to_point = point - origin;
+        let distance = to_point.length();
+        let direction = to_point / distance;
+        let pdf = self.solid_angle_pdf(direction, distance);
+        if pdf == 0. || !pdf.is_finite() {
+            return None;
+        }
+        return Some(LightSample{
+            direction,
+            distance,
+            radiance: self.radiance,
+            pdf,
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, origin: Vec3d, direction: Vec3d, distance: f64) -> f64 {
+        if !distance.is_finite() {
+            return 0.;
+        }
+        let planar = origin + direction * distance - self.corner;
+        let scale = self.u.length().max(self.v.length()).max(1.);
+        if self.unit_normal.dot(&planar).abs() > 1e-6 * scale {
+            return 0.;
+        }
+        //coordinates along the edges, which need not be orthogonal
+        let n = self.u.cross(self.v);
+        let w = n / n.length_squared();
+        let alpha = w.dot(&planar.cross(self.v));
+        let beta = w.dot(&self.u.cross(planar));
+        if !(-1e-9..=1. + 1e-9).contains(&alpha) || !(-1e-9..=1. + 1e-9).contains(&beta) {
+            return 0.;
+        }
+        return self.solid_angle_pdf(direction, distance);
+    }
+}
+
+//isotropic point light, intensity in candela
+pub(crate) struct PointLight {
+    position: Vec3d,
+    intensity: Vec3d,
+}
+
+impl PointLight {
+    pub(crate) fn new(position: Vec3d, intensity: Vec3d) -> Self {
+        Self{
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, origin: Vec3d, _rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        return delta_sample(origin, self.position, self.intensity);
+    }
+
+    fn pdf(&self, _origin: Vec3d, _direction: Vec3d, _distance: f64) -> f64 {
+        return 0.;
+    }
+}
+
+//point light shining into a cone, full intensity inside the inner angle and fading out smoothly towards the outer angle
+pub(crate) struct SpotLight {
+    position: Vec3d,
+    //unit axis of the cone, pointing away from the light
+    direction: Vec3d,
+    cos_inner: f64,
+    cos_outer: f64,
+    intensity: Vec3d,
+}
+
+impl SpotLight {
+    //half angles of the cones in degrees
+    pub(crate) fn new(position: Vec3d, direction: Vec3d, inner_angle: f64, outer_angle: f64, intensity: Vec3d) -> Self {
+        Self{
+            position,
+            direction: direction.unit(),
+            cos_inner: inner_angle.min(outer_angle).to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+            intensity,
+        }
+    }
+
+    //intensity of a spot light that emits power in total, falloff region counted as half
+    pub(crate) fn intensity_from_power(power: f64, inner_angle: f64, outer_angle: f64) -> f64 {
+        let cos_inner = inner_angle.min(outer_angle).to_radians().cos();
+        let cos_outer = outer_angle.to_radians().cos();
+        return power / (2. * PI * (1. - 0.5 * (cos_inner + cos_outer)));
+    }
+
+    //0 outside the outer cone to 1 inside the inner cone
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            return 1.;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        return t * t * (3. - 2. * t);
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, origin: Vec3d, _rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        let falloff = self.falloff((origin - self.position).unit().dot(&self.direction));
+        if falloff == 0. {
+            return None;
+        }
+        return delta_sample(origin, self.position, falloff * self.intensity);
+    }
+
+    fn pdf(&self, _origin: Vec3d, _direction: Vec3d, _distance: f64) -> f64 {
+        return 0.;
+    }
+}
+
+//light from a single direction at infinity like a distant sun, illuminance in lux on a surface facing it
+pub(crate) struct DirectionalLight {
+    //unit direction the light travels in
+    direction: Vec3d,
+    illuminance: Vec3d,
+}
+
+impl DirectionalLight {
+    pub(crate) fn new(direction: Vec3d, illuminance: Vec3d) -> Self {
+        Self{
+            direction: direction.unit(),
+            illuminance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _origin: Vec3d, _rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        return Some(LightSample{
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            radiance: self.illuminance,
+            pdf: 1.,
+            delta: true,
+        });
+    }
+
+    fn pdf(&self, _origin: Vec3d, _direction: Vec3d, _distance: f64) -> f64 {
+        return 0.;
+    }
+}
+
+//irradiance of a point source with the given intensity, falling off with the squared distance
+fn delta_sample(origin: Vec3d, position: Vec3d, intensity: Vec3d) -> Option<LightSample> {
+    let to_light = position - origin;
+    let distance_squared = to_light.length_squared();
+    if distance_squared == 0. {
+        return None;
+    }
+    let distance = distance_squared.sqrt();
+    return Some(LightSample{
+        direction: to_light / distance,
+        distance,
+        radiance: intensity / distance_squared,
+        pdf: 1.,
+        delta: true,
+    });
+}
+
+//lights of a scene, one is picked uniformly per sample
+pub(crate) struct LightList {
+    lights: Vec<Box<dyn Light + Sync>>,
+}
+
+impl LightList {
+    pub(crate) fn new(lights: Vec<Box<dyn Light + Sync>>) -> Self {
+        Self{
+            lights,
+        }
+    }
+
+    //the pdf of the sample includes the probability of picking the light
+    pub(crate) fn sample(&self, origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let light = &self.lights[rng.gen_range(0..self.lights.len())];
+        let mut sample = light.sample(origin, rng)?;
+        sample.pdf /= self.lights.len() as f64;
+        return Some(sample);
+    }
+
+    pub(crate) fn pdf(&self, origin: Vec3d, direction: Vec3d, distance: f64) -> f64 {
+        if self.lights.is_empty() {
+            return 0.;
+        }
+        let sum = self.lights.iter().map(|light| light.pdf(origin, direction, distance)).sum::<f64>();
+        return sum / self.lights.len() as f64;
+    }
+
+    //radiance of the lights at infinity seen by a ray escaping along the unit direction, None without such lights
+    //bsdf_pdf is the solid angle density the direction was sampled with, None for camera rays and specular bounces
+    pub(crate) fn background(&self, direction: Vec3d, bsdf_pdf: Option<f64>) -> Option<Vec3d> {
+        let mut radiance = None;
+        for light in &self.lights {
+            if let Some(light_radiance) = light.background(direction) {
+                //every light is weighted against bsdf sampling with its own density, like next event estimation does
+                let weight = match bsdf_pdf {
+                    Some(pdf) if light_radiance != Vec3d::zero() => {
+                        power_heuristic(pdf, light.pdf(Vec3d::zero(), direction, f64::INFINITY) / self.lights.len() as f64)
+                    }
+                    _ => 1.,
+                };
+                radiance = Some(radiance.unwrap_or(Vec3d::zero()) + weight * light_radiance);
+            }
+        }
+        return radiance;
+    }
+}
+
+//weight of a strategy with density pdf against another with density other_pdf, both taking one sample
+pub(crate) fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0. {
+        return 0.;
+    }
+    return a / (a + b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_sphere_samples_lie_on_the_sphere(){
+        let light = SphereLight::new(Vec3d::new(0., 3., 0.), 1., Vec3d::new(1., 1., 1.));
+        let mut rng = StdRng::from_seed([7; 32]);
+        for _ in 0..1000 {
+            let sample = light.sample(Vec3d::zero(), &mut rng).unwrap();
+            let point = sample.direction * sample.distance;
+            assert!(((point - Vec3d::new(0., 3., 0.)).length() - 1.).abs() < 1e-9);
+            assert!((light.pdf(Vec3d::zero(), sample.direction, sample.distance) - sample.pdf).abs() < 1e-9);
+        }
+        //solid angle of the cone
+        let expected = 2. * PI * (1. - f64::sqrt(8. / 9.));
+        let sample = light.sample(Vec3d::zero(), &mut rng).unwrap();
+        assert!((1. / sample.pdf - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_samples_inside(){
+        let light = SphereLight::new(Vec3d::zero(), 2., Vec3d::new(1., 1., 1.));
+        let mut rng = StdRng::from_seed([7; 32]);
+        assert!(light.sample(Vec3d::new(0.5, 0., 0.), &mut rng).is_none());
+        assert_eq!(0., light.pdf(Vec3d::new(0.5, 0., 0.), Vec3d::up(), 1.5));
+    }
+
+    #[test]
+    fn test_quad_light(){
+        //a 2 by 1 rectangle one unit above the origin, edges not in the order of the normal
+        let light = QuadLight::new(Vec3d::new(-1., 1., -0.5), Vec3d::new(0., 0., 1.), Vec3d::new(2., 0., 0.), Vec3d::new(1., 1., 1.));
+        let mut rng = StdRng::from_seed([7; 32]);
+        let n = 100000;
+        let mut solid_angle = 0.;
+        for _ in 0..n {
+            let sample = light.sample(Vec3d::zero(), &mut rng).unwrap();
+            assert!((sample.direction * sample.distance).y - 1. < 1e-12);
+            assert!((light.pdf(Vec3d::zero(), sample.direction, sample.distance) - sample.pdf).abs() < 1e-9 * sample.pdf);
+            solid_angle += 1. / sample.pdf / n as f64;
+        }
+        let expected = 4. * f64::asin(2. / f64::sqrt((4. + 4.) * (1. + 4.)));
+        assert!((solid_angle - expected).abs() < 0.01 * expected, "solid angle {}, expected {}", solid_angle, expected);
+        //from above, through the other side
+        let sample = light.sample(Vec3d::new(0., 3., 0.), &mut rng).unwrap();
+        assert!(sample.direction.y < 0.);
+        //beside the rectangle and off its plane
+        assert_eq!(0., light.pdf(Vec3d::zero(), Vec3d::new(2., 1., 0.).unit(), 5f64.sqrt()));
+        assert_eq!(0., light.pdf(Vec3d::zero(), Vec3d::up(), 2.));
+        //in its plane nothing is sampled
+        assert!(light.sample(Vec3d::new(5., 1., 0.), &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_list_pdf(){
+        let lights = LightList::new(vec![
+            Box::new(SphereLight::new(Vec3d::new(0., 3., 0.), 1., Vec3d::new(1., 1., 1.))),
+            Box::new(SphereLight::new(Vec3d::new(0., -3., 0.), 1., Vec3d::new(1., 1., 1.))),
+        ]);
+        let single = SphereLight::new(Vec3d::new(0., 3., 0.), 1., Vec3d::new(1., 1., 1.));
+        assert!((lights.pdf(Vec3d::zero(), Vec3d::up(), 2.) - single.pdf(Vec3d::zero(), Vec3d::up(), 2.) / 2.).abs() < 1e-12);
+        //a point that is on neither light
+        assert_eq!(0., lights.pdf(Vec3d::zero(), Vec3d::right(), 2.));
+    }
+
+    #[test]
+    fn test_delta_lights(){
+        let mut rng = StdRng::from_seed([7; 32]);
+        let point = PointLight::new(Vec3d::new(0., 2., 0.), Vec3d::new(8., 8., 8.));
+        let sample = point.sample(Vec3d::zero(), &mut rng).unwrap();
+        assert_eq!((Vec3d::up(), 2., Vec3d::new(2., 2., 2.), true), (sample.direction, sample.distance, sample.radiance, sample.delta));
+        assert_eq!(0., point.pdf(Vec3d::zero(), Vec3d::up(), 2.));
+
+        let spot = SpotLight::new(Vec3d::new(0., 1., 0.), Vec3d::down(), 30., 45., Vec3d::new(1., 1., 1.));
+        assert!((spot.sample(Vec3d::new(0.5, 0., 0.), &mut rng).unwrap().radiance.x * 1.25 - 1.).abs() < 1e-12);
+        assert!(spot.sample(Vec3d::new(1.1, 0., 0.), &mut rng).is_none());
+        let between = spot.sample(Vec3d::new(0.8, 0., 0.), &mut rng).unwrap().radiance.x * 1.64;
+        assert!(between > 0. && between < 1.);
+        //a cone with the full half angle of 180 degrees is a point light
+        assert!((SpotLight::intensity_from_power(4. * PI, 180., 180.) - 1.).abs() < 1e-12);
+
+        let directional = DirectionalLight::new(Vec3d::new(0., -2., 0.), Vec3d::new(3., 3., 3.));
+        let sample = directional.sample(Vec3d::zero(), &mut rng).unwrap();
+        assert_eq!((Vec3d::up(), f64::INFINITY, 3.), (sample.direction, sample.distance, sample.radiance.y));
+    }
+
+    #[test]
+    fn test_power_heuristic(){
+        assert_eq!(0.5, power_heuristic(2., 2.));
+        assert_eq!(1., power_heuristic(1., 0.));
+        assert!((power_heuristic(1., 3.) - 0.1).abs() < 1e-12);
+    }
+}
@@ -1,41 +1,94 @@
+use std::sync::Arc;
+use crate::bsdf::{Bsdf, ConductorFresnel, Lambertian, MixtureBsdf};
+use crate::microfacet;
+use crate::principled::{PrincipledBsdf, PrincipledParameters};
+use crate::texture::Texture;
 use crate::ray::Ray;
 use crate::vec3d::Vec3d;
 
 pub(crate) struct Material{
-    pub albedo_color : Vec3d,
-    pub smoothness: f64,
-    pub reflectivity: f64,
-    pub absorption: f64,
-    pub reflection_fuzz: f64,
-    pub(crate) refractioness: f64,
-    pub refraction_index: f64,
     pub(crate) emission_color: Vec3d,
     pub(crate) emission_intensity: f64,
+    bsdf: Box<dyn Bsdf + Send + Sync>,
 }
 
 impl Material {
     pub(crate) fn builder() -> MaterialBuilder {
         MaterialBuilder{
             albedo_color: Vec3d::new(0.5,0.,0.5),
-            smoothness: 0.,
+            albedo_texture: None,
+            roughness: 0.,
             reflectivity: 0.,
             absorption: 0.,
-            reflection_fuzz: 0.,
+            reflection_fresnel: ConductorFresnel::Schlick(Vec3d::new(1.,1.,1.)),
             refractioness: 0.,
             refraction_index: 1.,
             emission_color: Vec3d::new(1.,1.,1.),
             emission_intensity: 0.0,
         }
     }
+
+    //base color, metallic, roughness, ... instead of lobe weights
+    pub(crate) fn principled() -> PrincipledBuilder {
+        PrincipledBuilder{
+            parameters: PrincipledParameters::default(),
+            base_color_texture: None,
+            emission_color: Vec3d::new(1.,1.,1.),
+            emission_intensity: 0.,
+        }
+    }
+
+    //emitted radiance
+    pub(crate) fn emitted(&self) -> Vec3d {
+        return self.emission_color * self.emission_intensity;
+    }
+
+    pub(crate) fn bsdf(&self) -> &dyn Bsdf {
+        return self.bsdf.as_ref();
+    }
+
+    //same emission and same scattering for a few fixed directions and random numbers, lets tests
+    //check parsed materials against the builder calls they should amount to
+    #[cfg(test)]
+    pub(crate) fn scatters_like(&self, other: &Material) -> bool {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        if self.emitted() != other.emitted() {
+            return false;
+        }
+        let hit = crate::hit::HitRecord::with_unit_normal(Vec3d::zero(), Vec3d::up(), 1., Vec3d::down(), Arc::new(Material::builder().build()));
+        let directions = [Vec3d::up(), Vec3d::new(1., 1., 0.).unit(), Vec3d::new(-1., 2., 0.5).unit(), Vec3d::new(0.3, -1., 0.2).unit()];
+        for wo in directions {
+            for wi in directions {
+                if self.bsdf.eval(&hit, wo, wi) != other.bsdf.eval(&hit, wo, wi) || self.bsdf.pdf(&hit, wo, wi) != other.bsdf.pdf(&hit, wo, wi) {
+                    return false;
+                }
+            }
+            for seed in 0..8 {
+                let sample = self.bsdf.sample(&hit, wo, &mut StdRng::from_seed([seed; 32]));
+                let other_sample = other.bsdf.sample(&hit, wo, &mut StdRng::from_seed([seed; 32]));
+                let same = match (sample, other_sample) {
+                    (Some(a), Some(b)) => a.direction == b.direction && a.weight == b.weight && a.pdf == b.pdf && a.specular == b.specular,
+                    (None, None) => true,
+                    _ => false,
+                };
+                if !same {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
 }
 
 
 pub(crate) struct MaterialBuilder{
     albedo_color : Vec3d,
-    smoothness: f64,
+    albedo_texture: Option<Arc<dyn Texture + Send + Sync>>,
+    roughness: f64,
     reflectivity: f64,
     absorption: f64,
-    reflection_fuzz: f64,
+    reflection_fresnel: ConductorFresnel,
     refractioness: f64,
     refraction_index: f64,
     emission_color:Vec3d,
@@ -44,20 +97,6 @@ pub(crate) struct MaterialBuilder{
 
 impl MaterialBuilder {
 
-    pub(crate) fn new() -> MaterialBuilder {
-        MaterialBuilder{
-            albedo_color: Vec3d::new(0.5,0.,0.5),
-            smoothness: 0.,
-            reflectivity: 0.,
-            absorption: 0.,
-            reflection_fuzz: 0.,
-            refractioness: 0.,
-            refraction_index: 1.,
-            emission_color: Vec3d::new(1.,1.,1.),
-            emission_intensity: 0.,
-        }
-    }
-
     pub(crate) fn albedo(mut self, color: Vec3d, absorption: f64) -> MaterialBuilder {
         self.albedo_color = color;
         let absorption_clamped = absorption.clamp(0.,1.);
@@ -65,17 +104,32 @@ impl MaterialBuilder {
         self
     }
 
-    pub(crate) fn smoothness(mut self, smoothness: f64) -> MaterialBuilder {
-        let smoothness_clamped = smoothness.clamp(0.,1.);
-        self.smoothness = smoothness_clamped;
+    //replaces the albedo color
+    pub(crate) fn albedo_texture(mut self, texture: Arc<dyn Texture + Send + Sync>) -> MaterialBuilder {
+        self.albedo_texture = Some(texture);
         self
     }
 
-    pub(crate) fn reflection(mut self, reflectivity: f64, reflection_fuzz: f64) -> MaterialBuilder {
+    //microfacet roughness shared by the metal and the glass lobe, both blur alike, 0 is perfectly smooth
+    pub(crate) fn roughness(mut self, roughness: f64) -> MaterialBuilder {
+        let roughness_clamped = roughness.clamp(0.,1.);
+        self.roughness = roughness_clamped;
+        self
+    }
+
+    pub(crate) fn reflection(mut self, reflectivity: f64, roughness: f64) -> MaterialBuilder {
         let reflectivity_clamped = reflectivity.clamp(0.,1.);
         self.reflectivity = reflectivity_clamped;
-        let reflection_fuzz_clamped = reflection_fuzz.clamp(0.,1.);
-        self.reflection_fuzz = reflection_fuzz_clamped;
+        self.roughness(roughness)
+    }
+
+    //reflectance of the metal at normal incidence, white by default
+    pub(crate) fn reflection_color(self, color: Vec3d) -> MaterialBuilder {
+        self.reflection_fresnel(ConductorFresnel::Schlick(color))
+    }
+
+    pub(crate) fn reflection_fresnel(mut self, fresnel: ConductorFresnel) -> MaterialBuilder {
+        self.reflection_fresnel = fresnel;
         self
     }
 
@@ -91,17 +145,99 @@ impl MaterialBuilder {
         self
     }
 
+    //reflectivity, refractioness and absorption are the relative weights of the metal, glass and diffuse lobes
+    fn build_bsdf(&self) -> Box<dyn Bsdf + Send + Sync> {
+        let sum = self.reflectivity + self.refractioness + self.absorption;
+        if sum <= 0. {
+            return Box::new(MixtureBsdf::new(Vec::new()));
+        }
+        let white = Vec3d::new(1., 1., 1.);
+        return Box::new(MixtureBsdf::new(vec![
+            (self.reflectivity / sum, microfacet::conductor(self.roughness, self.reflection_fresnel, self.reflectivity * white)),
+            (self.refractioness / sum, microfacet::dielectric(self.roughness, self.refraction_index, (1. - self.absorption) * white)),
+            (self.absorption / sum, Box::new(match &self.albedo_texture {
+                Some(texture) => Lambertian::textured(texture.clone(), 1. - self.absorption),
+                None => Lambertian::new((1. - self.absorption) * self.albedo_color),
+            })),
+        ]));
+    }
+
     pub(crate) fn build(self) -> Material {
         Material{
-            albedo_color: self.albedo_color,
-            smoothness: self.smoothness,
-            reflectivity: self.reflectivity,
-            absorption: self.absorption,
-            reflection_fuzz: self.reflection_fuzz,
-            refractioness: self.refractioness,
-            refraction_index: self.refraction_index,
+            bsdf: self.build_bsdf(),
             emission_color: self.emission_color,
             emission_intensity: self.emission_intensity,
         }
     }
-}
\ No newline at end of file
+}
+
+pub(crate) struct PrincipledBuilder{
+    parameters: PrincipledParameters,
+    base_color_texture: Option<Arc<dyn Texture + Send + Sync>>,
+    emission_color: Vec3d,
+    emission_intensity: f64,
+}
+
+impl PrincipledBuilder {
+    pub(crate) fn base_color(mut self, color: Vec3d) -> PrincipledBuilder {
+        self.parameters.base_color = color;
+        self
+    }
+
+    //colors the diffuse layer, so only for materials without metallic and transmission
+    pub(crate) fn base_color_texture(mut self, texture: Arc<dyn Texture + Send + Sync>) -> PrincipledBuilder {
+        self.base_color_texture = Some(texture);
+        self
+    }
+
+    pub(crate) fn metallic(mut self, metallic: f64) -> PrincipledBuilder {
+        self.parameters.metallic = metallic.clamp(0.,1.);
+        self
+    }
+
+    pub(crate) fn roughness(mut self, roughness: f64) -> PrincipledBuilder {
+        self.parameters.roughness = roughness.clamp(0.,1.);
+        self
+    }
+
+    pub(crate) fn specular(mut self, specular: f64, specular_tint: f64) -> PrincipledBuilder {
+        self.parameters.specular = specular.clamp(0.,1.);
+        self.parameters.specular_tint = specular_tint.clamp(0.,1.);
+        self
+    }
+
+    pub(crate) fn sheen(mut self, sheen: f64, sheen_tint: f64) -> PrincipledBuilder {
+        self.parameters.sheen = sheen.clamp(0.,1.);
+        self.parameters.sheen_tint = sheen_tint.clamp(0.,1.);
+        self
+    }
+
+    pub(crate) fn clearcoat(mut self, clearcoat: f64, clearcoat_roughness: f64) -> PrincipledBuilder {
+        self.parameters.clearcoat = clearcoat.clamp(0.,1.);
+        self.parameters.clearcoat_roughness = clearcoat_roughness.clamp(0.,1.);
+        self
+    }
+
+    pub(crate) fn transmission(mut self, transmission: f64, refraction_index: f64) -> PrincipledBuilder {
+        self.parameters.transmission = transmission.clamp(0.,1.);
+        self.parameters.refraction_index = refraction_index;
+        self
+    }
+
+    pub(crate) fn emission(mut self, emission_color: Vec3d, emission_intensity: f64) -> PrincipledBuilder {
+        self.emission_color = emission_color;
+        self.emission_intensity = emission_intensity;
+        self
+    }
+
+    pub(crate) fn build(self) -> Material {
+        //the specular and glass layers would ignore the texture
+        assert!(self.base_color_texture.is_none() || (self.parameters.metallic == 0. && self.parameters.transmission == 0.),
+            "base_color_texture cannot be combined with metallic or transmission");
+        Material{
+            bsdf: Box::new(PrincipledBsdf::new(&self.parameters, self.base_color_texture)),
+            emission_color: self.emission_color,
+            emission_intensity: self.emission_intensity,
+        }
+    }
+}
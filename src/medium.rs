@@ -0,0 +1,478 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use rand::Rng;
+use crate::hit::Hittable;
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+use crate::world::SHADOW_EPSILON;
+
+//participating media like fog and smoke, scattering and absorbing light along rays instead of at surfaces
+
+//distribution of the directions light scatters into, g > 0 favors forward and g < 0 backward scattering
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    //g in -1..1, 0 is isotropic
+    pub(crate) fn new(g: f64) -> Self {
+        Self{
+            g,
+        }
+    }
+
+    //density of scattering from the unit direction of travel into the unit direction wi, also the pdf of sample
+    pub(crate) fn eval(&self, direction: Vec3d, wi: Vec3d) -> f64 {
+        let g = self.g;
+        let denominator = 1. + g * g - 2. * g * direction.dot(&wi);
+        return (1. - g * g) / (4. * PI * denominator * denominator.max(0.).sqrt());
+    }
+
+    pub(crate) fn sample(&self, direction: Vec3d, rng: &mut dyn rand::RngCore) -> (Vec3d, f64) {
+        let g = self.g;
+        let u = rng.gen::<f64>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * u
+        } else {
+            let s = (1. - g * g) / (1. - g + 2. * g * u);
+            ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f64>();
+        let (tangent, bitangent) = direction.orthonormal_basis();
+        let wi = (sin_theta * phi.cos()) * tangent + (sin_theta * phi.sin()) * bitangent + cos_theta * direction;
+        return (wi, self.eval(direction, wi));
+    }
+}
+
+pub(crate) trait Medium {
+    //absorption and scattering coefficients per unit of length at the point
+    fn coefficients(&self, point: Vec3d) -> (Vec3d, Vec3d);
+
+    //radiance emitted per unit of length at the point, for fire
+    fn emission(&self, _point: Vec3d) -> Vec3d {
+        return Vec3d::zero();
+    }
+
+    //bound on every channel of the extinction anywhere in the medium, used by delta and ratio tracking
+    fn majorant(&self) -> f64;
+
+    fn phase(&self) -> HenyeyGreenstein;
+
+    //media with constant coefficients and no emission are sampled in closed form
+    fn is_homogeneous(&self) -> bool {
+        return false;
+    }
+}
+
+//constant density medium, coefficients are per unit of length
+pub(crate) struct HomogeneousMedium {
+    absorption: Vec3d,
+    scattering: Vec3d,
+    phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    pub(crate) fn new(absorption: Vec3d, scattering: Vec3d, g: f64) -> Self {
+        Self{
+            absorption,
+            scattering,
+            phase: HenyeyGreenstein::new(g),
+        }
+    }
+}
+
+impl Medium for HomogeneousMedium {
+    fn coefficients(&self, _point: Vec3d) -> (Vec3d, Vec3d) {
+        return (self.absorption, self.scattering);
+    }
+
+    fn majorant(&self) -> f64 {
+        let extinction = self.absorption + self.scattering;
+        return extinction.x.max(extinction.y).max(extinction.z);
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        return self.phase;
+    }
+
+    fn is_homogeneous(&self) -> bool {
+        return true;
+    }
+}
+
+//medium filling the inside of a closed boundary, or the whole scene without one
+pub(crate) struct MediumRegion {
+    boundary: Option<Arc<dyn Hittable + Send + Sync>>,
+    medium: Box<dyn Medium + Send + Sync>,
+}
+
+impl MediumRegion {
+    pub(crate) fn bounded(boundary: Arc<dyn Hittable + Send + Sync>, medium: Box<dyn Medium + Send + Sync>) -> Self {
+        Self{
+            boundary: Some(boundary),
+            medium,
+        }
+    }
+
+    pub(crate) fn everywhere(medium: Box<dyn Medium + Send + Sync>) -> Self {
+        Self{
+            boundary: None,
+            medium,
+        }
+    }
+
+    //pieces of the ray between 0 and t_max inside the region, every crossing of the boundary enters or leaves it
+    fn overlaps(&self, ray: &Ray, t_max: f64, overlaps: &mut Vec<(f64, f64)>) {
+        let boundary = match &self.boundary {
+            Some(boundary) => boundary,
+            None => {
+                overlaps.push((0., t_max));
+                return;
+            }
+        };
+        //start behind the origin so a ray starting inside finds where it entered
+        let mut t = f64::NEG_INFINITY;
+        while let Some(entry) = boundary.hit(ray, t..f64::INFINITY) {
+            if entry.t >= t_max {
+                break;
+            }
+            let exit = match boundary.hit(ray, entry.t + SHADOW_EPSILON..f64::INFINITY) {
+                Some(exit) => exit.t,
+                None => break,
+            };
+            if exit > 0. {
+                overlaps.push((entry.t.max(0.), exit.min(t_max)));
+            }
+            t = exit + SHADOW_EPSILON;
+        }
+    }
+}
+
+//result of sampling the distance a ray travels through the media before it scatters
+pub(crate) struct MediumSample {
+    //transmittance and scattering coefficient divided by the pdf of the sampled distance
+    pub(crate) weight: Vec3d,
+    //distance along the ray where the light scatters and the phase function there, None if it reaches t_max
+    pub(crate) scatter: Option<(f64, HenyeyGreenstein)>,
+    //radiance emitted by the media along the sampled part of the ray, not yet multiplied by weight
+    pub(crate) emitted: Vec3d,
+}
+
+impl MediumSample {
+    fn absorbed() -> Self {
+        Self{
+            weight: Vec3d::zero(),
+            scatter: None,
+            emitted: Vec3d::zero(),
+        }
+    }
+}
+
+pub(crate) struct MediumList {
+    regions: Vec<MediumRegion>,
+}
+
+impl MediumList {
+    pub(crate) fn new(regions: Vec<MediumRegion>) -> Self {
+        Self{
+            regions,
+        }
+    }
+
+    //pieces of constant media between 0 and t_max in ray order, with the indices of the regions covering them
+    fn segments(&self, ray: &Ray, t_max: f64) -> Vec<(f64, f64, Vec<usize>)> {
+        let mut overlaps = Vec::new();
+        let mut covered: Vec<(f64, f64, usize)> = Vec::new();
+        for (index, region) in self.regions.iter().enumerate() {
+            overlaps.clear();
+            region.overlaps(ray, t_max, &mut overlaps);
+            covered.extend(overlaps.iter().filter(|(start, end)| start < end).map(|&(start, end)| (start, end, index)));
+        }
+        let mut bounds: Vec<f64> = covered.iter().flat_map(|&(start, end, _)| [start, end]).collect();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        //no bound falls inside a window, so a region covers it whole or not at all, also when it reaches infinity
+        return bounds.windows(2).filter_map(|pair| {
+            let active: Vec<usize> = covered.iter().filter(|&&(start, end, _)| start <= pair[0] && pair[1] <= end).map(|&(_, _, index)| index).collect();
+            if active.is_empty() { None } else { Some((pair[0], pair[1], active)) }
+        }).collect();
+    }
+
+    //summed absorption and scattering of the regions
+    fn coefficients(&self, active: &[usize], point: Vec3d) -> (Vec3d, Vec3d) {
+        return active.iter().fold((Vec3d::zero(), Vec3d::zero()), |(absorption, scattering), &index| {
+            let (a, s) = self.regions[index].medium.coefficients(point);
+            (absorption + a, scattering + s)
+        });
+    }
+
+    fn majorant(&self, active: &[usize]) -> f64 {
+        return active.iter().map(|&index| self.regions[index].medium.majorant()).sum();
+    }
+
+    //one of the regions scattering at the point, picked by how much it scatters, and its weight
+    fn pick_phase(&self, active: &[usize], point: Vec3d, rng: &mut dyn rand::RngCore) -> Option<(HenyeyGreenstein, Vec3d)> {
+        let scattering: Vec<Vec3d> = active.iter().map(|&index| self.regions[index].medium.coefficients(point).1).collect();
+        let total: f64 = scattering.iter().map(|&s| mean(s)).sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut u = rng.gen::<f64>() * total;
+        let chosen = scattering.iter().position(|&s| {
+            u -= mean(s);
+            u < 0.
+        }).unwrap_or(active.len() - 1);
+        //the mixture of the phase functions weighted by their scattering, estimated with the one picked
+        return Some((self.regions[active[chosen]].medium.phase(), scattering[chosen] / mean(scattering[chosen])));
+    }
+
+    //fraction of the light passing through the media between 0 and t_max along the ray, in closed form
+    //for homogeneous media and estimated by ratio tracking otherwise
+    pub(crate) fn transmittance(&self, ray: &Ray, t_max: f64, rng: &mut dyn rand::RngCore) -> Vec3d {
+        if self.regions.is_empty() {
+            return Vec3d::new(1., 1., 1.);
+        }
+        let speed = ray.direction_no_unit.length();
+        let unit_direction = ray.direction_no_unit / speed;
+        let mut transmittance = Vec3d::new(1., 1., 1.);
+        for (start, end, active) in self.segments(ray, t_max) {
+            if active.iter().all(|&index| self.regions[index].medium.is_homogeneous()) {
+                let (absorption, scattering) = self.coefficients(&active, ray.origin);
+                transmittance = transmittance.comp_vise(exp_attenuation(absorption + scattering, (end - start) * speed));
+                continue;
+            }
+            let majorant = self.majorant(&active);
+            if majorant <= 0. {
+                continue;
+            }
+            let mut distance = start * speed;
+            loop {
+                distance -= (1. - rng.gen::<f64>()).ln() / majorant;
+                if distance >= end * speed {
+                    break;
+                }
+                let (absorption, scattering) = self.coefficients(&active, ray.origin + distance * unit_direction);
+                let extinction = absorption + scattering;
+                transmittance = transmittance.comp_vise(Vec3d::new(1., 1., 1.) - extinction / majorant).max(&Vec3d::zero());
+                if transmittance == Vec3d::zero() {
+                    return transmittance;
+                }
+            }
+        }
+        return transmittance;
+    }
+
+    //free-flight sampling of the distance to the next scattering, in closed form through homogeneous media
+    //and by delta tracking through the others
+    pub(crate) fn sample(&self, ray: &Ray, t_max: f64, rng: &mut dyn rand::RngCore) -> MediumSample {
+        let mut sample = MediumSample{
+            weight: Vec3d::new(1., 1., 1.),
+            scatter: None,
+            emitted: Vec3d::zero(),
+        };
+        if self.regions.is_empty() {
+            return sample;
+        }
+        let speed = ray.direction_no_unit.length();
+        let unit_direction = ray.direction_no_unit / speed;
+        for (start, end, active) in self.segments(ray, t_max) {
+            let done = if active.iter().all(|&index| self.regions[index].medium.is_homogeneous()) {
+                self.sample_homogeneous(&mut sample, ray.origin, (start * speed, end * speed), &active, rng)
+            } else {
+                self.delta_tracking(&mut sample, ray.origin, unit_direction, (start * speed, end * speed), &active, rng)
+            };
+            if done {
+                if let Some((distance, phase)) = sample.scatter {
+                    sample.scatter = Some((distance / speed, phase));
+                }
+                return sample;
+            }
+        }
+        return sample;
+    }
+
+    //one color channel is picked for the exponential distribution and the pdf averages over all three
+    //to keep the weights bounded, returns true if the path scatters or ends in the segment
+    fn sample_homogeneous(&self, sample: &mut MediumSample, origin: Vec3d, (start, end): (f64, f64), active: &[usize], rng: &mut dyn rand::RngCore) -> bool {
+        let (absorption, scattering) = self.coefficients(active, origin);
+        let extinction = absorption + scattering;
+        let channel = rng.gen_range(0..3);
+        let distance = -(1. - rng.gen::<f64>()).ln() / extinction[channel];
+        if distance < end - start {
+            let transmittance = exp_attenuation(extinction, distance);
+            let pdf = (0..3).map(|c| extinction[c] * transmittance[c]).sum::<f64>() / 3.;
+            return match self.pick_phase(active, origin, rng) {
+                Some((phase, weight)) => {
+                    sample.weight = sample.weight.comp_vise(transmittance.comp_vise(weight)) * (mean(scattering) / pdf);
+                    sample.scatter = Some((start + distance, phase));
+                    true
+                }
+                //purely absorbing, the path ends here
+                None => {
+                    *sample = MediumSample{ emitted: sample.emitted, ..MediumSample::absorbed() };
+                    true
+                }
+            };
+        }
+        let transmittance = exp_attenuation(extinction, end - start);
+        let pdf = (transmittance.x + transmittance.y + transmittance.z) / 3.;
+        if pdf <= 0. {
+            *sample = MediumSample{ emitted: sample.emitted, ..MediumSample::absorbed() };
+            return true;
+        }
+        sample.weight = sample.weight.comp_vise(transmittance) / pdf;
+        return false;
+    }
+
+    //tentative collisions at the rate of the majorant, each one either scatters or is a null collision,
+    //absorption only lowers the weight, emission is collected at every tentative collision
+    fn delta_tracking(&self, sample: &mut MediumSample, origin: Vec3d, unit_direction: Vec3d, (start, end): (f64, f64), active: &[usize], rng: &mut dyn rand::RngCore) -> bool {
+        let majorant = self.majorant(active);
+        if majorant <= 0. {
+            return false;
+        }
+        let mut distance = start;
+        loop {
+            distance -= (1. - rng.gen::<f64>()).ln() / majorant;
+            if distance >= end {
+                return false;
+            }
+            let point = origin + distance * unit_direction;
+            let emission = active.iter().fold(Vec3d::zero(), |acc, &index| acc + self.regions[index].medium.emission(point));
+            sample.emitted = sample.emitted + sample.weight.comp_vise(emission) / majorant;
+            let (absorption, scattering) = self.coefficients(active, point);
+            let scatter_probability = (mean(scattering) / majorant).min(1.);
+            if rng.gen::<f64>() < scatter_probability {
+                if let Some((phase, weight)) = self.pick_phase(active, point, rng) {
+                    sample.weight = sample.weight.comp_vise(weight);
+                    sample.scatter = Some((distance, phase));
+                    return true;
+                }
+            }
+            let null = (Vec3d::new(majorant, majorant, majorant) - absorption - scattering).max(&Vec3d::zero());
+            sample.weight = sample.weight.comp_vise(null) / (majorant * (1. - scatter_probability));
+            if sample.weight == Vec3d::zero() {
+                return true;
+            }
+        }
+    }
+}
+
+//exp(-extinction * distance) per channel, 1 in channels without extinction even over infinite distances
+fn exp_attenuation(extinction: Vec3d, distance: f64) -> Vec3d {
+    let channel = |sigma: f64| if sigma == 0. { 1. } else { (-sigma * distance).exp() };
+    return Vec3d::new(channel(extinction.x), channel(extinction.y), channel(extinction.z));
+}
+
+fn mean(v: Vec3d) -> f64 {
+    return (v.x + v.y + v.z) / 3.;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::material::Material;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_henyey_greenstein(){
+        let mut rng = StdRng::from_seed([5; 32]);
+        let direction = Vec3d::new(1., 2., -1.).unit();
+        for g in [-0.6, 0., 0.8] {
+            let phase = HenyeyGreenstein::new(g);
+            //integrates to 1 over the sphere, 2 pi times the integral over cos theta
+            let steps = 20000;
+            let (tangent, _) = direction.orthonormal_basis();
+            let integral: f64 = (0..steps).map(|i| {
+                let cos = -1. + (i as f64 + 0.5) * 2. / steps as f64;
+                let wi = cos * direction + (1. - cos * cos).sqrt() * tangent;
+                2. * PI * phase.eval(direction, wi) * 2. / steps as f64
+            }).sum();
+            assert!((integral - 1.).abs() < 1e-3, "g {}: integral {}", g, integral);
+            //the mean cosine of sampled directions is g
+            let n = 20000;
+            let mut mean_cos = 0.;
+            for _ in 0..n {
+                let (wi, pdf) = phase.sample(direction, &mut rng);
+                assert!((wi.length() - 1.).abs() < 1e-9);
+                assert!((pdf - phase.eval(direction, wi)).abs() < 1e-9 * pdf.max(1.));
+                mean_cos += direction.dot(&wi) / n as f64;
+            }
+            assert!((mean_cos - g).abs() < 0.02, "g {}: mean cos {}", g, mean_cos);
+        }
+    }
+
+    #[test]
+    fn test_transmittance(){
+        let mut rng = StdRng::from_seed([5; 32]);
+        let material = Arc::new(Material::builder().build());
+        let ball: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3d::new(0., 0., -5.), 1., material));
+        let media = MediumList::new(vec![
+            MediumRegion::bounded(ball.clone(), Box::new(HomogeneousMedium::new(Vec3d::new(0.5, 0., 1.), Vec3d::new(0., 0.5, 0.), 0.))),
+            MediumRegion::everywhere(Box::new(HomogeneousMedium::new(Vec3d::new(0.1, 0.1, 0.1), Vec3d::zero(), 0.))),
+        ]);
+        //two units through the ball and ten through the atmosphere, t is scaled by the unnormalized direction
+        let ray = Ray::new(Vec3d::zero(), Vec3d::new(0., 0., -2.));
+        let expected = Vec3d::new(f64::exp(-2.), f64::exp(-2.), f64::exp(-3.));
+        assert!((media.transmittance(&ray, 5., &mut rng) - expected).length() < 1e-12);
+        //starting inside the ball
+        let inside = Ray::new(Vec3d::new(0., 0., -5.), Vec3d::up());
+        let expected = Vec3d::new((-0.5 - 0.3f64).exp(), (-0.5 - 0.3f64).exp(), (-1. - 0.3f64).exp());
+        assert!((media.transmittance(&inside, 3., &mut rng) - expected).length() < 1e-12);
+        //missing the ball
+        let miss = Ray::new(Vec3d::zero(), Vec3d::right());
+        assert!((media.transmittance(&miss, 2., &mut rng) - Vec3d::new(1., 1., 1.) * (-0.2f64).exp()).length() < 1e-12);
+        assert_eq!(Vec3d::new(1., 1., 1.), MediumList::new(Vec::new()).transmittance(&ray, f64::INFINITY, &mut rng));
+    }
+
+    #[test]
+    fn test_atmosphere_reaches_infinity(){
+        let mut rng = StdRng::from_seed([7; 32]);
+        let media = MediumList::new(vec![MediumRegion::everywhere(Box::new(HomogeneousMedium::new(Vec3d::new(1., 1., 1.), Vec3d::new(1., 1., 1.), 0.)))]);
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        assert_eq!(Vec3d::zero(), media.transmittance(&ray, f64::INFINITY, &mut rng));
+        //rays leaving the scene always scatter, with the scattering albedo as weight
+        for _ in 0..1000 {
+            let sample = media.sample(&ray, f64::INFINITY, &mut rng);
+            let (t, _) = sample.scatter.unwrap();
+            assert!(t.is_finite() && t > 0.);
+            assert!((sample.weight - Vec3d::new(0.5, 0.5, 0.5)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_free_flight_sampling_is_unbiased(){
+        let mut rng = StdRng::from_seed([6; 32]);
+        let material = Arc::new(Material::builder().build());
+        let ball: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3d::new(0., 0., -3.), 1., material));
+        let absorption = Vec3d::new(0.2, 0.5, 0.);
+        let scattering = Vec3d::new(0.3, 0.5, 1.5);
+        let media = MediumList::new(vec![MediumRegion::bounded(ball, Box::new(HomogeneousMedium::new(absorption, scattering, 0.3)))]);
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        //passing weights average to the transmittance, scattering weights to the scattered fraction
+        let extinction = absorption + scattering;
+        let transmittance = media.transmittance(&ray, f64::INFINITY, &mut rng);
+        let n = 100000;
+        let (mut passed, mut scattered) = (Vec3d::zero(), Vec3d::zero());
+        for _ in 0..n {
+            let sample = media.sample(&ray, f64::INFINITY, &mut rng);
+            match sample.scatter {
+                Some((t, phase)) => {
+                    assert!((2. ..=4.).contains(&t));
+                    assert_eq!(HenyeyGreenstein::new(0.3), phase);
+                    scattered = scattered + sample.weight / n as f64;
+                }
+                None => passed = passed + sample.weight / n as f64,
+            }
+        }
+        for c in 0..3 {
+            let expected_scattered = scattering[c] / extinction[c] * (1. - transmittance[c]);
+            assert!((passed[c] - transmittance[c]).abs() < 0.01, "channel {}: passed {:?}, expected {:?}", c, passed, transmittance);
+            assert!((scattered[c] - expected_scattered).abs() < 0.01, "channel {}: scattered {:?}", c, scattered);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::f64::consts::PI;
+use rand::{Rng, RngCore};
+use crate::bsdf::{Bsdf, BsdfSample, ConductorFresnel, Dielectric, Mirror, fresnel_dielectric};
+use crate::hit::HitRecord;
+use crate::vec3d::Vec3d;
+
+//ggx (trowbridge-reitz) microfacet models, all angles are measured against the shading normal
+
+//below this alpha the smooth delta versions are used instead
+const MIN_ALPHA: f64 = 1e-3;
+
+//metal lobe, the delta mirror for nearly smooth surfaces so they stay sharp and skip light sampling
+pub(crate) fn conductor(roughness: f64, fresnel: ConductorFresnel, tint: Vec3d) -> Box<dyn Bsdf + Send + Sync> {
+    if is_smooth(roughness) {
+        return Box::new(Mirror::conductor(fresnel, tint));
+    }
+    return Box::new(RoughConductor::new(Ggx::from_roughness(roughness), fresnel, tint));
+}
+
+//glass lobe, the delta dielectric for nearly smooth surfaces
+pub(crate) fn dielectric(roughness: f64, refraction_index: f64, tint: Vec3d) -> Box<dyn Bsdf + Send + Sync> {
+    if is_smooth(roughness) {
+        return Box::new(Dielectric::new(refraction_index, tint));
+    }
+    return Box::new(RoughDielectric::new(Ggx::from_roughness(roughness), refraction_index, tint));
+}
+
+fn is_smooth(roughness: f64) -> bool {
+    return roughness * roughness < MIN_ALPHA;
+}
+
+//isotropic ggx distribution of microfacet normals
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    //alpha = roughness^2, perceptually more linear
+    pub(crate) fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0., 1.);
+        Self{
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    //density of microfacet normals with cos_h to the macro normal
+    pub(crate) fn d(&self, cos_h: f64) -> f64 {
+        if cos_h <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = cos_h * cos_h * (a2 - 1.) + 1.;
+        return a2 / (PI * t * t);
+    }
+
+    fn lambda(&self, cos: f64) -> f64 {
+        let cos2 = cos * cos;
+        if cos2 <= 0. {
+            return f64::INFINITY;
+        }
+        let tan2 = ((1. - cos2) / cos2).max(0.);
+        return (-1. + f64::sqrt(1. + self.alpha * self.alpha * tan2)) / 2.;
+    }
+
+    //smith masking of one direction
+    pub(crate) fn g1(&self, cos: f64) -> f64 {
+        return 1. / (1. + self.lambda(cos));
+    }
+
+    //height correlated masking and shadowing
+    pub(crate) fn g2(&self, cos_o: f64, cos_i: f64) -> f64 {
+        return 1. / (1. + self.lambda(cos_o) + self.lambda(cos_i));
+    }
+
+    //density of visible normals from wo: g1(wo) * max(0, wo.h) * d(h) / cos_o
+    pub(crate) fn visible_pdf(&self, normal: Vec3d, wo: Vec3d, h: Vec3d) -> f64 {
+        let cos_o = wo.dot(&normal);
+        if cos_o <= 0. {
+            return 0.;
+        }
+        return self.g1(cos_o) * wo.dot(&h).max(0.) * self.d(h.dot(&normal)) / cos_o;
+    }
+
+    //microfacet normal visible from wo (Heitz 2018), wo has to be above the surface
+    pub(crate) fn sample_visible_normal(&self, normal: Vec3d, wo: Vec3d, rng: &mut dyn RngCore) -> Vec3d {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let local_wo = Vec3d::new(wo.dot(&tangent), wo.dot(&bitangent), wo.dot(&normal));
+        //stretch to the hemisphere configuration
+        let vh = Vec3d::new(self.alpha * local_wo.x, self.alpha * local_wo.y, local_wo.z).unit();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0. {
+            Vec3d::new(-vh.y, vh.x, 0.) / f64::sqrt(length_squared)
+        } else {
+            Vec3d::new(1., 0., 0.)
+        };
+        let t2 = vh.cross(t1);
+        let r = f64::sqrt(rng.gen::<f64>());
+        let phi = 2. * PI * rng.gen::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * f64::sqrt((1. - p1 * p1).max(0.)) + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt((1. - p1 * p1 - p2 * p2).max(0.)) * vh;
+        //unstretch
+        let local_h = Vec3d::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-9)).unit();
+        return local_h.x * tangent + local_h.y * bitangent + local_h.z * normal;
+    }
+}
+
+//rough metal
+pub(crate) struct RoughConductor {
+    distribution: Ggx,
+    fresnel: ConductorFresnel,
+    tint: Vec3d,
+}
+
+impl RoughConductor {
+    pub(crate) fn new(distribution: Ggx, fresnel: ConductorFresnel, tint: Vec3d) -> Self {
+        Self{
+            distribution,
+            fresnel,
+            tint,
+        }
+    }
+}
+
+impl Bsdf for RoughConductor {
+    //f * cos_i = F * D * G2 / (4 * cos_o)
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d {
+        let n = hit.normal;
+        let (cos_o, cos_i) = (wo.dot(&n), wi.dot(&n));
+        if cos_o <= 0. || cos_i <= 0. {
+            return Vec3d::zero();
+        }
+        let h = (wo + wi).unit();
+        let value = self.distribution.d(h.dot(&n)) * self.distribution.g2(cos_o, cos_i) / (4. * cos_o);
+        return value * self.tint.comp_vise(self.fresnel.eval(wo.dot(&h)));
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.normal;
+        let cos_o = wo.dot(&n);
+        if cos_o <= 0. {
+            return None;
+        }
+        let h = self.distribution.sample_visible_normal(n, wo, rng);
+        let direction = (-wo).reflect(&h).unit();
+        let cos_i = direction.dot(&n);
+        if cos_i <= 0. {
+            return None;
+        }
+        //f * cos_i / pdf simplifies to F * G2 / G1
+        let weight = self.distribution.g2(cos_o, cos_i) / self.distribution.g1(cos_o);
+        return Some(BsdfSample{
+            direction,
+            weight: weight * self.tint.comp_vise(self.fresnel.eval(wo.dot(&h))),
+            pdf: self.pdf(hit, wo, direction),
+            specular: false,
+        });
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> f64 {
+        let n = hit.normal;
+        if wo.dot(&n) <= 0. || wi.dot(&n) <= 0. {
+            return 0.;
+        }
+        let h = (wo + wi).unit();
+        //jacobian of the reflection 1 / (4 wo.h)
+        return self.distribution.visible_pdf(n, wo, h) / (4. * wo.dot(&h));
+    }
+}
+
+//rough glass (Walter et al. 2007), reflection or refraction chosen by the fresnel term of the sampled microfacet
+pub(crate) struct RoughDielectric {
+    distribution: Ggx,
+    refraction_index: f64,
+    tint: Vec3d,
+}
+
+impl RoughDielectric {
+    pub(crate) fn new(distribution: Ggx, refraction_index: f64, tint: Vec3d) -> Self {
+        Self{
+            distribution,
+            refraction_index,
+            tint,
+        }
+    }
+
+    //index of the side wi enters relative to the side of wo
+    fn eta(&self, hit: &HitRecord) -> f64 {
+        return if hit.front_face {self.refraction_index} else {1. / self.refraction_index};
+    }
+
+    //microfacet normal on the side of wo for a reflected or refracted pair, None for impossible configurations
+    fn half_vector(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Option<(Vec3d, bool)> {
+        let n = hit.normal;
+        let reflect = wi.dot(&n) > 0.;
+        let h = if reflect { wo + wi } else { wo + self.eta(hit) * wi };
+        if h.length_squared() == 0. {
+            return None;
+        }
+        let mut h = h.unit();
+        if h.dot(&n) < 0. {
+            h = -h;
+        }
+        //wo has to be on the front side of the microfacet, a refracted wi on its back side
+        if wo.dot(&h) <= 0. || (reflect && wi.dot(&h) <= 0.) || (!reflect && wi.dot(&h) >= 0.) {
+            return None;
+        }
+        return Some((h, reflect));
+    }
+}
+
+impl Bsdf for RoughDielectric {
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d {
+        let n = hit.normal;
+        let (cos_o, cos_i) = (wo.dot(&n), wi.dot(&n));
+        if cos_o <= 0. || cos_i == 0. {
+            return Vec3d::zero();
+        }
+        let (h, reflect) = match self.half_vector(hit, wo, wi) {
+            Some(half) => half,
+            None => return Vec3d::zero(),
+        };
+        let eta = self.eta(hit);
+        let fresnel = fresnel_dielectric(wo.dot(&h), eta);
+        let d = self.distribution.d(h.dot(&n));
+        let g = self.distribution.g2(cos_o, cos_i.abs());
+        let value = if reflect {
+            fresnel * d * g / (4. * cos_o)
+        } else {
+            //like the smooth dielectric, radiance is not scaled by the change in solid angle
+            let denominator = wo.dot(&h) + eta * wi.dot(&h);
+            (1. - fresnel) * d * g * eta * eta * wi.dot(&h).abs() * wo.dot(&h) / (cos_o * denominator * denominator)
+        };
+        return value * self.tint;
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.normal;
+        let cos_o = wo.dot(&n);
+        if cos_o <= 0. {
+            return None;
+        }
+        let h = self.distribution.sample_visible_normal(n, wo, rng);
+        let eta = self.eta(hit);
+        let fresnel = fresnel_dielectric(wo.dot(&h), eta);
+        let direction = if rng.gen::<f64>() < fresnel {
+            (-wo).reflect(&h).unit()
+        } else {
+            (-wo).refract(&h, 1. / eta).unit()
+        };
+        let cos_i = direction.dot(&n);
+        let pdf = self.pdf(hit, wo, direction);
+        if pdf <= 0. {
+            return None;
+        }
+        //f * |cos_i| / pdf simplifies to G2 / G1 for both cases
+        let weight = self.distribution.g2(cos_o, cos_i.abs()) / self.distribution.g1(cos_o);
+        return Some(BsdfSample{
+            direction,
+            weight: weight * self.tint,
+            pdf,
+            specular: false,
+        });
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> f64 {
+        let n = hit.normal;
+        if wo.dot(&n) <= 0. || wi.dot(&n) == 0. {
+            return 0.;
+        }
+        let (h, reflect) = match self.half_vector(hit, wo, wi) {
+            Some(half) => half,
+            None => return 0.,
+        };
+        let eta = self.eta(hit);
+        let fresnel = fresnel_dielectric(wo.dot(&h), eta);
+        let visible = self.distribution.visible_pdf(n, wo, h);
+        if reflect {
+            return fresnel * visible / (4. * wo.dot(&h));
+        }
+        let denominator = wo.dot(&h) + eta * wi.dot(&h);
+        return (1. - fresnel) * visible * eta * eta * wi.dot(&h).abs() / (denominator * denominator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::material::Material;
+
+    fn hit(front_face: bool) -> HitRecord {
+        let material = Arc::new(Material::builder().build());
+        let in_dir = if front_face { Vec3d::down() } else { Vec3d::up() };
+        return HitRecord::with_unit_normal(Vec3d::zero(), Vec3d::up(), 1., in_dir, material);
+    }
+
+    fn uniform_sphere(rng: &mut StdRng) -> Vec3d {
+        let z = 2. * rng.gen::<f64>() - 1.;
+        let phi = 2. * PI * rng.gen::<f64>();
+        let r = f64::sqrt(1. - z * z);
+        return Vec3d::new(r * phi.cos(), z, r * phi.sin());
+    }
+
+    #[test]
+    fn test_ggx_normalization(){
+        //the projected area of the microfacets is 1: integral of d(h) cos_h over the hemisphere
+        for roughness in [0.2, 0.5, 1.] {
+            let ggx = Ggx::from_roughness(roughness);
+            let steps = 20000;
+            let integral = (0..steps).map(|i| {
+                let theta = (i as f64 + 0.5) / steps as f64 * PI / 2.;
+                ggx.d(theta.cos()) * theta.cos() * theta.sin() * 2. * PI * (PI / 2. / steps as f64)
+            }).sum::<f64>();
+            assert!((integral - 1.).abs() < 1e-3, "roughness {}: {}", roughness, integral);
+        }
+    }
+
+    #[test]
+    fn test_visible_normals_integrate_to_one(){
+        let ggx = Ggx::from_roughness(0.6);
+        let n = Vec3d::up();
+        let wo = Vec3d::new(0.6, 0.8, 0.).unit();
+        let mut rng = StdRng::from_seed([11; 32]);
+        let samples = 200000;
+        let integral = (0..samples).map(|_| ggx.visible_pdf(n, wo, uniform_sphere(&mut rng)) * 4. * PI).sum::<f64>() / samples as f64;
+        assert!((integral - 1.).abs() < 0.02, "{}", integral);
+    }
+
+    fn check_sampling(bsdf: &dyn Bsdf, hit: &HitRecord, wo: Vec3d, seed: u8) {
+        //sampled weights match eval / pdf and the pdf integrates to at most 1
+        let mut rng = StdRng::from_seed([seed; 32]);
+        for _ in 0..1000 {
+            if let Some(sample) = bsdf.sample(hit, wo, &mut rng) {
+                let expected = bsdf.eval(hit, wo, sample.direction) / bsdf.pdf(hit, wo, sample.direction);
+                assert!((expected - sample.weight).length() < 1e-6 * (1. + expected.length()), "{:?} {:?}", expected, sample.weight);
+            }
+        }
+        let samples = 200000;
+        let integral = (0..samples).map(|_| bsdf.pdf(hit, wo, uniform_sphere(&mut rng)) * 4. * PI).sum::<f64>() / samples as f64;
+        //single scattering loses some energy to masking
+        assert!(integral < 1.03 && integral > 0.7, "{}", integral);
+    }
+
+    #[test]
+    fn test_rough_conductor(){
+        let white = Vec3d::new(1., 1., 1.);
+        let bsdf = RoughConductor::new(Ggx::from_roughness(0.5), ConductorFresnel::Schlick(white), white);
+        check_sampling(&bsdf, &hit(true), Vec3d::new(0.3, 0.9, 0.1).unit(), 1);
+        assert_eq!(Vec3d::zero(), bsdf.eval(&hit(true), Vec3d::up(), Vec3d::down()));
+    }
+
+    #[test]
+    fn test_rough_dielectric(){
+        let white = Vec3d::new(1., 1., 1.);
+        let bsdf = RoughDielectric::new(Ggx::from_roughness(0.7), 1.5, white);
+        check_sampling(&bsdf, &hit(true), Vec3d::new(0.5, 0.8, -0.2).unit(), 2);
+        //leaving the glass
+        check_sampling(&bsdf, &hit(false), Vec3d::new(0.5, -0.8, -0.2).unit(), 3);
+        //light passes through
+        assert!(bsdf.eval(&hit(true), Vec3d::up(), Vec3d::new(0.1, -1., 0.).unit()).x > 0.);
+    }
+
+    #[test]
+    fn test_white_furnace(){
+        //a white conductor without fresnel loss reflects at most everything
+        let white = Vec3d::new(1., 1., 1.);
+        let bsdf = RoughConductor::new(Ggx::from_roughness(0.3), ConductorFresnel::Schlick(white), white);
+        let hit = hit(true);
+        let wo = Vec3d::new(0., 1., 1.).unit();
+        let mut rng = StdRng::from_seed([4; 32]);
+        let samples = 100000;
+        let albedo = (0..samples).map(|_| bsdf.sample(&hit, wo, &mut rng).map_or(0., |s| s.weight.x)).sum::<f64>() / samples as f64;
+        assert!(albedo > 0.9 && albedo <= 1.001, "{}", albedo);
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use crate::hit::Hittable;
+use crate::material::Material;
+use crate::triangle::TriangleMesh;
+use crate::vec3d::Vec3d;
+
+//diffuse lobe weight of imported materials, same as used for the hand written materials
+const DIFFUSE_ABSORPTION: f64 = 0.3;
+const DEFAULT_GROUP: &str = "default";
+
+#[derive(Debug)]
+pub(crate) struct ObjError {
+    pub(crate) file: String,
+    //0 if the error is not tied to a line, e.g. the file could not be read
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+impl ObjError {
+    fn new(file: &str, line: usize, message: String) -> Self {
+        Self{
+            file: file.to_string(),
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.file, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<ObjError> for std::io::Error {
+    fn from(error: ObjError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+//one mesh per combination of group and material
+pub(crate) struct ObjGroup {
+    pub(crate) mesh: Arc<TriangleMesh>,
+}
+
+pub(crate) struct ObjModel {
+    pub(crate) groups: Vec<ObjGroup>,
+}
+
+impl ObjModel {
+    pub(crate) fn hittables(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        return self.groups.iter().flat_map(|group| TriangleMesh::triangles(&group.mesh)).collect();
+    }
+}
+
+pub(crate) fn load_obj(path: &str, default_material: Arc<Material>) -> Result<ObjModel, ObjError> {
+    let source = std::fs::read_to_string(path).map_err(|e| ObjError::new(path, 0, e.to_string()))?;
+    let directory = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+    return parse_obj(&source, path, default_material, |library| {
+        let library_path = directory.join(library);
+        load_mtl(&library_path.to_string_lossy())
+    });
+}
+
+pub(crate) fn load_mtl(path: &str) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    let source = std::fs::read_to_string(path).map_err(|e| ObjError::new(path, 0, e.to_string()))?;
+    return parse_mtl(&source, path);
+}
+
+//mtllib statements are resolved through load_library so parsing does not depend on the file system
+pub(crate) fn parse_obj<F>(source: &str, file: &str, default_material: Arc<Material>, mut load_library: F) -> Result<ObjModel, ObjError>
+    where F: FnMut(&str) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    let mut positions: Vec<Vec3d> = Vec::new();
+    let mut normals: Vec<Vec3d> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut materials: HashMap<String, Arc<Material>> = HashMap::new();
+
+    let mut builders: Vec<MeshBuilder> = Vec::new();
+    let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = DEFAULT_GROUP.to_string();
+    let mut material_name: Option<String> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| ObjError::new(file, line_number, message);
+        let mut tokens = strip_comment(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let values = parse_floats(&args, 3, 4, keyword).map_err(error)?;
+                positions.push(Vec3d::new(values[0], values[1], values[2]));
+            }
+            "vn" => {
+                let values = parse_floats(&args, 3, 3, keyword).map_err(error)?;
+                let normal = Vec3d::new(values[0], values[1], values[2]);
+                if normal.length_squared() == 0. {
+                    return Err(error("`vn` must not have zero length".to_string()));
+                }
+                normals.push(normal.unit());
+            }
+            "vt" => {
+                let values = parse_floats(&args, 1, 3, keyword).map_err(error)?;
+                uvs.push((values[0], values.get(1).copied().unwrap_or(0.)));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(format!("face needs at least 3 vertices, found {}", args.len())));
+                }
+                let mut face = Vec::with_capacity(args.len());
+                for arg in &args {
+                    face.push(parse_face_vertex(arg, positions.len(), uvs.len(), normals.len()).map_err(error)?);
+                }
+
+                let key = (group.clone(), material_name.clone());
+                let builder_index = *builder_lookup.entry(key).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(material_name.clone()));
+                    builders.len() - 1
+                });
+                let builder = &mut builders[builder_index];
+                let local: Vec<usize> = face.iter().map(|vertex| builder.vertex(vertex, &positions, &uvs, &normals)).collect();
+                let points: Vec<Vec3d> = face.iter().map(|vertex| positions[vertex.0]).collect();
+                for [a, b, c] in triangulate(&points) {
+                    builder.indices.push([local[a], local[b], local[c]]);
+                }
+            }
+            "g" | "o" => {
+                group = if args.is_empty() { DEFAULT_GROUP.to_string() } else { args.join(" ") };
+            }
+            "usemtl" => {
+                if args.len() != 1 {
+                    return Err(error(format!("usemtl expects 1 material name, found {}", args.len())));
+                }
+                if !materials.contains_key(args[0]) {
+                    return Err(error(format!("unknown material `{}`", args[0])));
+                }
+                material_name = Some(args[0].to_string());
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(error("mtllib expects at least 1 file name".to_string()));
+                }
+                for library in &args {
+                    materials.extend(load_library(library)?);
+                }
+            }
+            //smoothing groups, lines, points and free-form geometry are not supported
+            _ => {}
+        }
+    }
+
+    let groups = builders.into_iter()
+        .filter(|builder| !builder.indices.is_empty())
+        .map(|builder| {
+            let material = match &builder.material_name {
+                Some(name) => materials[name].clone(),
+                None => default_material.clone(),
+            };
+            builder.build(material)
+        })
+        .collect();
+    return Ok(ObjModel{ groups });
+}
+
+pub(crate) fn parse_mtl(source: &str, file: &str) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    let mut definitions: Vec<(String, MtlDefinition)> = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let error = |message: String| ObjError::new(file, line_number, message);
+        let mut tokens = strip_comment(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.len() != 1 {
+                return Err(error(format!("newmtl expects 1 material name, found {}", args.len())));
+            }
+            definitions.push((args[0].to_string(), MtlDefinition::default()));
+            continue;
+        }
+        let is_supported = matches!(keyword, "Kd" | "Ks" | "Ke" | "Ns" | "Ni" | "d" | "Tr");
+        if !is_supported {
+            //Ka, illum, texture maps etc. have no counterpart in Material
+            continue;
+        }
+        let definition = match definitions.last_mut() {
+            Some((_, definition)) => definition,
+            None => return Err(error(format!("`{}` before the first newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => definition.diffuse = Some(parse_color(&args, keyword).map_err(error)?),
+            "Ks" => definition.specular = Some(parse_color(&args, keyword).map_err(error)?),
+            "Ke" => definition.emission = Some(parse_color(&args, keyword).map_err(error)?),
+            "Ns" => definition.specular_exponent = Some(parse_floats(&args, 1, 1, keyword).map_err(error)?[0]),
+            "Ni" => definition.refraction_index = Some(parse_floats(&args, 1, 1, keyword).map_err(error)?[0]),
+            "d" => definition.dissolve = Some(parse_floats(&args, 1, 1, keyword).map_err(error)?[0]),
+            "Tr" => definition.dissolve = Some(1. - parse_floats(&args, 1, 1, keyword).map_err(error)?[0]),
+            _ => unreachable!(),
+        }
+    }
+
+    return Ok(definitions.into_iter().map(|(name, definition)| (name, Arc::new(definition.build()))).collect());
+}
+
+#[derive(Default)]
+struct MtlDefinition {
+    diffuse: Option<Vec3d>,
+    specular: Option<Vec3d>,
+    emission: Option<Vec3d>,
+    specular_exponent: Option<f64>,
+    refraction_index: Option<f64>,
+    dissolve: Option<f64>,
+}
+
+impl MtlDefinition {
+    fn build(&self) -> Material {
+        let mut builder = Material::builder()
+            .albedo(self.diffuse.unwrap_or(Vec3d::new(0.8, 0.8, 0.8)), DIFFUSE_ABSORPTION);
+
+        if let Some(specular) = self.specular {
+            let reflectivity = max_component(&specular);
+            if reflectivity > 0. {
+                //phong exponent to ggx alpha = sqrt(2 / (Ns + 2)) and roughness = sqrt(alpha), 0 for a perfect mirror
+                let roughness = self.specular_exponent.map_or(0., |ns| (2. / (ns.max(0.) + 2.)).powf(0.25));
+                builder = builder.reflection(reflectivity, roughness).reflection_color(specular / reflectivity);
+            }
+        }
+
+        let dissolve = self.dissolve.unwrap_or(1.).clamp(0., 1.);
+        builder = builder.refraction(self.refraction_index.unwrap_or(1.), 1. - dissolve);
+
+        if let Some(emission) = self.emission {
+            let intensity = max_component(&emission);
+            if intensity > 0. {
+                builder = builder.emission(emission / intensity, intensity);
+            }
+        }
+        return builder.build();
+    }
+}
+
+struct MeshBuilder {
+    material_name: Option<String>,
+    positions: Vec<Vec3d>,
+    normals: Vec<Vec3d>,
+    uvs: Vec<(f64, f64)>,
+    has_all_normals: bool,
+    has_all_uvs: bool,
+    indices: Vec<[usize; 3]>,
+    //obj indexes positions, uvs and normals separately, the mesh needs one index per vertex
+    vertex_lookup: HashMap<FaceVertex, usize>,
+}
+
+//position, uv and normal index
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+impl MeshBuilder {
+    fn new(material_name: Option<String>) -> Self {
+        Self{
+            material_name,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            has_all_normals: true,
+            has_all_uvs: true,
+            indices: Vec::new(),
+            vertex_lookup: HashMap::new(),
+        }
+    }
+
+    fn vertex(&mut self, vertex: &FaceVertex, positions: &[Vec3d], uvs: &[(f64, f64)], normals: &[Vec3d]) -> usize {
+        if let Some(&index) = self.vertex_lookup.get(vertex) {
+            return index;
+        }
+        let (position, uv, normal) = *vertex;
+        self.positions.push(positions[position]);
+        self.uvs.push(uv.map_or((0., 0.), |i| uvs[i]));
+        self.normals.push(normal.map_or(Vec3d::zero(), |i| normals[i]));
+        self.has_all_uvs &= uv.is_some();
+        self.has_all_normals &= normal.is_some();
+        let index = self.positions.len() - 1;
+        self.vertex_lookup.insert(*vertex, index);
+        return index;
+    }
+
+    fn build(self, material: Arc<Material>) -> ObjGroup {
+        let normals = if self.has_all_normals { Some(self.normals) } else { None };
+        let uvs = if self.has_all_uvs { Some(self.uvs) } else { None };
+        ObjGroup{
+            mesh: Arc::new(TriangleMesh::new(self.positions, normals, uvs, self.indices, material)),
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    return match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    };
+}
+
+fn parse_floats(args: &[&str], min: usize, max: usize, keyword: &str) -> Result<Vec<f64>, String> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+        return Err(format!("`{}` expects {} numbers, found {}", keyword, expected, args.len()));
+    }
+    return args.iter()
+        .map(|arg| arg.parse::<f64>().map_err(|_| format!("`{}`: invalid number `{}`", keyword, arg)))
+        .collect();
+}
+
+fn parse_color(args: &[&str], keyword: &str) -> Result<Vec3d, String> {
+    //a single value is used for all channels
+    let values = parse_floats(args, 1, 3, keyword)?;
+    return match values.len() {
+        1 => Ok(Vec3d::new(values[0], values[0], values[0])),
+        3 => Ok(Vec3d::new(values[0], values[1], values[2])),
+        _ => Err(format!("`{}` expects 1 or 3 numbers, found 2", keyword)),
+    };
+}
+
+//formats v, v/vt, v//vn and v/vt/vn with 1-based or negative relative indices
+fn parse_face_vertex(arg: &str, position_count: usize, uv_count: usize, normal_count: usize) -> Result<FaceVertex, String> {
+    let parts: Vec<&str> = arg.split('/').collect();
+    if parts.len() > 3 {
+        return Err(format!("invalid face vertex `{}`", arg));
+    }
+    let position = resolve_index(parts[0], position_count, "position", arg)?;
+    let uv = match parts.get(1) {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, uv_count, "texture coordinate", arg)?),
+        _ => None,
+    };
+    let normal = match parts.get(2) {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, normal_count, "normal", arg)?),
+        _ => None,
+    };
+    return Ok((position, uv, normal));
+}
+
+fn resolve_index(part: &str, count: usize, kind: &str, arg: &str) -> Result<usize, String> {
+    let index: i64 = part.parse().map_err(|_| format!("invalid face vertex `{}`", arg))?;
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} out of range in `{}`, {} defined so far", kind, index, arg, count));
+    }
+    return Ok(resolved as usize);
+}
+
+fn max_component(v: &Vec3d) -> f64 {
+    return v.x.max(v.y).max(v.z);
+}
+
+//ear clipping in the plane of the polygon, falls back to a fan for degenerate polygons
+fn triangulate(points: &[Vec3d]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    let fan = || (1..n - 1).map(|i| [0, i, i + 1]).collect::<Vec<[usize; 3]>>();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    //newell normal, robust for non planar polygons
+    let mut normal = Vec3d::zero();
+    for i in 0..n {
+        let current = points[i];
+        let next = points[(i + 1) % n];
+        normal = normal + Vec3d::new(
+            (current.y - next.y) * (current.z + next.z),
+            (current.z - next.z) * (current.x + next.x),
+            (current.x - next.x) * (current.y + next.y),
+        );
+    }
+    if normal.length_squared() < 1e-24 {
+        return fan();
+    }
+
+    //project onto the plane of the two axes not dominated by the normal, keeping counter-clockwise order
+    let abs = Vec3d::new(normal.x.abs(), normal.y.abs(), normal.z.abs());
+    let (a, b) = if abs.x > abs.y && abs.x > abs.z { (1, 2) } else if abs.y > abs.z { (2, 0) } else { (0, 1) };
+    let dominant = if abs.x > abs.y && abs.x > abs.z { normal.x } else if abs.y > abs.z { normal.y } else { normal.z };
+    let flip = if dominant < 0. { -1. } else { 1. };
+    let projected: Vec<(f64, f64)> = points.iter().map(|p| (p[a], flip * p[b])).collect();
+
+    let cross = |o: (f64, f64), p: (f64, f64), q: (f64, f64)| (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0);
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (prev, current, next) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            let (p0, p1, p2) = (projected[prev], projected[current], projected[next]);
+            if cross(p0, p1, p2) <= 0. {
+                return false;
+            }
+            return remaining.iter()
+                .filter(|&&j| j != prev && j != current && j != next)
+                .all(|&j| {
+                    let p = projected[j];
+                    cross(p0, p1, p) < 0. || cross(p1, p2, p) < 0. || cross(p2, p0, p) < 0.
+                });
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            }
+            None => return fan(),
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    return triangles;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_library(name: &str) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+        return Err(ObjError::new(name, 0, "not found".to_string()));
+    }
+
+    #[test]
+    fn test_parse_faces_and_groups(){
+        let source = "\
+# quad and a triangle
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+g quad
+f 1/1/1 2/2/1 3/3/1 4/1/1
+g tri
+f -4 -3 -2
+";
+        let model = parse_obj(source, "test.obj", Arc::new(Material::builder().build()), no_library).unwrap();
+        assert_eq!(2, model.groups.len());
+        //groups keep the order they first appear in
+        assert_eq!(2, model.groups[0].mesh.triangle_count());
+        assert_eq!(1, model.groups[1].mesh.triangle_count());
+        assert_eq!(3, model.hittables().len());
+    }
+
+    #[test]
+    fn test_parse_errors_have_line_numbers(){
+        let material = Arc::new(Material::builder().build());
+        let error = parse_obj("v 0 0 0\nv 1 0\n", "a.obj", material.clone(), no_library).err().unwrap();
+        assert_eq!(2, error.line);
+        assert_eq!("a.obj:2: `v` expects 3 to 4 numbers, found 2", error.to_string());
+
+        let error = parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\n\nf 1 2 4\n", "a.obj", material.clone(), no_library).err().unwrap();
+        assert_eq!(5, error.line);
+
+        let error = parse_obj("vn 0 0 0\n", "a.obj", material.clone(), no_library).err().unwrap();
+        assert_eq!("a.obj:1: `vn` must not have zero length", error.to_string());
+
+        let error = parse_obj("usemtl missing\n", "a.obj", material, no_library).err().unwrap();
+        assert_eq!("a.obj:1: unknown material `missing`", error.to_string());
+    }
+
+    #[test]
+    fn test_triangulate_concave(){
+        //L-shape, a fan from vertex 0 would leave the polygon
+        let points = vec![
+            Vec3d::new(0.,0.,0.), Vec3d::new(2.,0.,0.), Vec3d::new(2.,1.,0.),
+            Vec3d::new(1.,1.,0.), Vec3d::new(1.,2.,0.), Vec3d::new(0.,2.,0.),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(4, triangles.len());
+        let area: f64 = triangles.iter()
+            .map(|t| (points[t[1]] - points[t[0]]).cross(points[t[2]] - points[t[0]]).length() / 2.)
+            .sum();
+        assert!((area - 3.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_mtl(){
+        let source = "\
+newmtl glass
+Kd 0.1 0.2 0.3
+Ni 1.5
+d 0.25
+newmtl lamp
+Ke 2 4 1
+";
+        let materials = parse_mtl(source, "test.mtl").unwrap();
+        let glass = Material::builder().albedo(Vec3d::new(0.1, 0.2, 0.3), DIFFUSE_ABSORPTION).refraction(1.5, 0.75).build();
+        assert!(materials["glass"].scatters_like(&glass));
+        assert!(!materials["glass"].scatters_like(&Material::builder().albedo(Vec3d::new(0.1, 0.2, 0.3), DIFFUSE_ABSORPTION).refraction(1.3, 0.75).build()));
+        let lamp = &materials["lamp"];
+        assert_eq!(Vec3d::new(0.5, 1., 0.25), lamp.emission_color);
+        assert_eq!(4., lamp.emission_intensity);
+
+        let error = parse_mtl("Kd 1 1 1\n", "test.mtl").err().unwrap();
+        assert_eq!("test.mtl:1: `Kd` before the first newmtl", error.to_string());
+    }
+}
@@ -0,0 +1,72 @@
+use std::ops::Range;
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+//infinite plane through a point, the bvh keeps it outside of its nodes
+pub(crate) struct Plane {
+    point: Vec3d,
+    unit_normal: Vec3d,
+    //directions of u and v on the plane, one texture repeat per unit of length
+    tangent: Vec3d,
+    bitangent: Vec3d,
+    material: Arc<Material>,
+}
+
+impl Plane {
+    pub(crate) fn new(point: Vec3d, normal: Vec3d, material: Arc<Material>) -> Self {
+        let unit_normal = normal.unit();
+        let (tangent, bitangent) = unit_normal.orthonormal_basis();
+        Self{
+            point,
+            unit_normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let denominator = self.unit_normal.dot(&ray.direction_no_unit);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = self.unit_normal.dot(&(self.point - ray.origin)) / denominator;
+        if !interval.contains(&t) {
+            return None;
+        }
+        let pos = ray.at(t);
+        let planar = pos - self.point;
+        //wrapped into 0..1 like the uvs of the other shapes
+        let uv = (planar.dot(&self.tangent).rem_euclid(1.), planar.dot(&self.bitangent).rem_euclid(1.));
+        return Some(HitRecord::with_unit_normal(pos, self.unit_normal, t, ray.direction_no_unit, self.material.clone())
+            .with_uv(uv));
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return Aabb::infinite();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plane(){
+        let material = Arc::new(Material::builder().build());
+        let plane = Plane::new(Vec3d::new(0., -1., 0.), Vec3d::new(0., 3., 0.), material);
+        let rec = plane.hit(&Ray::new(Vec3d::new(100., 0., -200.), Vec3d::new(1., -1., 0.)), 0.001..f64::INFINITY).unwrap();
+        assert_eq!((1., Vec3d::new(101., -1., -200.), Vec3d::up(), true), (rec.t, rec.pos, rec.normal, rec.front_face));
+        let below = plane.hit(&Ray::new(Vec3d::new(0., -3., 0.), Vec3d::up()), 0.001..f64::INFINITY).unwrap();
+        assert_eq!((2., Vec3d::down(), false), (below.t, below.normal, below.front_face));
+        assert!(plane.hit(&Ray::new(Vec3d::zero(), Vec3d::right()), 0.001..f64::INFINITY).is_none());
+        assert!(plane.hit(&Ray::new(Vec3d::zero(), Vec3d::up()), 0.001..f64::INFINITY).is_none());
+        assert!(!plane.bounding_box().is_finite());
+    }
+}
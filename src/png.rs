@@ -0,0 +1,443 @@
+use crate::image::{ColorSpace, Image};
+use crate::vec3d::Vec3d;
+use crate::zlib;
+
+//writes non-interlaced truecolor PNG with 8 or 16 bits per channel, optional alpha and tEXt chunks,
+//reads every standard color type, bit depth and interlacing
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+//x, y, x step and y step of the seven adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum PngBitDepth {
+    Eight,
+    Sixteen,
+}
+
+impl PngBitDepth {
+    fn bits(&self) -> u8 {
+        return match self {
+            PngBitDepth::Eight => 8,
+            PngBitDepth::Sixteen => 16,
+        };
+    }
+}
+
+//the alpha channel is written if the image has one, text is a list of (keyword, text) pairs
+pub(crate) fn encode(image: &Image, bit_depth: PngBitDepth, text: &[(String, String)]) -> Vec<u8> {
+    let alpha = image.has_alpha();
+    let channels = if alpha { 4 } else { 3 };
+    let bytes_per_pixel = channels * bit_depth.bits() as usize / 8;
+
+    let mut out = Vec::new();
+    out.extend(SIGNATURE);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((image.width as u32).to_be_bytes());
+    header.extend((image.height as u32).to_be_bytes());
+    header.push(bit_depth.bits());
+    header.push(if alpha { COLOR_TYPE_RGBA } else { COLOR_TYPE_RGB });
+    //deflate compression, adaptive filtering, no interlacing
+    header.extend([0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+
+    //srgb with perceptual rendering intent, gAMA for readers without srgb support
+    write_chunk(&mut out, b"sRGB", &[0]);
+    write_chunk(&mut out, b"gAMA", &45455u32.to_be_bytes());
+
+    for (keyword, value) in text {
+        write_chunk(&mut out, b"tEXt", &text_chunk(keyword, value));
+    }
+
+    let mut filtered = Vec::with_capacity(image.height * (1 + image.width * bytes_per_pixel));
+    let mut previous_row = vec![0u8; image.width * bytes_per_pixel];
+    let mut row_bytes = Vec::with_capacity(image.width * bytes_per_pixel);
+    for row in 0..image.height {
+        row_bytes.clear();
+        for col in 0..image.width {
+            let mut color = image.pixel_color(row, col);
+            let mut coverage = 1.;
+            if alpha {
+                //png stores straight alpha, the framebuffer holds colors premultiplied by the coverage
+                coverage = image.pixel_alpha(row, col).clamp(0., 1.);
+                if coverage > 0. {
+                    color = color / coverage;
+                }
+            }
+            let color = image.display_color(color);
+            let mut samples = vec![color.x, color.y, color.z];
+            if alpha {
+                samples.push(coverage);
+            }
+            for sample in samples {
+                match bit_depth {
+                    PngBitDepth::Eight => row_bytes.push((255.999 * sample) as u8),
+                    PngBitDepth::Sixteen => row_bytes.extend(((65535.999 * sample) as u16).to_be_bytes()),
+                }
+            }
+        }
+        filter_row(&mut filtered, &row_bytes, &previous_row, bytes_per_pixel);
+        std::mem::swap(&mut previous_row, &mut row_bytes);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib::compress(&filtered));
+    write_chunk(&mut out, b"IEND", &[]);
+    return out;
+}
+
+//alpha is dropped, samples are converted to linear according to color_space
+pub(crate) fn decode(data: &[u8], color_space: ColorSpace) -> std::io::Result<Image> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(invalid("not a png file"));
+    }
+    let mut pos = SIGNATURE.len();
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    loop {
+        let length = read_u32(data, pos)? as usize;
+        let chunk = data.get(pos + 4..pos + 8 + length).ok_or(invalid("png chunk is truncated"))?;
+        let crc = read_u32(data, pos + 8 + length)?;
+        if crc32(chunk) != crc {
+            return Err(invalid("png chunk checksum does not match"));
+        }
+        let (chunk_type, body) = chunk.split_at(4);
+        match chunk_type {
+            b"IHDR" => header = Some(PngHeader::parse(body)?),
+            b"PLTE" => palette = body.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            //ancillary chunks are optional, critical ones have an upper case first letter
+            _ if chunk_type[0].is_ascii_uppercase() => {
+                return Err(invalid(&format!("unsupported critical png chunk {}", String::from_utf8_lossy(chunk_type))));
+            }
+            _ => {}
+        }
+        pos += 12 + length;
+    }
+    let header = header.ok_or(invalid("png header is missing"))?;
+    if header.color_type == COLOR_TYPE_PALETTE && palette.is_empty() {
+        return Err(invalid("png palette is missing"));
+    }
+    let raw = zlib::decompress(&compressed)?;
+    //the rows are checked one by one below, this only keeps the header from allocating more than the data holds
+    let bits = header.width.checked_mul(header.height).and_then(|pixels| pixels.checked_mul(header.bits_per_pixel()));
+    if bits.is_none_or(|bits| bits.div_ceil(8) > raw.len()) {
+        return Err(invalid("png image data is truncated"));
+    }
+
+    let mut image = Image::new(header.height, header.width);
+    let passes: Vec<(usize, usize, usize, usize)> = if header.interlaced { ADAM7.to_vec() } else { vec![(0, 0, 1, 1)] };
+    let mut offset = 0;
+    for (x0, y0, dx, dy) in passes {
+        if x0 >= header.width || y0 >= header.height {
+            continue;
+        }
+        let pass_width = (header.width - x0).div_ceil(dx);
+        let pass_height = (header.height - y0).div_ceil(dy);
+        let stride = (pass_width * header.bits_per_pixel()).div_ceil(8);
+        let size = pass_height * (stride + 1);
+        let filtered = raw.get(offset..offset + size).ok_or(invalid("png image data is truncated"))?;
+        offset += size;
+        let rows = unfilter(filtered, stride, header.bits_per_pixel().div_ceil(8))?;
+        for (pass_row, row_bytes) in rows.chunks_exact(stride).enumerate() {
+            for pass_col in 0..pass_width {
+                let color = header.pixel(row_bytes, pass_col, &palette, color_space)?;
+                image.set_pixel_color(y0 + pass_row * dy, x0 + pass_col * dx, color);
+            }
+        }
+    }
+    return Ok(image);
+}
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn parse(data: &[u8]) -> std::io::Result<Self> {
+        if data.len() != 13 {
+            return Err(invalid("png header has the wrong size"));
+        }
+        let header = Self{
+            width: read_u32(data, 0)? as usize,
+            height: read_u32(data, 4)? as usize,
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: data[12] == 1,
+        };
+        let allowed_depths: &[u8] = match header.color_type {
+            COLOR_TYPE_GRAY => &[1, 2, 4, 8, 16],
+            COLOR_TYPE_PALETTE => &[1, 2, 4, 8],
+            COLOR_TYPE_RGB | COLOR_TYPE_GRAY_ALPHA | COLOR_TYPE_RGBA => &[8, 16],
+            _ => return Err(invalid(&format!("invalid png color type {}", header.color_type))),
+        };
+        if !allowed_depths.contains(&header.bit_depth) {
+            return Err(invalid(&format!("invalid bit depth {} for png color type {}", header.bit_depth, header.color_type)));
+        }
+        if header.width == 0 || header.height == 0 || data[10] != 0 || data[11] != 0 || data[12] > 1 {
+            return Err(invalid("unsupported png header"));
+        }
+        return Ok(header);
+    }
+
+    fn channels(&self) -> usize {
+        return match self.color_type {
+            COLOR_TYPE_GRAY | COLOR_TYPE_PALETTE => 1,
+            COLOR_TYPE_GRAY_ALPHA => 2,
+            COLOR_TYPE_RGB => 3,
+            _ => 4,
+        };
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        return self.channels() * self.bit_depth as usize;
+    }
+
+    //sample of the channel of the pixel in the row, not normalized
+    fn sample(&self, row: &[u8], col: usize, channel: usize) -> u16 {
+        let index = col * self.channels() + channel;
+        return match self.bit_depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+            8 => row[index] as u16,
+            //packed starting with the most significant bits
+            bits => {
+                let bit = index * bits as usize;
+                let shift = 8 - bits as usize - bit % 8;
+                ((row[bit / 8] >> shift) & ((1u16 << bits) - 1) as u8) as u16
+            }
+        };
+    }
+
+    fn pixel(&self, row: &[u8], col: usize, palette: &[[u8; 3]], color_space: ColorSpace) -> std::io::Result<Vec3d> {
+        let max = ((1u32 << self.bit_depth) - 1) as f64;
+        let value = |channel: usize| color_space.to_linear(self.sample(row, col, channel) as f64 / max);
+        return Ok(match self.color_type {
+            COLOR_TYPE_GRAY | COLOR_TYPE_GRAY_ALPHA => {
+                let gray = value(0);
+                Vec3d::new(gray, gray, gray)
+            }
+            COLOR_TYPE_PALETTE => {
+                let rgb = palette.get(self.sample(row, col, 0) as usize).ok_or(invalid("png palette index out of range"))?;
+                let channel = |c: u8| color_space.to_linear(c as f64 / 255.);
+                Vec3d::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2]))
+            }
+            _ => Vec3d::new(value(0), value(1), value(2)),
+        });
+    }
+}
+
+//reverses the per row filters, bytes_per_pixel is at least 1
+fn unfilter(data: &[u8], stride: usize, bytes_per_pixel: usize) -> std::io::Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    for (index, row) in data.chunks_exact(stride + 1).enumerate() {
+        let start = out.len();
+        let filter = row[0];
+        for (i, &byte) in row[1..].iter().enumerate() {
+            let left = if i >= bytes_per_pixel { out[start + i - bytes_per_pixel] } else { 0 };
+            let up = if index > 0 { out[start + i - stride] } else { 0 };
+            let upper_left = if index > 0 && i >= bytes_per_pixel { out[start + i - stride - bytes_per_pixel] } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, upper_left),
+                _ => return Err(invalid(&format!("invalid png filter type {}", filter))),
+            };
+            out.push(byte.wrapping_add(predictor));
+        }
+    }
+    return Ok(out);
+}
+
+fn read_u32(data: &[u8], pos: usize) -> std::io::Result<u32> {
+    let bytes = data.get(pos..pos + 4).ok_or(invalid("png file is truncated"))?;
+    return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(chunk_type);
+    out.extend(data);
+    //the checksum covers type and data but not the length
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+//keyword of 1 to 79 latin-1 characters, null separator, latin-1 text
+fn text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let latin1 = |s: &str| -> Vec<u8> {
+        s.chars().map(|c| if (c as u32) < 256 && c != '\0' { c as u8 } else { b'?' }).collect()
+    };
+    let mut keyword = latin1(keyword.trim());
+    keyword.truncate(79);
+    assert!(!keyword.is_empty(), "tEXt keyword must not be empty");
+    let mut data = keyword;
+    data.push(0);
+    data.extend(latin1(text));
+    return data;
+}
+
+//picks the filter with the smallest sum of absolute signed differences, the usual heuristic
+fn filter_row(out: &mut Vec<u8>, row: &[u8], previous: &[u8], bytes_per_pixel: usize) {
+    let left = |i: usize| if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+    let upper_left = |i: usize| if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+    for filter in 0..5u8 {
+        let candidate: Vec<u8> = (0..row.len()).map(|i| {
+            let predictor = match filter {
+                0 => 0,
+                1 => left(i),
+                2 => previous[i],
+                3 => ((left(i) as u16 + previous[i] as u16) / 2) as u8,
+                _ => paeth(left(i), previous[i], upper_left(i)),
+            };
+            row[i].wrapping_sub(predictor)
+        }).collect();
+        let cost = candidate.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>();
+        if best.as_ref().is_none_or(|(best_cost, _, _)| cost < *best_cost) {
+            best = Some((cost, filter, candidate));
+        }
+    }
+    let (_, filter, bytes) = best.unwrap();
+    out.push(filter);
+    out.extend(bytes);
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    return if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c };
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    return table;
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    return crc ^ 0xffffffff;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32(){
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        //crc of an empty IEND chunk as found at the end of every png
+        assert_eq!(0xae426082, crc32(b"IEND"));
+    }
+
+    #[test]
+    fn test_paeth(){
+        assert_eq!(10, paeth(10, 20, 20));
+        assert_eq!(20, paeth(10, 20, 10));
+        assert_eq!(20, paeth(10, 20, 5));
+    }
+
+    #[test]
+    fn test_text_chunk(){
+        assert_eq!(b"Samples per pixel\x00100".to_vec(), text_chunk("Samples per pixel", "100"));
+        assert_eq!(b"Seed\x00\xe9?".to_vec(), text_chunk(" Seed ", "é€"));
+    }
+
+    #[test]
+    fn test_layout(){
+        let mut image = Image::new(1, 2);
+        image.set_pixels(vec![Vec3d::new(1., 0., 0.25), Vec3d::new(0., 1., 1.)]);
+        let data = encode(&image, PngBitDepth::Sixteen, &[("Software".to_string(), "raytracer".to_string())]);
+        assert_eq!(SIGNATURE, data[0..8]);
+        //IHDR: width, height, bit depth and color type
+        assert_eq!(b"IHDR", &data[12..16]);
+        assert_eq!([0, 0, 0, 2, 0, 0, 0, 1, 16, COLOR_TYPE_RGB], data[16..26]);
+        assert_eq!([0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82], data[data.len() - 12..]);
+        assert!(data.windows(22).any(|w| w == b"tEXtSoftware\x00raytracer"));
+    }
+
+    fn png_file(header: [u8; 13], palette: &[u8], raw: &[u8]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        write_chunk(&mut out, b"IHDR", &header);
+        if !palette.is_empty() {
+            write_chunk(&mut out, b"PLTE", palette);
+        }
+        write_chunk(&mut out, b"IDAT", &zlib::compress(raw));
+        write_chunk(&mut out, b"IEND", &[]);
+        return out;
+    }
+
+    #[test]
+    fn test_decode_palette_and_gray(){
+        //3x2, 2 bit palette indices 0 1 2 / 3 0 1
+        let header = [0, 0, 0, 3, 0, 0, 0, 2, 2, COLOR_TYPE_PALETTE, 0, 0, 0];
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let raw = [0, 0b00011000, 0, 0b11000100];
+        let image = decode(&png_file(header, &palette, &raw), ColorSpace::Linear).unwrap();
+        assert_eq!(Vec3d::new(0., 1., 0.), image.pixel_color(0, 2));
+        assert_eq!(Vec3d::new(0., 0., 1.), image.pixel_color(1, 0));
+        assert_eq!(Vec3d::new(1., 0., 0.), image.pixel_color(1, 2));
+
+        //1x2 16 bit gray with alpha, the second row uses the up filter
+        let header = [0, 0, 0, 1, 0, 0, 0, 2, 16, COLOR_TYPE_GRAY_ALPHA, 0, 0, 0];
+        let raw = [0, 0x80, 0x00, 0xff, 0xff, 2, 0x10, 0x00, 0x00, 0x00];
+        let image = decode(&png_file(header, &[], &raw), ColorSpace::Linear).unwrap();
+        assert_eq!(0x8000 as f64 / 65535., image.pixel_color(0, 0).y);
+        assert_eq!(0x9000 as f64 / 65535., image.pixel_color(1, 0).x);
+
+        //a header far larger than the image data is rejected before allocating
+        let header = [0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 8, COLOR_TYPE_RGB, 0, 0, 0];
+        assert_eq!("png image data is truncated", decode(&png_file(header, &[], &raw), ColorSpace::Linear).err().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_decode_interlaced(){
+        //3x3 8 bit gray with value row * 3 + col, every adam7 pass with pixels in it
+        let header = [0, 0, 0, 3, 0, 0, 0, 3, 8, COLOR_TYPE_GRAY, 0, 0, 1];
+        let raw = [0, 0, 0, 2, 0, 6, 8, 0, 1, 0, 7, 0, 3, 4, 5];
+        let image = decode(&png_file(header, &[], &raw), ColorSpace::Linear).unwrap();
+        for row in 0..3 {
+            for col in 0..3 {
+                assert_eq!((row * 3 + col) as f64 / 255., image.pixel_color(row, col).x);
+            }
+        }
+        let mut corrupted = png_file(header, &[], &raw);
+        corrupted[20] ^= 1;
+        assert!(decode(&corrupted, ColorSpace::Linear).is_err());
+    }
+}
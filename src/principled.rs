@@ -0,0 +1,299 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use rand::{Rng, RngCore};
+use crate::lerp_vec3d;
+use crate::bsdf::{Bsdf, BsdfSample, ConductorFresnel, cosine_hemisphere};
+use crate::hit::HitRecord;
+use crate::microfacet;
+use crate::texture::{Constant, Texture};
+use crate::tonemap::luminance;
+use crate::vec3d::Vec3d;
+
+//disney style material made of a clearcoat on top of a base of metal, glass and plastic
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct PrincipledParameters {
+    pub(crate) base_color: Vec3d,
+    pub(crate) metallic: f64,
+    pub(crate) roughness: f64,
+    //reflectance of the opaque dielectric at normal incidence is 0.08 * specular, 0.5 matches an index of 1.5
+    pub(crate) specular: f64,
+    //tints the dielectric reflection towards the base color
+    pub(crate) specular_tint: f64,
+    //grazing retro reflection of cloth
+    pub(crate) sheen: f64,
+    pub(crate) sheen_tint: f64,
+    pub(crate) clearcoat: f64,
+    pub(crate) clearcoat_roughness: f64,
+    pub(crate) transmission: f64,
+    //index of the transmissive part
+    pub(crate) refraction_index: f64,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> Self {
+        Self{
+            base_color: Vec3d::new(0.8, 0.8, 0.8),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.,
+            sheen: 0.,
+            sheen_tint: 0.5,
+            clearcoat: 0.,
+            clearcoat_roughness: 0.03,
+            transmission: 0.,
+            refraction_index: 1.5,
+        }
+    }
+}
+
+//reflectance of the clearcoat at normal incidence, an index of 1.5
+const CLEARCOAT_F0: f64 = 0.04;
+
+const CLEARCOAT: usize = 0;
+const SPECULAR: usize = 1;
+const TRANSMISSION: usize = 2;
+const DIFFUSE: usize = 3;
+
+//every layer removes the energy it reflects from the layers below it, so the sum stays below 1
+pub(crate) struct PrincipledBsdf {
+    //indexed by CLEARCOAT, SPECULAR, TRANSMISSION and DIFFUSE
+    lobes: [Box<dyn Bsdf + Send + Sync>; 4],
+    clearcoat: f64,
+    specular_f0: Vec3d,
+    //fractions of the base covered by the specular, transmission and diffuse lobes
+    opaque: f64,
+    transmissive: f64,
+    diffuse: f64,
+    dielectric_f0: f64,
+    delta: bool,
+}
+
+impl PrincipledBsdf {
+    //the diffuse layer takes its color from the texture if given
+    pub(crate) fn new(parameters: &PrincipledParameters, base_color_texture: Option<Arc<dyn Texture + Send + Sync>>) -> Self {
+        let white = Vec3d::new(1., 1., 1.);
+        let base_color = parameters.base_color;
+        let metallic = parameters.metallic.clamp(0., 1.);
+        let transmission = parameters.transmission.clamp(0., 1.);
+        let tint = lerp_vec3d(white, normalized_hue(base_color), parameters.specular_tint.clamp(0., 1.));
+        let dielectric_f0 = (0.08 * parameters.specular) * tint;
+        let specular_f0 = lerp_vec3d(dielectric_f0, base_color, metallic);
+        let sheen_color = parameters.sheen * lerp_vec3d(white, normalized_hue(base_color), parameters.sheen_tint.clamp(0., 1.));
+
+        let lobes: [Box<dyn Bsdf + Send + Sync>; 4] = [
+            microfacet::conductor(parameters.clearcoat_roughness, ConductorFresnel::Schlick(CLEARCOAT_F0 * white), white),
+            microfacet::conductor(parameters.roughness, ConductorFresnel::Schlick(specular_f0), white),
+            microfacet::dielectric(parameters.roughness, parameters.refraction_index, base_color),
+            Box::new(DiffuseSheen{
+                albedo: base_color_texture.unwrap_or(Arc::new(Constant::new(base_color))),
+                sheen: sheen_color,
+            }),
+        ];
+        let clearcoat = parameters.clearcoat.clamp(0., 1.);
+        let opaque = 1. - (1. - metallic) * transmission;
+        let transmissive = (1. - metallic) * transmission;
+        let diffuse = (1. - metallic) * (1. - transmission);
+        let weights = [clearcoat, opaque, transmissive, diffuse];
+        let delta = lobes.iter().zip(weights).all(|(lobe, weight)| weight <= 0. || lobe.is_delta());
+        Self{
+            lobes,
+            clearcoat,
+            specular_f0,
+            opaque,
+            transmissive,
+            diffuse,
+            dielectric_f0: luminance(dielectric_f0),
+            delta,
+        }
+    }
+
+    //factors of the lobes and the probabilities of sampling them for the outgoing direction
+    fn weights(&self, hit: &HitRecord, wo: Vec3d) -> ([f64; 4], [f64; 4]) {
+        //inside the object only the glass lobe that let the path in applies
+        if !hit.front_face {
+            let mut weights = [0.; 4];
+            weights[TRANSMISSION] = 1.;
+            return (weights, weights);
+        }
+        let cos_o = wo.dot(&hit.normal).clamp(0., 1.);
+        let coat_reflectance = schlick(CLEARCOAT_F0, cos_o);
+        let below_coat = 1. - self.clearcoat * coat_reflectance;
+        let factors = [
+            self.clearcoat,
+            below_coat * self.opaque,
+            below_coat * self.transmissive,
+            below_coat * self.diffuse * (1. - schlick(self.dielectric_f0, cos_o)),
+        ];
+        //roughly the energy each lobe reflects
+        let specular_reflectance = luminance(ConductorFresnel::Schlick(self.specular_f0).eval(cos_o));
+        let energy = [factors[CLEARCOAT] * coat_reflectance, factors[SPECULAR] * specular_reflectance, factors[TRANSMISSION], factors[DIFFUSE]];
+        let total = energy.iter().sum::<f64>();
+        if total <= 0. {
+            return (factors, [0.; 4]);
+        }
+        return (factors, energy.map(|e| e / total));
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d {
+        let (factors, _) = self.weights(hit, wo);
+        return self.lobes.iter().zip(factors)
+            .filter(|(_, factor)| *factor > 0.)
+            .map(|(lobe, factor)| factor * lobe.eval(hit, wo, wi))
+            .sum();
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let (factors, probabilities) = self.weights(hit, wo);
+        let mut chance = rng.gen::<f64>();
+        let index = probabilities.iter().position(|p| {
+            chance -= p;
+            chance < 0.
+        }).or(probabilities.iter().rposition(|p| *p > 0.))?;
+        let mut sample = self.lobes[index].sample(hit, wo, rng)?;
+        if sample.specular {
+            sample.weight = sample.weight * (factors[index] / probabilities[index]);
+            sample.pdf *= probabilities[index];
+            return Some(sample);
+        }
+        //the other smooth lobes could have produced the same direction
+        let pdf = self.pdf(hit, wo, sample.direction);
+        if pdf <= 0. {
+            return None;
+        }
+        sample.weight = self.eval(hit, wo, sample.direction) / pdf;
+        sample.pdf = pdf;
+        return Some(sample);
+    }
+
+    fn pdf(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> f64 {
+        let (_, probabilities) = self.weights(hit, wo);
+        return self.lobes.iter().zip(probabilities)
+            .filter(|(_, p)| *p > 0.)
+            .map(|(lobe, p)| p * lobe.pdf(hit, wo, wi))
+            .sum();
+    }
+
+    fn is_delta(&self) -> bool {
+        return self.delta;
+    }
+}
+
+//lambertian base with the sheen added towards grazing angles
+struct DiffuseSheen {
+    albedo: Arc<dyn Texture + Send + Sync>,
+    sheen: Vec3d,
+}
+
+impl Bsdf for DiffuseSheen {
+    fn eval(&self, hit: &HitRecord, wo: Vec3d, wi: Vec3d) -> Vec3d {
+        let cos = wi.dot(&hit.normal);
+        if cos <= 0. || wo.dot(&hit.normal) <= 0. {
+            return Vec3d::zero();
+        }
+        let cos_d = wi.dot(&(wo + wi).unit()).clamp(0., 1.);
+        return (self.albedo.value(hit.uv, hit.pos) / PI + self.sheen * (1. - cos_d).powi(5)) * cos;
+    }
+
+    fn sample(&self, hit: &HitRecord, wo: Vec3d, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let direction = cosine_hemisphere(hit.normal, rng);
+        let pdf = self.pdf(hit, wo, direction);
+        if pdf <= 0. {
+            return None;
+        }
+        return Some(BsdfSample{
+            direction,
+            weight: self.eval(hit, wo, direction) / pdf,
+            pdf,
+            specular: false,
+        });
+    }
+
+    fn pdf(&self, hit: &HitRecord, _wo: Vec3d, wi: Vec3d) -> f64 {
+        return wi.dot(&hit.normal).max(0.) / PI;
+    }
+}
+
+fn schlick(f0: f64, cos: f64) -> f64 {
+    return f0 + (1. - f0) * (1. - cos).powi(5);
+}
+
+//color with a luminance of 1, used for tinting
+fn normalized_hue(color: Vec3d) -> Vec3d {
+    let l = luminance(color);
+    return if l > 0. { color / l } else { Vec3d::new(1., 1., 1.) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::material::Material;
+
+    fn hit_from_above() -> HitRecord {
+        let material = Arc::new(Material::builder().build());
+        return HitRecord::with_unit_normal(Vec3d::zero(), Vec3d::up(), 1., Vec3d::down(), material);
+    }
+
+    //mean throughput of one bounce under uniform white illumination
+    fn albedo(parameters: PrincipledParameters, wo: Vec3d) -> Vec3d {
+        let bsdf = PrincipledBsdf::new(&parameters, None);
+        let hit = hit_from_above();
+        let mut rng = StdRng::from_seed([6; 32]);
+        let samples = 50000;
+        return (0..samples).filter_map(|_| bsdf.sample(&hit, wo, &mut rng)).map(|s| s.weight).sum::<Vec3d>() / samples as f64;
+    }
+
+    #[test]
+    fn test_energy_stays_below_one(){
+        let white = Vec3d::new(1., 1., 1.);
+        let grazing = Vec3d::new(0.95, 0.3, 0.).unit();
+        let cases = [
+            PrincipledParameters{ base_color: white, ..Default::default() },
+            PrincipledParameters{ base_color: white, metallic: 1., roughness: 0.2, ..Default::default() },
+            PrincipledParameters{ base_color: white, clearcoat: 1., sheen: 1., specular: 1., ..Default::default() },
+            PrincipledParameters{ base_color: white, metallic: 0.5, clearcoat: 1., roughness: 0.8, ..Default::default() },
+        ];
+        for parameters in cases {
+            for wo in [Vec3d::up(), grazing] {
+                let a = albedo(parameters, wo);
+                assert!(a.x <= 1.02, "{:?} {:?}", parameters, a);
+            }
+        }
+        //a white plastic reflects most of the light
+        assert!(albedo(cases[0], Vec3d::up()).x > 0.9);
+    }
+
+    #[test]
+    fn test_sample_matches_eval_and_pdf(){
+        let parameters = PrincipledParameters{ metallic: 0.3, clearcoat: 0.5, clearcoat_roughness: 0.2, sheen: 0.5, ..Default::default() };
+        let bsdf = PrincipledBsdf::new(&parameters, None);
+        let hit = hit_from_above();
+        let wo = Vec3d::new(0.4, 0.8, 0.1).unit();
+        let mut rng = StdRng::from_seed([8; 32]);
+        for _ in 0..1000 {
+            if let Some(sample) = bsdf.sample(&hit, wo, &mut rng) {
+                assert!(!sample.specular);
+                let expected = bsdf.eval(&hit, wo, sample.direction) / bsdf.pdf(&hit, wo, sample.direction);
+                assert!((expected - sample.weight).length() < 1e-9 * (1. + expected.length()));
+            }
+        }
+        assert!(!bsdf.is_delta());
+    }
+
+    #[test]
+    fn test_smooth_glass_is_delta(){
+        let parameters = PrincipledParameters{ transmission: 1., roughness: 0., ..Default::default() };
+        let bsdf = PrincipledBsdf::new(&parameters, None);
+        assert!(bsdf.is_delta());
+        let hit = hit_from_above();
+        let mut rng = StdRng::from_seed([2; 32]);
+        let refracted = (0..1000).filter_map(|_| bsdf.sample(&hit, Vec3d::up(), &mut rng)).filter(|s| s.direction.y < 0.).count();
+        //about 4% is reflected at normal incidence
+        assert!(refracted > 930 && refracted < 990, "{}", refracted);
+    }
+}
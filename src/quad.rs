@@ -0,0 +1,169 @@
+use std::ops::Range;
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::light::QuadLight;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+//quads have no extent along their normal, pad them so the slab test stays robust
+const BBOX_PADDING: f64 = 1e-6;
+
+//parallelogram spanned by the edges u and v from the corner, uv is (0, 0) at the corner and (1, 1) opposite of it
+pub(crate) struct Quad {
+    corner: Vec3d,
+    u: Vec3d,
+    v: Vec3d,
+    unit_normal: Vec3d,
+    //u x v / |u x v|^2, turns the cross products of the hit point with the edges into plane coordinates
+    w: Vec3d,
+    material: Arc<Material>,
+}
+
+impl Quad {
+    pub(crate) fn new(corner: Vec3d, u: Vec3d, v: Vec3d, material: Arc<Material>) -> Self {
+        let n = u.cross(v);
+        Self{
+            corner,
+            u,
+            v,
+            unit_normal: n.unit(),
+            w: n / n.length_squared(),
+            material,
+        }
+    }
+
+    //area light for next event estimation if the material emits
+    pub(crate) fn light(&self) -> Option<QuadLight> {
+        let radiance = self.material.emitted();
+        if radiance == Vec3d::zero() {
+            return None;
+        }
+        return Some(QuadLight::new(self.corner, self.u, self.v, radiance));
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let denominator = self.unit_normal.dot(&ray.direction_no_unit);
+        //parallel to the plane
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = self.unit_normal.dot(&(self.corner - ray.origin)) / denominator;
+        if !interval.contains(&t) {
+            return None;
+        }
+        let pos = ray.at(t);
+        let planar = pos - self.corner;
+        let alpha = self.w.dot(&planar.cross(self.v));
+        let beta = self.w.dot(&self.u.cross(planar));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+        return Some(HitRecord::with_unit_normal(pos, self.unit_normal, t, ray.direction_no_unit, self.material.clone())
+            .with_uv((alpha, beta)));
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let padding = Vec3d::new(BBOX_PADDING, BBOX_PADDING, BBOX_PADDING);
+        let bbox = Aabb::new(self.corner, self.corner + self.u + self.v)
+            .expand(&(self.corner + self.u))
+            .expand(&(self.corner + self.v));
+        return Aabb::new(bbox.min - padding, bbox.max + padding);
+    }
+}
+
+//axis-aligned box made of six quads, with uvs running over every face
+pub(crate) struct Cuboid {
+    sides: [Quad; 6],
+    bbox: Aabb,
+}
+
+impl Cuboid {
+    //any two opposite corners
+    pub(crate) fn new(a: Vec3d, b: Vec3d, material: Arc<Material>) -> Self {
+        let bbox = Aabb::new(a, b);
+        let (min, max) = (bbox.min, bbox.max);
+        let dx = Vec3d::new(max.x - min.x, 0., 0.);
+        let dy = Vec3d::new(0., max.y - min.y, 0.);
+        let dz = Vec3d::new(0., 0., max.z - min.z);
+        //front, right, back, left, top, bottom, with the edges seen from outside in counterclockwise order
+        let sides = [
+            Quad::new(Vec3d::new(min.x, min.y, max.z), dx, dy, material.clone()),
+            Quad::new(Vec3d::new(max.x, min.y, max.z), -dz, dy, material.clone()),
+            Quad::new(Vec3d::new(max.x, min.y, min.z), -dx, dy, material.clone()),
+            Quad::new(Vec3d::new(min.x, min.y, min.z), dz, dy, material.clone()),
+            Quad::new(Vec3d::new(min.x, max.y, max.z), dx, -dz, material.clone()),
+            Quad::new(Vec3d::new(min.x, min.y, min.z), dx, dz, material),
+        ];
+        Self{
+            sides,
+            bbox,
+        }
+    }
+
+    //one area light per side if the material emits
+    pub(crate) fn lights(&self) -> Vec<QuadLight> {
+        return self.sides.iter().filter_map(Quad::light).collect();
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let mut closest_so_far = interval.end;
+        let mut temp_rec = None;
+        for side in &self.sides {
+            if let Some(rec) = side.hit(ray, interval.start..closest_so_far) {
+                closest_so_far = rec.t;
+                temp_rec = Some(rec);
+            }
+        }
+        return temp_rec;
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let padding = Vec3d::new(BBOX_PADDING, BBOX_PADDING, BBOX_PADDING);
+        return Aabb::new(self.bbox.min - padding, self.bbox.max + padding);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad(){
+        let material = Arc::new(Material::builder().build());
+        let quad = Quad::new(Vec3d::new(-1., -1., -2.), Vec3d::new(2., 0., 0.), Vec3d::new(0., 4., 0.), material);
+        let rec = quad.hit(&Ray::new(Vec3d::zero(), Vec3d::new(0.5, 1., -2.)), 0.001..f64::INFINITY).unwrap();
+        assert_eq!((1., Vec3d::new(0.5, 1., -2.), Vec3d::backward(), true), (rec.t, rec.pos, rec.normal, rec.front_face));
+        assert_eq!((0.75, 0.5), rec.uv);
+        //outside the edges, parallel and behind
+        assert!(quad.hit(&Ray::new(Vec3d::zero(), Vec3d::new(1.5, 0., -2.)), 0.001..f64::INFINITY).is_none());
+        assert!(quad.hit(&Ray::new(Vec3d::zero(), Vec3d::right()), 0.001..f64::INFINITY).is_none());
+        assert!(quad.hit(&Ray::new(Vec3d::zero(), Vec3d::backward()), 0.001..f64::INFINITY).is_none());
+        let bbox = quad.bounding_box();
+        assert!(bbox.min.z < -2. && bbox.max.z > -2. && bbox.max.y > 3.);
+    }
+
+    #[test]
+    fn test_cuboid(){
+        let material = Arc::new(Material::builder().build());
+        let cuboid = Cuboid::new(Vec3d::new(1., 2., -1.), Vec3d::new(-1., 0., -3.), material);
+        //every face is hit from outside with an outward normal
+        let center = Vec3d::new(0., 1., -2.);
+        for direction in [Vec3d::right(), Vec3d::left(), Vec3d::up(), Vec3d::down(), Vec3d::forward(), Vec3d::backward()] {
+            let origin = center + 5. * direction;
+            let rec = cuboid.hit(&Ray::new(origin, -direction), 0.001..f64::INFINITY).unwrap();
+            assert!((rec.t - 4.).abs() < 1e-12);
+            assert_eq!((direction, true), (rec.normal, rec.front_face));
+            assert!(rec.uv.0 >= 0. && rec.uv.0 <= 1. && rec.uv.1 >= 0. && rec.uv.1 <= 1.);
+            assert!(cuboid.sides.iter().any(|side| side.unit_normal == direction));
+        }
+        //from inside the back face is hit
+        let rec = cuboid.hit(&Ray::new(center, Vec3d::up()), 0.001..f64::INFINITY).unwrap();
+        assert_eq!((1., false), (rec.t, rec.front_face));
+    }
+}
@@ -7,15 +7,23 @@ use crate::vec3d::Vec3d;
 pub(crate) struct Ray{
     pub(crate) origin : Vec3d,
     pub(crate) direction_no_unit: Vec3d,
+    //moment within the camera shutter the ray was sent at, 0 to 1 over the motion of moving objects
+    pub(crate) time: f64,
 }
 
 impl Ray{
     pub(crate) fn new(origin: Vec3d, direction_no_unit: Vec3d) -> Self{
         Self{
             origin,
-            direction_no_unit
+            direction_no_unit,
+            time: 0.,
         }
     }
+
+    pub(crate) fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
+    }
     //
     // pub(crate) fn new_rng_offset(mut origin: Vec3d, direction_no_unit: Vec3d) -> Self{
     //     Self{
@@ -0,0 +1,1634 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rand::{Rng, SeedableRng};
+use crate::bsdf::ConductorFresnel;
+use crate::camera::Camera;
+use crate::environment::EnvironmentLight;
+use crate::sky::{self, PreethamSky, SunLight};
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::hit::Hittable;
+use crate::instance::Instance;
+use crate::light::{DirectionalLight, Light, PointLight, SphereLight, SpotLight};
+use crate::material::Material;
+use crate::medium::{HomogeneousMedium, MediumRegion};
+use crate::volume::{GridMedium, VoxelGrid};
+use crate::obj;
+use crate::principled::PrincipledParameters;
+use crate::plane::Plane;
+use crate::quad::{Cuboid, Quad};
+use crate::sphere::{MovingSphere, Sphere};
+use crate::texture::{Checker, Constant, ImageTexture, NoisePattern, NoiseTexture, Perlin, Texture, WrapMode};
+use crate::transform::{AnimatedTransform, Keyframe, Transform};
+use crate::triangle::Triangle;
+use crate::vec3d::Vec3d;
+use crate::image::{ColorSpace, Image, OutputFormat};
+use crate::tonemap::{DisplayTransform, ToneMapping};
+
+//text scene description:
+//  # comment
+//  [section optional_name]
+//  key = value
+//sections: render, camera, output, texture <name>, material <name>, principled <name>, environment, sky,
+//point_light, spot_light, directional_light, sphere, triangle, quad, box, plane, random_spheres, obj,
+//instance, scatter, medium, atmosphere, volume
+//named shape sections are only placed by instance sections or fill with a medium
+
+#[derive(Debug)]
+pub(crate) struct SceneError {
+    pub(crate) file: String,
+    //0 if the error is not tied to a line, e.g. the file could not be read
+    pub(crate) line: usize,
+    pub(crate) key: Option<String>,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: ", self.file, self.line)?;
+        } else {
+            write!(f, "{}: ", self.file)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "`{}`: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<SceneError> for std::io::Error {
+    fn from(error: SceneError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+pub(crate) struct RenderSettings {
+    pub(crate) image_width: usize,
+    pub(crate) image_height: usize,
+    pub(crate) samples_per_pixel: usize,
+    pub(crate) max_bounces: usize,
+    pub(crate) sky: bool,
+}
+
+pub(crate) struct CameraSettings {
+    pub(crate) look_from: Vec3d,
+    pub(crate) look_at: Vec3d,
+    pub(crate) vup: Vec3d,
+    //in degrees
+    pub(crate) vertical_fov: f64,
+    pub(crate) aperture_radius: f64,
+    pub(crate) f_stop: Option<f64>,
+    pub(crate) focus_distance: Option<f64>,
+    pub(crate) aperture_blades: usize,
+    //in degrees
+    pub(crate) blade_rotation: f64,
+    //open and close time, moving objects go from time 0 to 1
+    pub(crate) shutter: (f64, f64),
+}
+
+pub(crate) struct OutputSettings {
+    pub(crate) files: Vec<(OutputFormat, String)>,
+    //transparent background, written as alpha channel by png
+    pub(crate) alpha: bool,
+    //exposure and tone mapping for 8 and 16 bit formats
+    pub(crate) display_transform: DisplayTransform,
+}
+
+pub(crate) struct Scene {
+    pub(crate) render: RenderSettings,
+    pub(crate) camera: CameraSettings,
+    pub(crate) output: OutputSettings,
+    pub(crate) objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    //emissive spheres, quads and box sides, also contained in objects, the delta lights and the environment or sky and sun
+    pub(crate) lights: Vec<Box<dyn Light + Sync>>,
+    //fog and smoke inside named objects and the atmosphere
+    pub(crate) media: Vec<MediumRegion>,
+    //seeds used for randomly generated content
+    pub(crate) seeds: Vec<u64>,
+}
+
+impl Scene {
+    //seed replaces the seeds of randomly generated content in the file if given
+    pub(crate) fn load(path: &str, seed: Option<u64>) -> Result<Scene, SceneError> {
+        let source = std::fs::read_to_string(path).map_err(|e| SceneError{
+            file: path.to_string(),
+            line: 0,
+            key: None,
+            message: e.to_string(),
+        })?;
+        let base_directory = Path::new(path).parent().unwrap_or(Path::new("")).to_path_buf();
+        return Self::parse(&source, path, &base_directory, seed);
+    }
+
+    //relative file references (obj) are resolved against base_directory
+    pub(crate) fn parse(source: &str, file: &str, base_directory: &Path, seed: Option<u64>) -> Result<Scene, SceneError> {
+        let sections = parse_sections(source, file)?;
+        let mut parser = SceneParser{
+            file,
+            base_directory: base_directory.to_path_buf(),
+            seed,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            objects: HashMap::new(),
+        };
+
+        let mut render = None;
+        let mut camera = None;
+        let mut output = None;
+        //[environment] or [sky], whichever was given
+        let mut background: Option<&str> = None;
+
+        //textures and materials first so they can be referenced before their definition
+        parser.definitions(&sections)?;
+
+        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+        let mut lights: Vec<Box<dyn Light + Sync>> = Vec::new();
+        let mut seeds = Vec::new();
+        let mut media = Vec::new();
+        let mut atmosphere = false;
+        //placed after all objects are known so they can reference objects defined later
+        let mut instances = Vec::new();
+        for section in &sections {
+            let reader = section.reader();
+            //named shape sections are not placed in the world, they define objects for [instance]
+            let mut shapes: Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+            match section.kind.as_str() {
+                "texture" | "material" | "principled" => {}
+                "render" => parser.unique(&mut render, section, |p| p.render(reader))?,
+                "camera" => parser.unique(&mut camera, section, |p| p.camera(reader))?,
+                "output" => parser.unique(&mut output, section, |p| p.output(reader))?,
+                "environment" | "sky" => {
+                    if let Some(previous) = background {
+                        let message = if previous == section.kind {
+                            format!("section [{}] can only appear once", section.kind)
+                        } else {
+                            "use either [environment] or [sky], not both".to_string()
+                        };
+                        return Err(parser.error(section.line, None, message));
+                    }
+                    background = Some(&section.kind);
+                    if section.kind == "environment" {
+                        lights.push(Box::new(parser.environment(reader)?));
+                    } else {
+                        lights.extend(parser.sky(reader)?);
+                    }
+                }
+                "sphere" => {
+                    let (sphere, light) = parser.sphere(reader)?;
+                    //only shapes at their own position can be sampled as lights
+                    if let (Some(light), None) = (light, &section.name) {
+                        lights.push(Box::new(light));
+                    }
+                    shapes.push(sphere);
+                }
+                "triangle" => shapes.push(parser.triangle(reader)?),
+                "quad" => {
+                    let quad = parser.quad(reader)?;
+                    if let (Some(light), None) = (quad.light(), &section.name) {
+                        lights.push(Box::new(light));
+                    }
+                    shapes.push(Box::new(quad));
+                }
+                "box" => {
+                    let cuboid = parser.cuboid(reader)?;
+                    if section.name.is_none() {
+                        lights.extend(cuboid.lights().into_iter().map(|light| Box::new(light) as Box<dyn Light + Sync>));
+                    }
+                    shapes.push(Box::new(cuboid));
+                }
+                "plane" => shapes.push(parser.plane(reader)?),
+                "point_light" => lights.push(Box::new(parser.point_light(reader)?)),
+                "spot_light" => lights.push(Box::new(parser.spot_light(reader)?)),
+                "directional_light" => lights.push(Box::new(parser.directional_light(reader)?)),
+                "random_spheres" => {
+                    let (spheres, seed) = parser.random_spheres(reader)?;
+                    shapes.extend(spheres);
+                    seeds.push(seed);
+                }
+                "obj" => shapes.extend(parser.obj(reader)?),
+                "instance" | "scatter" | "medium" => instances.push(section),
+                "atmosphere" => {
+                    if atmosphere {
+                        return Err(parser.error(section.line, None, "section [atmosphere] can only appear once".to_string()));
+                    }
+                    atmosphere = true;
+                    media.push(MediumRegion::everywhere(Box::new(parser.homogeneous_medium(reader)?)));
+                }
+                "volume" => media.push(parser.volume(reader)?),
+                other => return Err(parser.error(section.line, None, format!("unknown section `{}`", other))),
+            }
+            if !SHAPE_SECTIONS.contains(&section.kind.as_str()) {
+                continue;
+            }
+            match &section.name {
+                Some(name) => {
+                    if parser.objects.contains_key(name) {
+                        return Err(parser.error(section.line, None, format!("object `{}` is defined twice", name)));
+                    }
+                    parser.objects.insert(name.clone(), shared_object(shapes));
+                }
+                None => objects.extend(shapes),
+            }
+        }
+        for section in instances {
+            if section.kind == "instance" {
+                objects.push(Box::new(parser.instance(section.reader())?));
+            } else if section.kind == "medium" {
+                media.push(parser.medium(section.reader())?);
+            } else {
+                let (scattered, seed) = parser.scatter(section.reader())?;
+                objects.extend(scattered);
+                seeds.push(seed);
+            }
+        }
+
+        let render = match render {
+            Some(render) => render,
+            None => parser.render(SectionReader::empty())?,
+        };
+        let camera = match camera {
+            Some(camera) => camera,
+            None => parser.camera(SectionReader::empty())?,
+        };
+        let output = match output {
+            Some(output) => output,
+            None => parser.output(SectionReader::empty())?,
+        };
+
+        return Ok(Scene{
+            render,
+            camera,
+            output,
+            objects,
+            lights,
+            media,
+            seeds,
+        });
+    }
+
+    pub(crate) fn camera(&self) -> Camera {
+        let mut builder = Camera::builder()
+            .resolution(self.render.image_width, self.render.image_height)
+            .samples(self.render.samples_per_pixel, self.render.max_bounces)
+            .sky(self.render.sky)
+            .transparent_background(self.output.alpha)
+            .look_from(self.camera.look_from)
+            .look_at(self.camera.look_at)
+            .vup(self.camera.vup)
+            .vertical_fov(self.camera.vertical_fov)
+            .aperture_radius(self.camera.aperture_radius)
+            .aperture_blades(self.camera.aperture_blades, self.camera.blade_rotation)
+            .shutter(self.camera.shutter.0, self.camera.shutter.1);
+        if let Some(f_stop) = self.camera.f_stop {
+            builder = builder.f_stop(f_stop);
+        }
+        if let Some(focus_distance) = self.camera.focus_distance {
+            builder = builder.focus_distance(focus_distance);
+        }
+        return builder.build();
+    }
+}
+
+struct SceneParser<'a> {
+    file: &'a str,
+    base_directory: PathBuf,
+    seed: Option<u64>,
+    textures: HashMap<String, Arc<dyn Texture + Send + Sync>>,
+    materials: HashMap<String, Arc<Material>>,
+    //named shape sections, shared by the instances placing them
+    objects: HashMap<String, Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl<'a> SceneParser<'a> {
+    //[texture], [material] and [principled] sections
+    fn definitions(&mut self, sections: &[Section]) -> Result<(), SceneError> {
+        for section in sections.iter().filter(|s| s.kind == "texture") {
+            let name = match &section.name {
+                Some(name) => name.clone(),
+                None => return Err(self.error(section.line, None, "texture section needs a name, e.g. [texture wood]".to_string())),
+            };
+            if self.textures.contains_key(&name) {
+                return Err(self.error(section.line, None, format!("texture `{}` is defined twice", name)));
+            }
+            let texture = self.texture(section.reader())?;
+            self.textures.insert(name, texture);
+        }
+        for section in sections.iter().filter(|s| s.kind == "material" || s.kind == "principled") {
+            let name = match &section.name {
+                Some(name) => name.clone(),
+                None => return Err(self.error(section.line, None, format!("{} section needs a name, e.g. [{} glass]", section.kind, section.kind))),
+            };
+            if self.materials.contains_key(&name) {
+                return Err(self.error(section.line, None, format!("material `{}` is defined twice", name)));
+            }
+            let material = if section.kind == "principled" {
+                self.principled(section.reader())?
+            } else {
+                self.material(section.reader())?
+            };
+            self.materials.insert(name, Arc::new(material));
+        }
+        return Ok(());
+    }
+
+    fn error(&self, line: usize, key: Option<&str>, message: String) -> SceneError {
+        SceneError{
+            file: self.file.to_string(),
+            line,
+            key: key.map(|k| k.to_string()),
+            message,
+        }
+    }
+
+    fn unique<T, F>(&mut self, slot: &mut Option<T>, section: &Section, parse: F) -> Result<(), SceneError> where F: FnOnce(&mut Self) -> Result<T, SceneError> {
+        if slot.is_some() {
+            return Err(self.error(section.line, None, format!("section [{}] can only appear once", section.kind)));
+        }
+        *slot = Some(parse(self)?);
+        return Ok(());
+    }
+
+    fn render(&self, mut reader: SectionReader) -> Result<RenderSettings, SceneError> {
+        let settings = RenderSettings{
+            image_width: reader.positive_usize(self, "width")?.unwrap_or(400),
+            image_height: reader.positive_usize(self, "height")?.unwrap_or(225),
+            samples_per_pixel: reader.positive_usize(self, "samples_per_pixel")?.unwrap_or(100),
+            max_bounces: reader.usize(self, "max_bounces")?.unwrap_or(7),
+            sky: reader.bool(self, "sky")?.unwrap_or(false),
+        };
+        reader.finish(self)?;
+        return Ok(settings);
+    }
+
+    fn camera(&self, mut reader: SectionReader) -> Result<CameraSettings, SceneError> {
+        let look_at_line = reader.line_of("look_at");
+        let vup_line = reader.line_of("vup");
+        let fov_line = reader.line_of("vertical_fov");
+        let f_stop_line = reader.line_of("f_stop");
+        let blades_line = reader.line_of("aperture_blades");
+        let settings = CameraSettings{
+            look_from: reader.vec3(self, "look_from")?.unwrap_or(Vec3d::zero()),
+            look_at: reader.vec3(self, "look_at")?.unwrap_or(Vec3d::forward()),
+            vup: reader.vec3(self, "vup")?.unwrap_or(Vec3d::up()),
+            vertical_fov: reader.f64(self, "vertical_fov")?.unwrap_or(90.),
+            aperture_radius: reader.non_negative_f64(self, "aperture_radius")?.unwrap_or(0.),
+            f_stop: reader.positive_f64(self, "f_stop")?,
+            focus_distance: reader.positive_f64(self, "focus_distance")?,
+            aperture_blades: reader.usize(self, "aperture_blades")?.unwrap_or(0),
+            blade_rotation: reader.f64(self, "blade_rotation")?.unwrap_or(0.),
+            shutter: reader.parse(self, "shutter", |value| {
+                let numbers = parse_numbers(value, 2)?;
+                if 0. <= numbers[0] && numbers[0] <= numbers[1] && numbers[1] <= 1. {
+                    Ok((numbers[0], numbers[1]))
+                } else {
+                    Err(format!("expected `open close` with 0 <= open <= close <= 1, found `{}`", value))
+                }
+            })?.unwrap_or((0., 0.)),
+        };
+        reader.finish(self)?;
+
+        let view_direction = settings.look_at - settings.look_from;
+        if view_direction.length_squared() == 0. {
+            return Err(self.error(look_at_line, Some("look_at"), "look_at must differ from look_from".to_string()));
+        }
+        if settings.vup.cross(view_direction).length_squared() == 0. {
+            return Err(self.error(vup_line, Some("vup"), "vup must not be parallel to the view direction".to_string()));
+        }
+        if settings.vertical_fov <= 0. || settings.vertical_fov >= 180. {
+            return Err(self.error(fov_line, Some("vertical_fov"), format!("expected an angle between 0 and 180 degrees, found {}", settings.vertical_fov)));
+        }
+        if settings.f_stop.is_some() && settings.aperture_radius > 0. {
+            return Err(self.error(f_stop_line, Some("f_stop"), "set either f_stop or aperture_radius, not both".to_string()));
+        }
+        if settings.aperture_blades == 1 || settings.aperture_blades == 2 {
+            return Err(self.error(blades_line, Some("aperture_blades"), "expected 0 for a circular aperture or at least 3 blades".to_string()));
+        }
+        return Ok(settings);
+    }
+
+    fn output(&self, mut reader: SectionReader) -> Result<OutputSettings, SceneError> {
+        //one key per format, e.g. png = output/sample.png
+        let mut files = Vec::new();
+        for format in OutputFormat::ALL {
+            if let Some(path) = reader.string(self, format.name())? {
+                files.push((format, path));
+            }
+        }
+        if files.is_empty() {
+            files.push((OutputFormat::Png, "output/sample.png".to_string()));
+        }
+        let white_point_line = reader.line_of("white_point");
+        let mut tone_mapping = reader.parse(self, "tone_mapping", |value| {
+            ToneMapping::from_name(value).ok_or(format!("expected one of {}, found `{}`", ToneMapping::NAMES.join(", "), value))
+        })?.unwrap_or(ToneMapping::Linear);
+        if let Some(white_point) = reader.positive_f64(self, "white_point")? {
+            match &mut tone_mapping {
+                ToneMapping::ExtendedReinhard{ white_point: slot } => *slot = white_point,
+                _ => return Err(self.error(white_point_line, Some("white_point"), "only used by tone_mapping = extended_reinhard".to_string())),
+            }
+        }
+        let settings = OutputSettings{
+            files,
+            alpha: reader.bool(self, "alpha")?.unwrap_or(false),
+            display_transform: DisplayTransform{
+                exposure: reader.f64(self, "exposure")?.unwrap_or(0.),
+                tone_mapping,
+            },
+        };
+        reader.finish(self)?;
+        return Ok(settings);
+    }
+
+    fn texture(&self, mut reader: SectionReader) -> Result<Arc<dyn Texture + Send + Sync>, SceneError> {
+        let line = reader.line_of("type");
+        let kind = reader.required(self, "type", SectionReader::string)?;
+        let texture: Arc<dyn Texture + Send + Sync> = match kind.as_str() {
+            "constant" => Arc::new(Constant::new(reader.required(self, "color", SectionReader::vec3)?)),
+            "checker" => {
+                let even = reader.vec3(self, "even")?.unwrap_or(Vec3d::zero());
+                let odd = reader.vec3(self, "odd")?.unwrap_or(Vec3d::new(1., 1., 1.));
+                let frequency = reader.positive_f64(self, "frequency")?.unwrap_or(10.);
+                let solid = reader.bool(self, "solid")?.unwrap_or(false);
+                Arc::new(Checker::new(Arc::new(Constant::new(even)), Arc::new(Constant::new(odd)), frequency).solid(solid))
+            }
+            "noise" | "marble" | "wood" => {
+                let pattern = match kind.as_str() {
+                    "noise" => NoisePattern::Fbm,
+                    "marble" => NoisePattern::Marble,
+                    _ => NoisePattern::Wood,
+                };
+                let color_a = reader.vec3(self, "color_a")?.unwrap_or(Vec3d::zero());
+                let color_b = reader.vec3(self, "color_b")?.unwrap_or(Vec3d::new(1., 1., 1.));
+                let scale = reader.positive_f64(self, "scale")?.unwrap_or(1.);
+                let octaves = reader.positive_usize(self, "octaves")?.unwrap_or(7);
+                let seed = reader.parse(self, "seed", |value| {
+                    value.parse::<u64>().map_err(|_| format!("expected a non-negative integer, found `{}`", value))
+                })?.unwrap_or(0);
+                let perlin = Perlin::new(&mut rand::prelude::StdRng::from_seed(seed_bytes(seed)));
+                Arc::new(NoiseTexture::new(perlin, pattern, (color_a, color_b), scale, octaves))
+            }
+            "image" => {
+                let path_line = reader.line_of("path");
+                let path = reader.required(self, "path", SectionReader::string)?;
+                let wrap = reader.parse(self, "wrap", |value| {
+                    WrapMode::from_name(value).ok_or(format!("expected one of {}, found `{}`", WrapMode::NAMES.join(", "), value))
+                })?.unwrap_or(WrapMode::Repeat);
+                //srgb for colors, linear for data textures, ignored by floating point formats
+                let color_space = reader.parse(self, "color_space", |value| {
+                    ColorSpace::from_name(value).ok_or(format!("expected one of {}, found `{}`", ColorSpace::NAMES.join(", "), value))
+                })?.unwrap_or(ColorSpace::Srgb);
+                let full_path = self.base_directory.join(&path);
+                let image = Image::read_from_file(&full_path.to_string_lossy(), color_space)
+                    .map_err(|e| self.error(path_line, Some("path"), e.to_string()))?;
+                Arc::new(ImageTexture::new(Arc::new(image), wrap))
+            }
+            other => return Err(self.error(line, Some("type"), format!("expected one of constant, checker, noise, marble, wood, image, found `{}`", other))),
+        };
+        reader.finish(self)?;
+        return Ok(texture);
+    }
+
+    fn texture_reference(&self, reader: &mut SectionReader, key: &str) -> Result<Option<Arc<dyn Texture + Send + Sync>>, SceneError> {
+        let line = reader.line_of(key);
+        return match reader.string(self, key)? {
+            Some(name) => match self.textures.get(&name) {
+                Some(texture) => Ok(Some(texture.clone())),
+                None => Err(self.error(line, Some(key), format!("unknown texture `{}`", name))),
+            },
+            None => Ok(None),
+        };
+    }
+
+    fn material(&self, mut reader: SectionReader) -> Result<Material, SceneError> {
+        let mut builder = Material::builder();
+        let albedo = reader.vec3(self, "albedo")?;
+        let absorption = reader.f64(self, "absorption")?;
+        if albedo.is_some() || absorption.is_some() {
+            builder = builder.albedo(albedo.unwrap_or(Vec3d::new(0.5, 0., 0.5)), absorption.unwrap_or(0.));
+        }
+        if let Some(texture) = self.texture_reference(&mut reader, "albedo_texture")? {
+            builder = builder.albedo_texture(texture);
+        }
+        //roughness, or its inverse smoothness, or the fuzz radius of older scenes with about the same look
+        let mut roughness = None;
+        for key in ["roughness", "smoothness", "reflection_fuzz"] {
+            let line = reader.line_of(key);
+            let value = match reader.f64(self, key)? {
+                Some(value) => value.clamp(0., 1.),
+                None => continue,
+            };
+            if roughness.is_some() {
+                return Err(self.error(line, Some(key), "only one of roughness, smoothness and reflection_fuzz can be set".to_string()));
+            }
+            roughness = Some(match key {
+                "smoothness" => 1. - value,
+                "reflection_fuzz" => f64::sqrt(value),
+                _ => value,
+            });
+        }
+        let reflectivity = reader.f64(self, "reflectivity")?;
+        if reflectivity.is_some() || roughness.is_some() {
+            builder = builder.reflection(reflectivity.unwrap_or(0.), roughness.unwrap_or(0.));
+        }
+        //color of the metal: a tint, a measured preset or a complex index of refraction
+        let line = reader.line;
+        let metal_line = reader.line_of("metal");
+        let metal = match reader.string(self, "metal")? {
+            Some(name) => match ConductorFresnel::preset(&name) {
+                Some(fresnel) => Some(fresnel),
+                None => return Err(self.error(metal_line, Some("metal"), format!("expected one of {}, found `{}`", ConductorFresnel::PRESETS.join(", "), name))),
+            },
+            None => None,
+        };
+        let reflection_color = reader.vec3(self, "reflection_color")?;
+        let conductor_eta = reader.vec3(self, "conductor_eta")?;
+        let conductor_k = reader.vec3(self, "conductor_k")?;
+        let fresnel = match (metal, reflection_color, conductor_eta, conductor_k) {
+            (None, None, None, None) => None,
+            (Some(fresnel), None, None, None) => Some(fresnel),
+            (None, Some(color), None, None) => Some(ConductorFresnel::Schlick(color)),
+            (None, None, Some(eta), Some(k)) => Some(ConductorFresnel::Complex{eta, k}),
+            _ => return Err(self.error(line, None, "set one of metal, reflection_color or conductor_eta with conductor_k".to_string())),
+        };
+        if let Some(fresnel) = fresnel {
+            builder = builder.reflection_fresnel(fresnel);
+        }
+        let refraction_index = reader.positive_f64(self, "refraction_index")?;
+        let refractioness = reader.f64(self, "refractioness")?;
+        if refraction_index.is_some() || refractioness.is_some() {
+            builder = builder.refraction(refraction_index.unwrap_or(1.), refractioness.unwrap_or(0.));
+        }
+        let emission = reader.vec3(self, "emission")?;
+        let emission_intensity = reader.f64(self, "emission_intensity")?;
+        if emission.is_some() || emission_intensity.is_some() {
+            builder = builder.emission(emission.unwrap_or(Vec3d::new(1., 1., 1.)), emission_intensity.unwrap_or(0.));
+        }
+        reader.finish(self)?;
+        return Ok(builder.build());
+    }
+
+    fn principled(&self, mut reader: SectionReader) -> Result<Material, SceneError> {
+        let defaults = PrincipledParameters::default();
+        let mut builder = Material::principled();
+        if let Some(base_color) = reader.vec3(self, "base_color")? {
+            builder = builder.base_color(base_color);
+        }
+        let texture_line = reader.line_of("base_color_texture");
+        let texture = self.texture_reference(&mut reader, "base_color_texture")?;
+        let metallic = reader.f64(self, "metallic")?;
+        if let Some(metallic) = metallic {
+            builder = builder.metallic(metallic);
+        }
+        if let Some(roughness) = reader.f64(self, "roughness")? {
+            builder = builder.roughness(roughness);
+        }
+        let specular = reader.f64(self, "specular")?;
+        let specular_tint = reader.f64(self, "specular_tint")?;
+        if specular.is_some() || specular_tint.is_some() {
+            builder = builder.specular(specular.unwrap_or(defaults.specular), specular_tint.unwrap_or(defaults.specular_tint));
+        }
+        let sheen = reader.f64(self, "sheen")?;
+        let sheen_tint = reader.f64(self, "sheen_tint")?;
+        if sheen.is_some() || sheen_tint.is_some() {
+            builder = builder.sheen(sheen.unwrap_or(defaults.sheen), sheen_tint.unwrap_or(defaults.sheen_tint));
+        }
+        let clearcoat = reader.f64(self, "clearcoat")?;
+        let clearcoat_roughness = reader.f64(self, "clearcoat_roughness")?;
+        if clearcoat.is_some() || clearcoat_roughness.is_some() {
+            builder = builder.clearcoat(clearcoat.unwrap_or(defaults.clearcoat), clearcoat_roughness.unwrap_or(defaults.clearcoat_roughness));
+        }
+        let transmission = reader.f64(self, "transmission")?;
+        let refraction_index = reader.positive_f64(self, "refraction_index")?;
+        if transmission.is_some() || refraction_index.is_some() {
+            builder = builder.transmission(transmission.unwrap_or(defaults.transmission), refraction_index.unwrap_or(defaults.refraction_index));
+        }
+        if let Some(texture) = texture {
+            //the texture only colors the diffuse layer, metal and glass would keep the flat base_color
+            if metallic.unwrap_or(defaults.metallic) > 0. || transmission.unwrap_or(defaults.transmission) > 0. {
+                return Err(self.error(texture_line, Some("base_color_texture"), "cannot be combined with metallic or transmission".to_string()));
+            }
+            builder = builder.base_color_texture(texture);
+        }
+        let emission = reader.vec3(self, "emission")?;
+        let emission_intensity = reader.f64(self, "emission_intensity")?;
+        if emission.is_some() || emission_intensity.is_some() {
+            builder = builder.emission(emission.unwrap_or(Vec3d::new(1., 1., 1.)), emission_intensity.unwrap_or(0.));
+        }
+        reader.finish(self)?;
+        return Ok(builder.build());
+    }
+
+    fn material_reference(&self, reader: &mut SectionReader, key: &str) -> Result<Option<Arc<Material>>, SceneError> {
+        let line = reader.line_of(key);
+        return match reader.string(self, key)? {
+            Some(name) => match self.materials.get(&name) {
+                Some(material) => Ok(Some(material.clone())),
+                None => Err(self.error(line, Some(key), format!("unknown material `{}`", name))),
+            },
+            None => Ok(None),
+        };
+    }
+
+    fn required_material(&self, reader: &mut SectionReader) -> Result<Arc<Material>, SceneError> {
+        let line = reader.line;
+        return match self.material_reference(reader, "material")? {
+            Some(material) => Ok(material),
+            None => Err(self.error(line, Some("material"), "missing required key".to_string())),
+        };
+    }
+
+    //returned with its light for next event estimation if it emits and does not move
+    fn sphere(&self, mut reader: SectionReader) -> Result<(Box<dyn Hittable + Send + Sync>, Option<SphereLight>), SceneError> {
+        let center = reader.required(self, "center", SectionReader::vec3)?;
+        //center at the end of the motion, time 1
+        let center_end = reader.vec3(self, "center_end")?;
+        let radius_line = reader.line_of("radius");
+        let radius = reader.required(self, "radius", SectionReader::f64)?;
+        if radius == 0. {
+            return Err(self.error(radius_line, Some("radius"), "radius must not be 0".to_string()));
+        }
+        let material = self.required_material(&mut reader)?;
+        reader.finish(self)?;
+        if let Some(center_end) = center_end {
+            return Ok((Box::new(MovingSphere::new(center, center_end, radius, material)), None));
+        }
+        let sphere = Sphere::new(center, radius, material);
+        let light = sphere.light();
+        return Ok((Box::new(sphere), light));
+    }
+
+    fn triangle(&self, mut reader: SectionReader) -> Result<Box<dyn Hittable + Send + Sync>, SceneError> {
+        let v0 = reader.required(self, "v0", SectionReader::vec3)?;
+        let v1 = reader.required(self, "v1", SectionReader::vec3)?;
+        let v2 = reader.required(self, "v2", SectionReader::vec3)?;
+        let material = self.required_material(&mut reader)?;
+        reader.finish(self)?;
+        return Ok(Box::new(Triangle::new(v0, v1, v2, material)));
+    }
+
+    //parallelogram from corner along the edges u and v
+    fn quad(&self, mut reader: SectionReader) -> Result<Quad, SceneError> {
+        let corner = reader.required(self, "corner", SectionReader::vec3)?;
+        let u = reader.required(self, "u", SectionReader::vec3)?;
+        let v_line = reader.line_of("v");
+        let v = reader.required(self, "v", SectionReader::vec3)?;
+        if u.cross(v).length_squared() == 0. {
+            return Err(self.error(v_line, Some("v"), "u and v must not be parallel".to_string()));
+        }
+        let material = self.required_material(&mut reader)?;
+        reader.finish(self)?;
+        return Ok(Quad::new(corner, u, v, material));
+    }
+
+    //axis-aligned box between two opposite corners
+    fn cuboid(&self, mut reader: SectionReader) -> Result<Cuboid, SceneError> {
+        let min = reader.required(self, "min", SectionReader::vec3)?;
+        let max_line = reader.line_of("max");
+        let max = reader.required(self, "max", SectionReader::vec3)?;
+        if min.x == max.x || min.y == max.y || min.z == max.z {
+            return Err(self.error(max_line, Some("max"), "the box must have an extent along every axis".to_string()));
+        }
+        let material = self.required_material(&mut reader)?;
+        reader.finish(self)?;
+        return Ok(Cuboid::new(min, max, material));
+    }
+
+    fn plane(&self, mut reader: SectionReader) -> Result<Box<dyn Hittable + Send + Sync>, SceneError> {
+        let point = reader.required(self, "point", SectionReader::vec3)?;
+        let normal_line = reader.line_of("normal");
+        let normal = reader.required(self, "normal", SectionReader::vec3)?;
+        if normal.length_squared() == 0. {
+            return Err(self.error(normal_line, Some("normal"), "normal must not be 0 0 0".to_string()));
+        }
+        let material = self.required_material(&mut reader)?;
+        reader.finish(self)?;
+        return Ok(Box::new(Plane::new(point, normal, material)));
+    }
+
+    //color times the intensity in candela, or the intensity that emits power in lumens
+    fn light_intensity<F>(&self, reader: &mut SectionReader, power_to_intensity: F) -> Result<Vec3d, SceneError> where F: FnOnce(f64) -> f64 {
+        let color = reader.vec3(self, "color")?.unwrap_or(Vec3d::new(1., 1., 1.));
+        let power_line = reader.line_of("power");
+        let intensity = reader.non_negative_f64(self, "intensity")?;
+        let power = reader.non_negative_f64(self, "power")?;
+        let intensity = match (intensity, power) {
+            (Some(_), Some(_)) => return Err(self.error(power_line, Some("power"), "set either intensity or power, not both".to_string())),
+            (Some(intensity), None) => intensity,
+            (None, Some(power)) => power_to_intensity(power),
+            (None, None) => 1.,
+        };
+        return Ok(intensity * color);
+    }
+
+    fn light_direction(&self, reader: &mut SectionReader, default: Option<Vec3d>) -> Result<Vec3d, SceneError> {
+        let line = reader.line_of("direction");
+        let direction = match default {
+            Some(default) => reader.vec3(self, "direction")?.unwrap_or(default),
+            None => reader.required(self, "direction", SectionReader::vec3)?,
+        };
+        if direction.length_squared() == 0. {
+            return Err(self.error(line, Some("direction"), "direction must not be 0 0 0".to_string()));
+        }
+        return Ok(direction);
+    }
+
+    fn point_light(&self, mut reader: SectionReader) -> Result<PointLight, SceneError> {
+        let position = reader.required(self, "position", SectionReader::vec3)?;
+        let intensity = self.light_intensity(&mut reader, |power| power / (4. * std::f64::consts::PI))?;
+        reader.finish(self)?;
+        return Ok(PointLight::new(position, intensity));
+    }
+
+    //inner_angle and outer_angle are half angles of the cones in degrees
+    fn spot_light(&self, mut reader: SectionReader) -> Result<SpotLight, SceneError> {
+        let position = reader.required(self, "position", SectionReader::vec3)?;
+        let direction = self.light_direction(&mut reader, Some(Vec3d::down()))?;
+        let inner_line = reader.line_of("inner_angle");
+        let outer_line = reader.line_of("outer_angle");
+        let inner_angle = reader.non_negative_f64(self, "inner_angle")?.unwrap_or(30.);
+        let outer_angle = reader.positive_f64(self, "outer_angle")?.unwrap_or(45.);
+        if outer_angle > 180. {
+            return Err(self.error(outer_line, Some("outer_angle"), format!("expected an angle up to 180 degrees, found {}", outer_angle)));
+        }
+        if inner_angle > outer_angle {
+            return Err(self.error(inner_line, Some("inner_angle"), format!("inner_angle must not exceed outer_angle {}", outer_angle)));
+        }
+        let intensity = self.light_intensity(&mut reader, |power| SpotLight::intensity_from_power(power, inner_angle, outer_angle))?;
+        reader.finish(self)?;
+        return Ok(SpotLight::new(position, direction, inner_angle, outer_angle, intensity));
+    }
+
+    //direction the light travels in, illuminance in lux
+    fn directional_light(&self, mut reader: SectionReader) -> Result<DirectionalLight, SceneError> {
+        let direction = self.light_direction(&mut reader, None)?;
+        let color = reader.vec3(self, "color")?.unwrap_or(Vec3d::new(1., 1., 1.));
+        let illuminance = reader.non_negative_f64(self, "illuminance")?.unwrap_or(1.);
+        reader.finish(self)?;
+        return Ok(DirectionalLight::new(direction, illuminance * color));
+    }
+
+    //spheres with random position, radius and diffuse color inside the given ranges, returned with the seed used
+    fn random_spheres(&self, mut reader: SectionReader) -> Result<(Vec<Box<dyn Hittable + Send + Sync>>, u64), SceneError> {
+        let count = reader.required(self, "count", SectionReader::usize)?;
+        let seed = reader.parse(self, "seed", |value| {
+            value.parse::<u64>().map_err(|_| format!("expected a non-negative integer, found `{}`", value))
+        })?.unwrap_or(0);
+        let seed = self.seed.unwrap_or(seed);
+        let x = reader.required(self, "x", SectionReader::range)?;
+        let y = reader.required(self, "y", SectionReader::range)?;
+        let z = reader.required(self, "z", SectionReader::range)?;
+        let radius = reader.required(self, "radius", SectionReader::range)?;
+        let absorption = reader.f64(self, "absorption")?.unwrap_or(0.3);
+        reader.finish(self)?;
+
+        let mut rng = rand::prelude::StdRng::from_seed(seed_bytes(seed));
+        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let x = rng.gen_range(x.0..x.1);
+            let y = rng.gen_range(y.0..y.1);
+            let z = rng.gen_range(z.0..z.1);
+            let r = rng.gen_range(radius.0..radius.1);
+            let material = Arc::new(Material::builder().albedo(Vec3d::new(rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0)),absorption).build());
+            objects.push(Box::new(Sphere::new(Vec3d::new(x,y,z), r, material)));
+        }
+        return Ok((objects, seed));
+    }
+
+    //equirectangular image, usually hdr, lighting the scene from infinitely far away
+    fn environment(&self, mut reader: SectionReader) -> Result<EnvironmentLight, SceneError> {
+        let path_line = reader.line_of("path");
+        let path = reader.required(self, "path", SectionReader::string)?;
+        let intensity = reader.non_negative_f64(self, "intensity")?.unwrap_or(1.);
+        //degrees around the y axis
+        let rotation = reader.f64(self, "rotation")?.unwrap_or(0.);
+        reader.finish(self)?;
+
+        let full_path = self.base_directory.join(&path);
+        let image = Image::read_from_file(&full_path.to_string_lossy(), ColorSpace::Srgb)
+            .map_err(|e| self.error(path_line, Some("path"), e.to_string()))?;
+        return Ok(EnvironmentLight::new(Arc::new(image), intensity, rotation));
+    }
+
+    //preetham daylight, baked into an environment map, and the sun disk
+    fn sky(&self, mut reader: SectionReader) -> Result<Vec<Box<dyn Light + Sync>>, SceneError> {
+        let elevation_line = reader.line_of("sun_elevation");
+        let turbidity_line = reader.line_of("turbidity");
+        let elevation = reader.f64(self, "sun_elevation")?.unwrap_or(45.);
+        let azimuth = reader.f64(self, "sun_azimuth")?.unwrap_or(0.);
+        let turbidity = reader.f64(self, "turbidity")?.unwrap_or(3.);
+        let intensity = reader.non_negative_f64(self, "intensity")?.unwrap_or(1.);
+        let sun = reader.bool(self, "sun")?.unwrap_or(true);
+        reader.finish(self)?;
+
+        if elevation <= 0. || elevation > 90. {
+            return Err(self.error(elevation_line, Some("sun_elevation"), format!("expected an angle above 0 and up to 90 degrees, found {}", elevation)));
+        }
+        if !(1.7..=10.).contains(&turbidity) {
+            return Err(self.error(turbidity_line, Some("turbidity"), format!("expected a turbidity between 1.7 and 10, found {}", turbidity)));
+        }
+        let direction = sky::sun_direction(elevation, azimuth);
+        let image = PreethamSky::new(direction, turbidity).to_image(512, 256);
+        let mut lights: Vec<Box<dyn Light + Sync>> = vec![Box::new(EnvironmentLight::new(Arc::new(image), intensity, 0.))];
+        if sun {
+            lights.push(Box::new(SunLight::new(direction, turbidity, intensity)));
+        }
+        return Ok(lights);
+    }
+
+    fn obj(&self, mut reader: SectionReader) -> Result<Vec<Box<dyn Hittable + Send + Sync>>, SceneError> {
+        let path_line = reader.line_of("path");
+        let path = reader.required(self, "path", SectionReader::string)?;
+        //used for faces without usemtl
+        let material = match self.material_reference(&mut reader, "material")? {
+            Some(material) => material,
+            None => Arc::new(Material::builder().albedo(Vec3d::new(0.8, 0.8, 0.8), 0.3).build()),
+        };
+        reader.finish(self)?;
+
+        let full_path = self.base_directory.join(&path);
+        let model = obj::load_obj(&full_path.to_string_lossy(), material)
+            .map_err(|e| self.error(path_line, Some("path"), e.to_string()))?;
+        return Ok(model.hittables());
+    }
+
+    fn object_reference(&self, reader: &mut SectionReader) -> Result<Arc<dyn Hittable + Send + Sync>, SceneError> {
+        let line = reader.line_of("object");
+        let name = reader.required(self, "object", SectionReader::string)?;
+        return match self.objects.get(&name) {
+            Some(object) => Ok(object.clone()),
+            None => Err(self.error(line, Some("object"), format!("unknown object `{}`", name))),
+        };
+    }
+
+    //named object placed by scale, then rotation, then translation, or by a full matrix
+    fn instance(&self, mut reader: SectionReader) -> Result<Instance, SceneError> {
+        let object = self.object_reference(&mut reader)?;
+        //replaces the materials of the object
+        let material = self.material_reference(&mut reader, "material")?;
+        let matrix_line = reader.line_of("matrix");
+        let matrix = reader.parse(self, "matrix", |value| {
+            let numbers = parse_numbers(value, 16)?;
+            let mut rows = [[0.; 4]; 4];
+            for (index, number) in numbers.into_iter().enumerate() {
+                rows[index / 4][index % 4] = number;
+            }
+            //points and directions are transformed as affine maps, a projective row would be ignored
+            if rows[3] != [0., 0., 0., 1.] {
+                return Err("the bottom row of the matrix must be `0 0 0 1`".to_string());
+            }
+            Transform::from_matrix(rows).ok_or("the matrix cannot be inverted".to_string())
+        })?;
+        let scale = reader.parse(self, "scale", parse_scale)?;
+        let rotate = reader.parse(self, "rotate", parse_rotation)?;
+        let translate = reader.vec3(self, "translate")?;
+        //placement at the end of the motion, time 1, missing values stay at the start
+        let scale_end_line = reader.line_of("scale_end");
+        let scale_end = reader.parse(self, "scale_end", parse_scale)?;
+        let rotate_end = reader.parse(self, "rotate_end", parse_rotation)?;
+        let translate_end = reader.vec3(self, "translate_end")?;
+        reader.finish(self)?;
+
+        let moving = scale_end.is_some() || rotate_end.is_some() || translate_end.is_some();
+        let identity = Keyframe::identity();
+        let (rotation_axis, rotation_angle) = rotate.unwrap_or((identity.rotation_axis, identity.rotation_angle));
+        let start = Keyframe{
+            translation: translate.unwrap_or(identity.translation),
+            rotation_axis,
+            rotation_angle,
+            scale: scale.unwrap_or(identity.scale),
+        };
+        //a factor changing sign passes through 0 halfway and flattens the object
+        if let Some(scale_end) = scale_end {
+            let start_signs = [start.scale.x, start.scale.y, start.scale.z].map(f64::is_sign_negative);
+            let end_signs = [scale_end.x, scale_end.y, scale_end.z].map(f64::is_sign_negative);
+            if start_signs != end_signs {
+                return Err(self.error(scale_end_line, Some("scale_end"), "scale_end must have the same signs as scale".to_string()));
+            }
+        }
+        let instance = match matrix {
+            Some(matrix) => {
+                if scale.is_some() || rotate.is_some() || translate.is_some() || moving {
+                    return Err(self.error(matrix_line, Some("matrix"), "set either matrix or scale, rotate and translate, not both".to_string()));
+                }
+                Instance::new(object, matrix)
+            }
+            None if moving => {
+                let (rotation_axis, rotation_angle) = rotate_end.unwrap_or((rotation_axis, rotation_angle));
+                let end = Keyframe{
+                    translation: translate_end.unwrap_or(start.translation),
+                    rotation_axis,
+                    rotation_angle,
+                    scale: scale_end.unwrap_or(start.scale),
+                };
+                Instance::moving(object, AnimatedTransform::new(start, end))
+            }
+            None => Instance::new(object, start.transform()),
+        };
+        return Ok(match material {
+            Some(material) => instance.with_material(material),
+            None => instance,
+        });
+    }
+
+    //absorption and scattering per unit of length, both scaled by density, and the anisotropy
+    fn medium_coefficients(&self, reader: &mut SectionReader) -> Result<(Vec3d, Vec3d, f64), SceneError> {
+        let absorption_line = reader.line_of("absorption");
+        let scattering_line = reader.line_of("scattering");
+        let anisotropy_line = reader.line_of("anisotropy");
+        let absorption = reader.vec3(self, "absorption")?.unwrap_or(Vec3d::zero());
+        let scattering = reader.vec3(self, "scattering")?.unwrap_or(Vec3d::zero());
+        let density = reader.non_negative_f64(self, "density")?.unwrap_or(1.);
+        //mean cosine of the henyey-greenstein phase function
+        let anisotropy = reader.f64(self, "anisotropy")?.unwrap_or(0.);
+        for (key, line, value) in [("absorption", absorption_line, absorption), ("scattering", scattering_line, scattering)] {
+            if value.x < 0. || value.y < 0. || value.z < 0. {
+                return Err(self.error(line, Some(key), "coefficients must not be negative".to_string()));
+            }
+        }
+        if anisotropy <= -1. || anisotropy >= 1. {
+            return Err(self.error(anisotropy_line, Some("anisotropy"), format!("expected a value between -1 and 1, found {}", anisotropy)));
+        }
+        return Ok((density * absorption, density * scattering, anisotropy));
+    }
+
+    fn homogeneous_medium(&self, mut reader: SectionReader) -> Result<HomogeneousMedium, SceneError> {
+        let (absorption, scattering, anisotropy) = self.medium_coefficients(&mut reader)?;
+        reader.finish(self)?;
+        return Ok(HomogeneousMedium::new(absorption, scattering, anisotropy));
+    }
+
+    //density grid stretched over min and max, which default to the bounds stored in .vol files
+    fn volume(&self, mut reader: SectionReader) -> Result<MediumRegion, SceneError> {
+        let path_line = reader.line_of("path");
+        let min_line = reader.line_of("min");
+        let emission_line = reader.line_of("emission");
+        let path = reader.required(self, "path", SectionReader::string)?;
+        //voxels along x, y and z, needed by raw files
+        let resolution = reader.parse(self, "resolution", |value| {
+            let numbers: Vec<usize> = value.split_whitespace().map(|n| n.parse::<usize>().ok().filter(|&n| n > 0)).collect::<Option<Vec<usize>>>()
+                .filter(|numbers| numbers.len() == 3)
+                .ok_or(format!("expected 3 positive integers, found `{}`", value))?;
+            Ok([numbers[0], numbers[1], numbers[2]])
+        })?;
+        let min = reader.vec3(self, "min")?;
+        let max = reader.vec3(self, "max")?;
+        let (absorption, scattering, anisotropy) = self.medium_coefficients(&mut reader)?;
+        //grid of the emitted radiance for fire, scaled by emission and the absorption
+        let emission_path_line = reader.line_of("emission_path");
+        let emission_path = reader.string(self, "emission_path")?;
+        let emission = reader.vec3(self, "emission")?;
+        reader.finish(self)?;
+
+        let read_grid = |path: &str, line: usize, key: &str| {
+            let full_path = self.base_directory.join(path);
+            VoxelGrid::read_from_file(&full_path.to_string_lossy(), resolution).map_err(|e| self.error(line, Some(key), e.to_string()))
+        };
+        let density = read_grid(&path, path_line, "path")?;
+        let bounds = match (min, max, density.bounds()) {
+            (Some(min), Some(max), _) => Aabb::new(min, max),
+            (None, None, Some(bounds)) => bounds,
+            (None, None, None) => return Err(self.error(path_line, Some("path"), "raw grid files need min and max".to_string())),
+            _ => return Err(self.error(min_line, Some("min"), "set both min and max or neither".to_string())),
+        };
+        let extent = bounds.extent();
+        if extent.x <= 0. || extent.y <= 0. || extent.z <= 0. {
+            return Err(self.error(min_line, Some("min"), "the volume must have a positive size along every axis".to_string()));
+        }
+        let mut medium = GridMedium::new(density, bounds, absorption, scattering, anisotropy);
+        match (emission_path, emission) {
+            (Some(emission_path), emission) => {
+                let grid = read_grid(&emission_path, emission_path_line, "emission_path")?;
+                medium = medium.with_emission(grid, emission.unwrap_or(Vec3d::new(1., 1., 1.)));
+            }
+            (None, Some(_)) => return Err(self.error(emission_line, Some("emission"), "only used with emission_path".to_string())),
+            (None, None) => {}
+        }
+        return Ok(medium.into_region());
+    }
+
+    //medium inside a named object, which has to be closed, the object itself stays invisible
+    fn medium(&self, mut reader: SectionReader) -> Result<MediumRegion, SceneError> {
+        let boundary = self.object_reference(&mut reader)?;
+        return Ok(MediumRegion::bounded(boundary, Box::new(self.homogeneous_medium(reader)?)));
+    }
+
+    //copies of a named object at random positions inside the given ranges, returned with the seed used
+    fn scatter(&self, mut reader: SectionReader) -> Result<(Vec<Box<dyn Hittable + Send + Sync>>, u64), SceneError> {
+        let object = self.object_reference(&mut reader)?;
+        let material = self.material_reference(&mut reader, "material")?;
+        let count = reader.required(self, "count", SectionReader::usize)?;
+        let seed = reader.parse(self, "seed", |value| {
+            value.parse::<u64>().map_err(|_| format!("expected a non-negative integer, found `{}`", value))
+        })?.unwrap_or(0);
+        let seed = self.seed.unwrap_or(seed);
+        let x = reader.required(self, "x", SectionReader::range)?;
+        let y = reader.required(self, "y", SectionReader::range)?;
+        let z = reader.required(self, "z", SectionReader::range)?;
+        let scale_line = reader.line_of("scale");
+        let scale = reader.range(self, "scale")?;
+        //random turn around the y axis
+        let rotate = reader.bool(self, "rotate")?.unwrap_or(false);
+        reader.finish(self)?;
+        if scale.is_some_and(|(min, _)| min <= 0.) {
+            return Err(self.error(scale_line, Some("scale"), "scale must be positive".to_string()));
+        }
+
+        let mut rng = rand::prelude::StdRng::from_seed(seed_bytes(seed));
+        let mut instances: Vec<Box<dyn Hittable + Send + Sync>> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let position = Vec3d::new(rng.gen_range(x.0..x.1), rng.gen_range(y.0..y.1), rng.gen_range(z.0..z.1));
+            let mut transform = Transform::translation(position);
+            if rotate {
+                transform = transform * Transform::rotation(Vec3d::up(), rng.gen_range(0.0..360.0));
+            }
+            if let Some(scale) = scale {
+                let factor = rng.gen_range(scale.0..scale.1);
+                transform = transform * Transform::scaling(Vec3d::new(factor, factor, factor));
+            }
+            let instance = Instance::new(object.clone(), transform);
+            instances.push(Box::new(match &material {
+                Some(material) => instance.with_material(material.clone()),
+                None => instance,
+            }));
+        }
+        return Ok((instances, seed));
+    }
+}
+
+//sections that define shapes, placed directly or named and placed by [instance]
+const SHAPE_SECTIONS: [&str; 7] = ["sphere", "triangle", "quad", "box", "plane", "random_spheres", "obj"];
+
+//single shapes are shared as they are, several get their own bvh
+fn shared_object(mut shapes: Vec<Box<dyn Hittable + Send + Sync>>) -> Arc<dyn Hittable + Send + Sync> {
+    if shapes.len() == 1 {
+        return Arc::from(shapes.pop().unwrap());
+    }
+    return Arc::new(Bvh::new(shapes));
+}
+
+//u64 seed spread over the first 8 bytes, seed 0 gives the all zero seed
+fn seed_bytes(seed: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    return bytes;
+}
+
+struct Entry {
+    key: String,
+    value: String,
+    line: usize,
+}
+
+struct Section {
+    kind: String,
+    name: Option<String>,
+    line: usize,
+    entries: Vec<Entry>,
+}
+
+impl Section {
+    fn reader(&self) -> SectionReader<'_> {
+        SectionReader{
+            line: self.line,
+            entries: self.entries.iter().collect(),
+        }
+    }
+}
+
+fn parse_sections(source: &str, file: &str) -> Result<Vec<Section>, SceneError> {
+    let error = |line: usize, key: Option<&str>, message: String| SceneError{
+        file: file.to_string(),
+        line,
+        key: key.map(|k| k.to_string()),
+        message,
+    };
+    let mut sections: Vec<Section> = Vec::new();
+    for (line_index, raw_line) in source.lines().enumerate() {
+        let line = line_index + 1;
+        let content = match raw_line.find('#') {
+            Some(index) => &raw_line[..index],
+            None => raw_line,
+        }.trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        if content.starts_with('[') {
+            if !content.ends_with(']') {
+                return Err(error(line, None, format!("expected `]` at the end of section header `{}`", content)));
+            }
+            let mut words = content[1..content.len() - 1].split_whitespace();
+            let kind = match words.next() {
+                Some(kind) => kind.to_string(),
+                None => return Err(error(line, None, "empty section header".to_string())),
+            };
+            let name = words.next().map(|n| n.to_string());
+            if words.next().is_some() {
+                return Err(error(line, None, format!("section header `{}` has more than one name", content)));
+            }
+            sections.push(Section{ kind, name, line, entries: Vec::new() });
+            continue;
+        }
+
+        let (key, value) = match content.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(error(line, None, format!("expected `key = value`, found `{}`", content))),
+        };
+        if key.is_empty() {
+            return Err(error(line, None, "missing key before `=`".to_string()));
+        }
+        let section = match sections.last_mut() {
+            Some(section) => section,
+            None => return Err(error(line, Some(key), "key outside of a section".to_string())),
+        };
+        if let Some(previous) = section.entries.iter().find(|e| e.key == key) {
+            return Err(error(line, Some(key), format!("duplicate key, first set on line {}", previous.line)));
+        }
+        section.entries.push(Entry{ key: key.to_string(), value: value.to_string(), line });
+    }
+    return Ok(sections);
+}
+
+//typed access to the entries of one section, every key has to be consumed exactly once
+struct SectionReader<'a> {
+    line: usize,
+    entries: Vec<&'a Entry>,
+}
+
+impl<'a> SectionReader<'a> {
+    fn empty() -> Self {
+        Self{
+            line: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn line_of(&self, key: &str) -> usize {
+        return self.entries.iter().find(|e| e.key == key).map_or(self.line, |e| e.line);
+    }
+
+    fn take(&mut self, key: &str) -> Option<&'a Entry> {
+        let index = self.entries.iter().position(|e| e.key == key)?;
+        return Some(self.entries.remove(index));
+    }
+
+    fn finish(self, parser: &SceneParser) -> Result<(), SceneError> {
+        return match self.entries.first() {
+            Some(entry) => Err(parser.error(entry.line, Some(&entry.key), "unknown key".to_string())),
+            None => Ok(()),
+        };
+    }
+
+    fn required<T, F>(&mut self, parser: &SceneParser, key: &str, read: F) -> Result<T, SceneError>
+        where F: FnOnce(&mut Self, &SceneParser, &str) -> Result<Option<T>, SceneError> {
+        let line = self.line;
+        return match read(self, parser, key)? {
+            Some(value) => Ok(value),
+            None => Err(parser.error(line, Some(key), "missing required key".to_string())),
+        };
+    }
+
+    fn parse<T, F>(&mut self, parser: &SceneParser, key: &str, parse: F) -> Result<Option<T>, SceneError> where F: FnOnce(&str) -> Result<T, String> {
+        return match self.take(key) {
+            Some(entry) => parse(&entry.value).map(Some).map_err(|message| parser.error(entry.line, Some(key), message)),
+            None => Ok(None),
+        };
+    }
+
+    fn string(&mut self, parser: &SceneParser, key: &str) -> Result<Option<String>, SceneError> {
+        return self.parse(parser, key, |value| {
+            if value.is_empty() { Err("expected a value".to_string()) } else { Ok(value.to_string()) }
+        });
+    }
+
+    fn f64(&mut self, parser: &SceneParser, key: &str) -> Result<Option<f64>, SceneError> {
+        return self.parse(parser, key, parse_number);
+    }
+
+    fn positive_f64(&mut self, parser: &SceneParser, key: &str) -> Result<Option<f64>, SceneError> {
+        return self.parse(parser, key, |value| {
+            let number = parse_number(value)?;
+            if number > 0. { Ok(number) } else { Err(format!("expected a positive number, found `{}`", value)) }
+        });
+    }
+
+    fn non_negative_f64(&mut self, parser: &SceneParser, key: &str) -> Result<Option<f64>, SceneError> {
+        return self.parse(parser, key, |value| {
+            let number = parse_number(value)?;
+            if number >= 0. { Ok(number) } else { Err(format!("expected a non-negative number, found `{}`", value)) }
+        });
+    }
+
+    fn usize(&mut self, parser: &SceneParser, key: &str) -> Result<Option<usize>, SceneError> {
+        return self.parse(parser, key, |value| {
+            value.parse::<usize>().map_err(|_| format!("expected a non-negative integer, found `{}`", value))
+        });
+    }
+
+    fn positive_usize(&mut self, parser: &SceneParser, key: &str) -> Result<Option<usize>, SceneError> {
+        return self.parse(parser, key, |value| {
+            match value.parse::<usize>() {
+                Ok(number) if number > 0 => Ok(number),
+                _ => Err(format!("expected a positive integer, found `{}`", value)),
+            }
+        });
+    }
+
+    fn bool(&mut self, parser: &SceneParser, key: &str) -> Result<Option<bool>, SceneError> {
+        return self.parse(parser, key, |value| {
+            match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(format!("expected `true` or `false`, found `{}`", value)),
+            }
+        });
+    }
+
+    fn vec3(&mut self, parser: &SceneParser, key: &str) -> Result<Option<Vec3d>, SceneError> {
+        return self.parse(parser, key, |value| {
+            let numbers = parse_numbers(value, 3)?;
+            Ok(Vec3d::new(numbers[0], numbers[1], numbers[2]))
+        });
+    }
+
+    fn range(&mut self, parser: &SceneParser, key: &str) -> Result<Option<(f64, f64)>, SceneError> {
+        return self.parse(parser, key, |value| {
+            let numbers = parse_numbers(value, 2)?;
+            if numbers[0] < numbers[1] { Ok((numbers[0], numbers[1])) } else { Err(format!("expected `min max` with min < max, found `{}`", value)) }
+        });
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    return match value.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("expected a number, found `{}`", value)),
+    };
+}
+
+//one factor for all axes or one per axis
+fn parse_scale(value: &str) -> Result<Vec3d, String> {
+    let numbers = value.split_whitespace().map(parse_number).collect::<Result<Vec<f64>, String>>()?;
+    let factors = match numbers.len() {
+        1 => Vec3d::new(numbers[0], numbers[0], numbers[0]),
+        3 => Vec3d::new(numbers[0], numbers[1], numbers[2]),
+        n => return Err(format!("expected 1 or 3 numbers, found {}", n)),
+    };
+    if factors.x == 0. || factors.y == 0. || factors.z == 0. {
+        return Err("scale must not be 0".to_string());
+    }
+    return Ok(factors);
+}
+
+//axis and angle in degrees
+fn parse_rotation(value: &str) -> Result<(Vec3d, f64), String> {
+    let numbers = parse_numbers(value, 4)?;
+    let axis = Vec3d::new(numbers[0], numbers[1], numbers[2]);
+    if axis.length_squared() == 0. {
+        return Err("the rotation axis must not be 0 0 0".to_string());
+    }
+    return Ok((axis, numbers[3]));
+}
+
+fn parse_numbers(value: &str, count: usize) -> Result<Vec<f64>, String> {
+    let numbers = value.split_whitespace().map(parse_number).collect::<Result<Vec<f64>, String>>()?;
+    if numbers.len() != count {
+        return Err(format!("expected {} numbers, found {}", count, numbers.len()));
+    }
+    return Ok(numbers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn parse(source: &str) -> Result<Scene, SceneError> {
+        return Scene::parse(source, "test.scene", Path::new(""), None);
+    }
+
+    //the named materials, which the scene only keeps inside the objects using them
+    fn parse_materials(source: &str) -> HashMap<String, Arc<Material>> {
+        let sections = parse_sections(source, "test.scene").unwrap();
+        let mut parser = SceneParser{
+            file: "test.scene",
+            base_directory: PathBuf::new(),
+            seed: None,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            objects: HashMap::new(),
+        };
+        parser.definitions(&sections).unwrap();
+        return parser.materials;
+    }
+
+    #[test]
+    fn test_default_scene(){
+        let scene = parse(include_str!("../scenes/default.scene")).unwrap();
+        assert_eq!(1920, scene.render.image_width);
+        assert_eq!(7, scene.render.max_bounces);
+        assert_eq!(108, scene.objects.len());
+        assert_eq!(2, scene.lights.len());
+        assert_eq!(80., parse_materials(include_str!("../scenes/default.scene"))["emission_white"].emission_intensity);
+        assert_eq!(vec![(OutputFormat::Png, "output/sample.png".to_string())], scene.output.files);
+        assert_eq!(vec![0], scene.seeds);
+        assert_eq!(ToneMapping::Aces, scene.output.display_transform.tone_mapping);
+    }
+
+    #[test]
+    fn test_tone_mapping(){
+        let scene = parse("[output]\ntone_mapping = extended_reinhard\nwhite_point = 8\nexposure = -1.5\n").unwrap();
+        assert_eq!(DisplayTransform{ exposure: -1.5, tone_mapping: ToneMapping::ExtendedReinhard{ white_point: 8. } }, scene.output.display_transform);
+
+        let error = parse("[output]\ntone_mapping = agx\nwhite_point = 8\n").err().unwrap();
+        assert_eq!("test.scene:3: `white_point`: only used by tone_mapping = extended_reinhard", error.to_string());
+        assert!(parse("[output]\ntone_mapping = filmic\n").is_err());
+    }
+
+    #[test]
+    fn test_metal_keys(){
+        let materials = parse_materials("[material gold]\nreflectivity = 1\nroughness = 0.3\nmetal = gold\n\n[material old]\nreflectivity = 1\nreflection_fuzz = 0.25\nreflection_color = 1 0.5 0\n");
+        let gold = Material::builder().reflection(1., 0.3).reflection_fresnel(ConductorFresnel::preset("gold").unwrap()).build();
+        assert!(materials["gold"].scatters_like(&gold));
+        //fuzz 0.25 is roughness 0.5
+        let old = Material::builder().reflection(1., 0.5).reflection_color(Vec3d::new(1., 0.5, 0.)).build();
+        assert!(materials["old"].scatters_like(&old));
+        assert!(!materials["old"].scatters_like(&gold));
+
+        let error = parse("[material a]\nroughness = 0.3\nsmoothness = 0.5\n").err().unwrap();
+        assert_eq!("test.scene:3: `smoothness`: only one of roughness, smoothness and reflection_fuzz can be set", error.to_string());
+        let error = parse("[material a]\nmetal = unobtainium\n").err().unwrap();
+        assert_eq!("test.scene:2: `metal`: expected one of gold, silver, copper, aluminium, found `unobtainium`", error.to_string());
+        assert!(parse("[material a]\nconductor_eta = 1 1 1\n").is_err());
+    }
+
+    #[test]
+    fn test_principled(){
+        let scene = parse("[sphere]\ncenter = 0 0 -1\nradius = 0.5\nmaterial = paint\n\n[principled paint]\nbase_color = 0.8 0.1 0.1\nmetallic = 0.2\nclearcoat = 1\n").unwrap();
+        let paint = Material::principled().base_color(Vec3d::new(0.8, 0.1, 0.1)).metallic(0.2).clearcoat(1., 0.03).build();
+        let rec = scene.objects[0].hit(&Ray::new(Vec3d::zero(), Vec3d::forward()), 0.001..f64::INFINITY).unwrap();
+        assert!(rec.material.scatters_like(&paint));
+        assert!(!rec.material.scatters_like(&Material::principled().base_color(Vec3d::new(0.8, 0.1, 0.1)).build()));
+
+        let error = parse("[principled paint]\nalbedo = 1 1 1\n").err().unwrap();
+        assert_eq!("test.scene:2: `albedo`: unknown key", error.to_string());
+        let error = parse("[principled]\n").err().unwrap();
+        assert_eq!("test.scene:1: principled section needs a name, e.g. [principled glass]", error.to_string());
+    }
+
+    #[test]
+    fn test_textures(){
+        let source = "[material floor]\nalbedo_texture = tiles\nabsorption = 0.5\n\n[texture tiles]\ntype = checker\neven = 0 0 0\nodd = 1 1 1\nfrequency = 4\n\n[texture veins]\ntype = marble\nscale = 3\n\n[principled stone]\nbase_color_texture = veins\n";
+        assert!(parse(source).is_ok());
+        assert_eq!(2, parse_materials(source).len());
+
+        let error = parse("[material a]\nalbedo_texture = missing\n").err().unwrap();
+        assert_eq!("test.scene:2: `albedo_texture`: unknown texture `missing`", error.to_string());
+        let error = parse("[texture veins]\ntype = marble\n\n[principled stone]\nbase_color_texture = veins\nmetallic = 0.5\n").err().unwrap();
+        assert_eq!("test.scene:5: `base_color_texture`: cannot be combined with metallic or transmission", error.to_string());
+        let error = parse("[texture a]\ntype = plaid\n").err().unwrap();
+        assert_eq!("test.scene:2: `type`: expected one of constant, checker, noise, marble, wood, image, found `plaid`", error.to_string());
+        let error = parse("[texture a]\ntype = image\npath = missing.png\n").err().unwrap();
+        assert_eq!((3, Some("path".to_string())), (error.line, error.key));
+        let error = parse("[texture a]\ntype = image\npath = a.png\ncolor_space = aces\n").err().unwrap();
+        assert_eq!("test.scene:4: `color_space`: expected one of srgb, linear, found `aces`", error.to_string());
+    }
+
+    #[test]
+    fn test_environment(){
+        let directory = std::env::temp_dir().join("raytracer_scene_test_environment");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("sky.ppm"), "P3 2 1 255 255 255 255 0 0 0").unwrap();
+        let scene = Scene::parse("[environment]\npath = sky.ppm\nintensity = 2\nrotation = 90\n", "test.scene", &directory, None).unwrap();
+        //the environment is seen by escaping rays as well as sampled
+        assert!(scene.lights[0].background(Vec3d::up()).is_some());
+        assert_eq!(1, scene.lights.len());
+
+        let error = parse("[environment]\npath = missing.hdr\n").err().unwrap();
+        assert_eq!((2, Some("path".to_string())), (error.line, error.key));
+        let error = Scene::parse("[environment]\npath = sky.ppm\n[environment]\npath = sky.ppm\n", "test.scene", &directory, None).err().unwrap();
+        assert_eq!("test.scene:3: section [environment] can only appear once", error.to_string());
+    }
+
+    #[test]
+    fn test_flat_shapes(){
+        let scene = parse("[material white]\nalbedo = 1 1 1\n\n[plane]\npoint = 0 0 0\nnormal = 0 1 0\nmaterial = white\n\n[quad]\ncorner = -1 0 -1\nu = 2 0 0\nv = 0 2 0\nmaterial = white\n\n[box]\nmin = 0 0 -3\nmax = 1 1 -2\nmaterial = white\n").unwrap();
+        assert_eq!(3, scene.objects.len());
+
+        let error = parse("[material white]\n[quad]\ncorner = 0 0 0\nu = 1 0 0\nv = 2 0 0\nmaterial = white\n").err().unwrap();
+        assert_eq!("test.scene:5: `v`: u and v must not be parallel", error.to_string());
+        let error = parse("[material white]\n[box]\nmin = 0 0 0\nmax = 1 0 1\nmaterial = white\n").err().unwrap();
+        assert_eq!((4, Some("max".to_string())), (error.line, error.key));
+        let error = parse("[material white]\n[plane]\npoint = 0 0 0\nmaterial = white\n").err().unwrap();
+        assert_eq!("test.scene:2: `normal`: missing required key", error.to_string());
+
+        let cornell = parse(include_str!("../scenes/cornell.scene")).unwrap();
+        assert_eq!(8, cornell.objects.len());
+        assert_eq!(1, cornell.lights.len());
+
+        //emissive quads and every side of emissive boxes are lights, unless they are named objects
+        let scene = parse("[material lamp]\nemission_intensity = 1\n\n[quad]\ncorner = 0 2 0\nu = 1 0 0\nv = 0 0 1\nmaterial = lamp\n\n[box]\nmin = 0 0 0\nmax = 1 1 1\nmaterial = lamp\n\n[quad panel]\ncorner = 0 2 0\nu = 1 0 0\nv = 0 0 1\nmaterial = lamp\n").unwrap();
+        assert_eq!(7, scene.lights.len());
+    }
+
+    #[test]
+    fn test_instances(){
+        let error = parse("[material white]\nemission = 1 1 1\n\n[instance]\nobject = pair\nscale = 2\nrotate = 0 1 0 90\ntranslate = 0 0 -5\n\n[instance]\nobject = pair\n\n[sphere pair]\ncenter = 1 0 0\nradius = 0.5\nmaterial = white\n\n[sphere pair]\ncenter = -1 0 0\nradius = 0.5\nmaterial = white\n").err().unwrap();
+        assert_eq!("test.scene:18: object `pair` is defined twice", error.to_string());
+
+        //instances may come before the object they place
+        let scene = parse("[material white]\nemission = 1 1 1\n\n[instance]\nobject = ball\nscale = 2\nrotate = 0 1 0 90\ntranslate = 0 0 -5\n\n[instance]\nobject = ball\nmatrix = 1 0 0 3  0 1 0 0  0 0 1 0  0 0 0 1\n\n[sphere ball]\ncenter = 1 0 0\nradius = 0.5\nmaterial = white\n").unwrap();
+        //the named sphere is only placed by the instances and is not a light
+        assert_eq!(2, scene.objects.len());
+        assert_eq!(0, scene.lights.len());
+        let bbox = scene.objects[0].bounding_box();
+        assert!((bbox.min - Vec3d::new(-1., -1., -8.)).length() < 1e-9 && (bbox.max - Vec3d::new(1., 1., -6.)).length() < 1e-9);
+        assert!((scene.objects[1].bounding_box().centroid() - Vec3d::new(4., 0., 0.)).length() < 1e-9);
+
+        let error = parse("[instance]\nobject = tree\n").err().unwrap();
+        assert_eq!("test.scene:2: `object`: unknown object `tree`", error.to_string());
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nscale = 1 0\n").err().unwrap();
+        assert_eq!("test.scene:8: `scale`: expected 1 or 3 numbers, found 2", error.to_string());
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nmatrix = 1 0 0 0 0 1 0 0 0 0 0 0 0 0 0 1\n").err().unwrap();
+        assert_eq!("test.scene:8: `matrix`: the matrix cannot be inverted", error.to_string());
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nmatrix = 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0.5 1\n").err().unwrap();
+        assert_eq!("test.scene:8: `matrix`: the bottom row of the matrix must be `0 0 0 1`", error.to_string());
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nmatrix = 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1\ntranslate = 1 0 0\n").err().unwrap();
+        assert_eq!((8, Some("matrix".to_string())), (error.line, error.key));
+    }
+
+    #[test]
+    fn test_motion_blur(){
+        let scene = parse("[camera]\nshutter = 0.25 0.75\n\n[material lamp]\nemission = 1 1 1\n\n[sphere]\ncenter = -2 0 -5\ncenter_end = 2 0 -5\nradius = 1\nmaterial = lamp\n\n[box crate]\nmin = -1 -1 -1\nmax = 1 1 1\nmaterial = lamp\n\n[instance]\nobject = crate\ntranslate = 0 0 -5\nrotate = 0 1 0 0\nrotate_end = 0 1 0 90\n").unwrap();
+        assert_eq!((0.25, 0.75), scene.camera.shutter);
+        //moving spheres are not sampled as lights
+        assert_eq!(0, scene.lights.len());
+        assert_eq!(-3., scene.objects[0].bounding_box().min.x);
+        //the rotating box covers its diagonal halfway
+        assert!(scene.objects[1].bounding_box().max.x >= 2f64.sqrt());
+        let ray = Ray::new(Vec3d::new(1.3, 0., 0.), Vec3d::forward());
+        assert!(scene.objects[1].hit(&ray, 0.001..f64::INFINITY).is_none());
+        assert!(scene.objects[1].hit(&ray.with_time(0.5), 0.001..f64::INFINITY).is_some());
+
+        let error = parse("[camera]\nshutter = 0.5 0.25\n").err().unwrap();
+        assert_eq!("test.scene:2: `shutter`: expected `open close` with 0 <= open <= close <= 1, found `0.5 0.25`", error.to_string());
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nmatrix = 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1\ntranslate_end = 1 0 0\n").err().unwrap();
+        assert_eq!((8, Some("matrix".to_string())), (error.line, error.key));
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[instance]\nobject = ball\nscale = 1 2 1\nscale_end = 1 -2 1\n").err().unwrap();
+        assert_eq!("test.scene:9: `scale_end`: scale_end must have the same signs as scale", error.to_string());
+    }
+
+    #[test]
+    fn test_media(){
+        let scene = parse("[material glass]\nrefraction_index = 1.5\nrefractioness = 1\n\n[medium]\nobject = bubble\nscattering = 1 1 1\ndensity = 0.5\nanisotropy = 0.7\n\n[sphere bubble]\ncenter = 0 0 -5\nradius = 1\nmaterial = glass\n\n[atmosphere]\nabsorption = 0.01 0.02 0.03\n").unwrap();
+        //the boundary is not placed
+        assert_eq!(0, scene.objects.len());
+        let media = crate::medium::MediumList::new(scene.media);
+        let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
+        let transmittance = media.transmittance(&Ray::new(Vec3d::zero(), Vec3d::forward()), 10., &mut rng);
+        let expected = Vec3d::new(f64::exp(-1. - 0.1), f64::exp(-1. - 0.2), f64::exp(-1. - 0.3));
+        assert!((transmittance - expected).length() < 1e-12);
+
+        let error = parse("[atmosphere]\nabsorption = 0.1 0.1 0.1\n[atmosphere]\n").err().unwrap();
+        assert_eq!("test.scene:3: section [atmosphere] can only appear once", error.to_string());
+        let error = parse("[atmosphere]\nscattering = 0.1 -0.1 0.1\n").err().unwrap();
+        assert_eq!("test.scene:2: `scattering`: coefficients must not be negative", error.to_string());
+        let error = parse("[atmosphere]\nanisotropy = 1\n").err().unwrap();
+        assert_eq!((2, Some("anisotropy".to_string())), (error.line, error.key));
+        let error = parse("[medium]\nobject = cloud\n").err().unwrap();
+        assert_eq!("test.scene:2: `object`: unknown object `cloud`", error.to_string());
+    }
+
+    #[test]
+    fn test_volumes(){
+        let directory = std::env::temp_dir().join("raytracer_scene_test_volumes");
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut vol = b"VOL\x03".to_vec();
+        for int in [1i32, 1, 1, 1, 1] {
+            vol.extend(int.to_le_bytes());
+        }
+        for float in [-1f32, -1., -4., 1., 1., -2., 0.5] {
+            vol.extend(float.to_le_bytes());
+        }
+        std::fs::write(directory.join("cloud.vol"), vol).unwrap();
+        std::fs::write(directory.join("fire.raw"), [1f32; 8].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+        let parse_in = |source: &str| Scene::parse(source, "test.scene", &directory, None);
+
+        //the .vol file places the cloud two units in front of the camera
+        let scene = parse_in("[volume]\npath = cloud.vol\nabsorption = 1 2 4\ndensity = 0.5\n\n[volume]\npath = fire.raw\nresolution = 2 2 2\nmin = 5 5 5\nmax = 6 6 6\nabsorption = 1 1 1\nemission_path = fire.raw\nemission = 4 2 1\n").unwrap();
+        assert_eq!(2, scene.media.len());
+        let media = crate::medium::MediumList::new(scene.media);
+        let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
+        let n = 20000;
+        let transmittance = (0..n).fold(Vec3d::zero(), |sum, _| sum + media.transmittance(&Ray::new(Vec3d::zero(), Vec3d::forward()), 10., &mut rng) / n as f64);
+        let expected = Vec3d::new(f64::exp(-0.5), f64::exp(-1.), f64::exp(-2.));
+        assert!((transmittance - expected).length() < 0.01, "transmittance {:?}", transmittance);
+
+        let error = parse_in("[volume]\npath = fire.raw\nmin = 0 0 0\nmax = 1 1 1\n").err().unwrap();
+        assert!(error.to_string().starts_with("test.scene:2: `path`: ") && error.to_string().ends_with("raw grid files need a resolution"), "{}", error);
+        let error = parse_in("[volume]\npath = fire.raw\nresolution = 2 2\n").err().unwrap();
+        assert_eq!("test.scene:3: `resolution`: expected 3 positive integers, found `2 2`", error.to_string());
+        let error = parse_in("[volume]\npath = fire.raw\nresolution = 2 2 2\n").err().unwrap();
+        assert_eq!("test.scene:2: `path`: raw grid files need min and max", error.to_string());
+        let error = parse_in("[volume]\npath = cloud.vol\nmin = 0 0 0\n").err().unwrap();
+        assert_eq!("test.scene:3: `min`: set both min and max or neither", error.to_string());
+        let error = parse_in("[volume]\npath = cloud.vol\nemission = 1 1 1\n").err().unwrap();
+        assert_eq!("test.scene:3: `emission`: only used with emission_path", error.to_string());
+        let error = parse_in("[volume]\npath = cloud.vol\nscattering = 1 -1 1\n").err().unwrap();
+        assert_eq!("test.scene:3: `scattering`: coefficients must not be negative", error.to_string());
+    }
+
+    #[test]
+    fn test_scatter(){
+        let source = "[material white]\n[material red]\nalbedo = 1 0 0\nabsorption = 0.5\n\n[box crate]\nmin = -0.5 0 -0.5\nmax = 0.5 1 0.5\nmaterial = white\n\n[scatter]\nobject = crate\nmaterial = red\ncount = 200\nseed = 7\nx = -10 10\ny = 0 0.1\nz = -20 -5\nscale = 0.5 2\nrotate = true\n\n[instance]\nobject = crate\nmaterial = red\n";
+        let scene = parse(source).unwrap();
+        assert_eq!(201, scene.objects.len());
+        assert_eq!(vec![7], scene.seeds);
+        let red = Material::builder().albedo(Vec3d::new(1., 0., 0.), 0.5).build();
+        for object in &scene.objects[..200] {
+            let bbox = object.bounding_box();
+            //a rotated box of up to twice the size still stands in the region
+            assert!(bbox.min.x > -11.5 && bbox.max.x < 11.5 && bbox.min.z > -21.5 && bbox.max.z < -3.5);
+            assert!(bbox.min.y >= -1e-9 && bbox.max.y <= 2.1 && bbox.max.y >= 0.5);
+            let center = bbox.centroid();
+            let rec = object.hit(&Ray::new(Vec3d::new(center.x, 10., center.z), Vec3d::down()), 0.001..f64::INFINITY).unwrap();
+            assert!(rec.material.scatters_like(&red));
+        }
+        //the seed from the command line replaces the one in the file
+        let other = Scene::parse(source, "test.scene", Path::new(""), Some(8)).unwrap();
+        assert_eq!(vec![8], other.seeds);
+        assert!(scene.objects[0].bounding_box() != other.objects[0].bounding_box());
+
+        let error = parse("[material white]\n[sphere ball]\ncenter = 0 0 0\nradius = 1\nmaterial = white\n[scatter]\nobject = ball\ncount = 3\nx = 0 1\ny = 0 1\nz = 0 1\nscale = -1 1\n").err().unwrap();
+        assert_eq!("test.scene:12: `scale`: scale must be positive", error.to_string());
+    }
+
+    #[test]
+    fn test_delta_lights(){
+        let scene = parse("[point_light]\nposition = 0 2 0\npower = 800\n\n[spot_light]\nposition = 0 3 0\ndirection = 0 -1 0.2\ninner_angle = 20\nouter_angle = 30\nintensity = 100\ncolor = 1 0.9 0.8\n\n[directional_light]\ndirection = -1 -1 0\nilluminance = 5\n").unwrap();
+        assert_eq!(3, scene.lights.len());
+
+        let error = parse("[point_light]\nposition = 0 2 0\nintensity = 1\npower = 800\n").err().unwrap();
+        assert_eq!("test.scene:4: `power`: set either intensity or power, not both", error.to_string());
+        let error = parse("[spot_light]\nposition = 0 2 0\ninner_angle = 50\n").err().unwrap();
+        assert_eq!("test.scene:3: `inner_angle`: inner_angle must not exceed outer_angle 45", error.to_string());
+        let error = parse("[directional_light]\ndirection = 0 0 0\n").err().unwrap();
+        assert_eq!((2, Some("direction".to_string())), (error.line, error.key));
+        let error = parse("[directional_light]\nilluminance = 2\n").err().unwrap();
+        assert_eq!("test.scene:1: `direction`: missing required key", error.to_string());
+    }
+
+    #[test]
+    fn test_sky(){
+        let scene = parse("[sky]\nsun_elevation = 20\nsun_azimuth = 135\nturbidity = 4\n").unwrap();
+        assert_eq!(2, scene.lights.len());
+        let scene = parse("[sky]\nsun = false\nintensity = 0.5\n").unwrap();
+        assert_eq!(1, scene.lights.len());
+
+        let error = parse("[sky]\nsun_elevation = -5\n").err().unwrap();
+        assert_eq!("test.scene:2: `sun_elevation`: expected an angle above 0 and up to 90 degrees, found -5", error.to_string());
+        let error = parse("[sky]\nturbidity = 20\n").err().unwrap();
+        assert_eq!((2, Some("turbidity".to_string())), (error.line, error.key));
+        let error = parse("[sky]\n[environment]\npath = sky.hdr\n").err().unwrap();
+        assert_eq!("test.scene:2: use either [environment] or [sky], not both", error.to_string());
+    }
+
+    #[test]
+    fn test_materials_can_be_defined_after_use(){
+        let scene = parse("[sphere]\ncenter = 0 0 -1\nradius = 0.5\nmaterial = red\n\n[material red]\nalbedo = 1 0 0\n").unwrap();
+        assert_eq!(1, scene.objects.len());
+        assert_eq!(225, scene.render.image_height);
+    }
+
+    #[test]
+    fn test_errors_point_to_line_and_key(){
+        let error = parse("[render]\nwidth = 10\nheight = ten\n").err().unwrap();
+        assert_eq!("test.scene:3: `height`: expected a positive integer, found `ten`", error.to_string());
+
+        let error = parse("[material a]\n\n[sphere]\ncenter = 0 0\nradius = 1\nmaterial = a\n").err().unwrap();
+        assert_eq!((4, Some("center".to_string())), (error.line, error.key));
+
+        let error = parse("[material a]\n[sphere]\ncenter = 0 0 0\nradius = 1\nmaterial = b\n").err().unwrap();
+        assert_eq!("test.scene:5: `material`: unknown material `b`", error.to_string());
+
+        let error = parse("[sphere]\ncenter = 0 0 0\nmaterial = b\n").err().unwrap();
+        assert_eq!("test.scene:1: `radius`: missing required key", error.to_string());
+
+        let error = parse("[camera]\nvertical_fov = 40\nvertical_fvo = 2\n").err().unwrap();
+        assert_eq!("test.scene:3: `vertical_fvo`: unknown key", error.to_string());
+
+        let error = parse("[camera]\nvertical_fov = 40\nvertical_fov = 2\n").err().unwrap();
+        assert_eq!("test.scene:3: `vertical_fov`: duplicate key, first set on line 2", error.to_string());
+
+        let error = parse("[camera]\nlook_from = 0 2 0\nlook_at = 0 0 0\n").err().unwrap();
+        assert_eq!("test.scene:1: `vup`: vup must not be parallel to the view direction", error.to_string());
+
+        let error = parse("[lights]\n").err().unwrap();
+        assert_eq!("test.scene:1: unknown section `lights`", error.to_string());
+    }
+}
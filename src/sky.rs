@@ -0,0 +1,193 @@
+use std::f64::consts::PI;
+use crate::environment::equirectangular_direction;
+use crate::image::Image;
+use crate::light::{sample_cone, Light, LightSample};
+use crate::vec3d::Vec3d;
+
+//analytic daylight from preetham, shirley and smits, "a practical analytic model for daylight" (1999)
+//radiance is in cd/m^2 like the other lights, the model gives the zenith luminance in kcd/m^2
+
+//half of the 0.533 degree diameter of the sun
+const SUN_ANGULAR_RADIUS_DEGREES: f64 = 0.2665;
+//luminance of the sun outside the atmosphere in cd/m^2, 128 klux over the solid angle of the disk
+const SUN_LUMINANCE: f64 = 1.88e9;
+//kcd/m^2 of the model to cd/m^2
+const KILO: f64 = 1000.;
+//wavelengths in micrometers standing in for the red, green and blue channels
+const WAVELENGTHS: [f64; 3] = [0.65, 0.57, 0.475];
+
+//elevation above the horizon and azimuth from -z towards +x, in degrees
+pub(crate) fn sun_direction(elevation: f64, azimuth: f64) -> Vec3d {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    return Vec3d::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos());
+}
+
+pub(crate) struct PreethamSky {
+    sun_direction: Vec3d,
+    //perez coefficients a to e of the luminance and the x and y chromaticity
+    coefficients: [[f64; 5]; 3],
+    //luminance and chromaticity at the zenith divided by the perez function there
+    zenith: [f64; 3],
+}
+
+impl PreethamSky {
+    //turbidity is about 2 for a clear sky and 10 for haze
+    pub(crate) fn new(sun_direction: Vec3d, turbidity: f64) -> Self {
+        let t = turbidity;
+        let coefficients = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let sun_direction = sun_direction.unit();
+        let theta_sun = sun_direction.y.clamp(-1., 1.).acos();
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| ((c[0] * theta_sun + c[1]) * theta_sun + c[2]) * theta_sun + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.]) + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394]) + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.]) + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516]) + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        let zenith = [KILO * luminance, x, y];
+        Self{
+            sun_direction,
+            zenith: [0, 1, 2].map(|i| zenith[i] / perez(&coefficients[i], 1., theta_sun)),
+            coefficients,
+        }
+    }
+
+    //linear srgb, the horizon color continues below the horizon
+    pub(crate) fn radiance(&self, direction: Vec3d) -> Vec3d {
+        let cos_theta = direction.y.max(0.01);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1., 1.).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma));
+        if luminance <= 0. || y <= 0. {
+            return Vec3d::zero();
+        }
+        let (big_x, big_y, big_z) = (x / y * luminance, luminance, (1. - x - y) / y * luminance);
+        let rgb = Vec3d::new(
+            3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z,
+        );
+        return rgb.max(&Vec3d::zero());
+    }
+
+    //equirectangular image of the sky, importance sampled as an environment map
+    pub(crate) fn to_image(&self, width: usize, height: usize) -> Image {
+        let mut image = Image::new(height, width);
+        for row in 0..height {
+            for col in 0..width {
+                let uv = ((col as f64 + 0.5) / width as f64, (row as f64 + 0.5) / height as f64);
+                image.set_pixel_color(row, col, self.radiance(equirectangular_direction(uv)));
+            }
+        }
+        return image;
+    }
+}
+
+//relative brightness of the direction at angle theta from the zenith and gamma from the sun
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    return (1. + c[0] * (c[1] / cos_theta).exp()) * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2));
+}
+
+//disk of the sun, attenuated by rayleigh and aerosol scattering along its path through the atmosphere
+pub(crate) struct SunLight {
+    direction: Vec3d,
+    //1 - cos of the angular radius
+    one_minus_cos_max: f64,
+    radiance: Vec3d,
+}
+
+impl SunLight {
+    pub(crate) fn new(direction: Vec3d, turbidity: f64, intensity: f64) -> Self {
+        let direction = direction.unit();
+        let elevation = direction.y.clamp(-1., 1.).asin().to_degrees();
+        //relative optical air mass after kasten and young, 1 at the zenith
+        let air_mass = 1. / (direction.y.max(0.) + 0.50572 * (elevation + 6.07995).max(0.01).powf(-1.6364));
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = WAVELENGTHS.map(|wavelength| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            f64::exp(-(rayleigh + aerosol) * air_mass)
+        });
+        let half_angle = SUN_ANGULAR_RADIUS_DEGREES.to_radians();
+        Self{
+            direction,
+            //1 - cos without cancellation
+            one_minus_cos_max: 2. * (half_angle / 2.).sin().powi(2),
+            radiance: intensity * SUN_LUMINANCE * Vec3d::new(transmittance[0], transmittance[1], transmittance[2]),
+        }
+    }
+
+    fn contains(&self, direction: Vec3d) -> bool {
+        return 1. - direction.dot(&self.direction) <= self.one_minus_cos_max;
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _origin: Vec3d, rng: &mut dyn rand::RngCore) -> Option<LightSample> {
+        return Some(LightSample{
+            direction: sample_cone(self.direction, self.one_minus_cos_max, rng),
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1. / (2. * PI * self.one_minus_cos_max),
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, _origin: Vec3d, direction: Vec3d, distance: f64) -> f64 {
+        if distance.is_finite() || !self.contains(direction) {
+            return 0.;
+        }
+        return 1. / (2. * PI * self.one_minus_cos_max);
+    }
+
+    fn background(&self, direction: Vec3d) -> Option<Vec3d> {
+        return Some(if self.contains(direction) { self.radiance } else { Vec3d::zero() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_sky_is_brightest_near_the_sun(){
+        let sun = sun_direction(30., 90.);
+        assert!((sun - Vec3d::new(0.75f64.sqrt(), 0.5, 0.)).length() < 1e-12);
+        let sky = PreethamSky::new(sun, 3.);
+        let zenith = sky.radiance(Vec3d::up());
+        //a few kcd/m^2 and blue at the zenith
+        assert!(zenith.y > 1000. && zenith.y < 20000., "zenith {:?}", zenith);
+        assert!(zenith.z > zenith.x);
+        let near_sun = sky.radiance(sun_direction(32., 90.));
+        let opposite = sky.radiance(sun_direction(32., -90.));
+        assert!(near_sun.y > 2. * opposite.y);
+        //hazier skies are brighter and less saturated
+        let hazy = PreethamSky::new(sun, 8.).radiance(Vec3d::up());
+        assert!(hazy.y > zenith.y && hazy.z / hazy.x < zenith.z / zenith.x);
+    }
+
+    #[test]
+    fn test_sun_light(){
+        let sun = SunLight::new(sun_direction(90., 0.), 2., 1.);
+        //about 100 klux at noon, with a yellow tint
+        let illuminance = sun.radiance.y * 2. * PI * sun.one_minus_cos_max;
+        assert!(illuminance > 90000. && illuminance < 128000., "illuminance {}", illuminance);
+        assert!(sun.radiance.x > sun.radiance.z);
+        //redder and darker at sunset
+        let sunset = SunLight::new(sun_direction(2., 0.), 2., 1.);
+        assert!(sunset.radiance.y < 0.5 * sun.radiance.y);
+        assert!(sunset.radiance.x / sunset.radiance.z > 2. * sun.radiance.x / sun.radiance.z);
+
+        let mut rng = StdRng::from_seed([9; 32]);
+        for _ in 0..100 {
+            let sample = sun.sample(Vec3d::zero(), &mut rng).unwrap();
+            assert!(sun.contains(sample.direction));
+            assert_eq!(sample.pdf, sun.pdf(Vec3d::zero(), sample.direction, f64::INFINITY));
+        }
+        assert_eq!(0., sun.pdf(Vec3d::zero(), Vec3d::up(), 5.));
+        assert_eq!(Some(Vec3d::zero()), sun.background(Vec3d::right()));
+    }
+}
@@ -1,10 +1,14 @@
+use std::f64::consts::PI;
 use std::ops::{Range, RangeInclusive};
 use std::sync::Arc;
+use crate::aabb::Aabb;
 use crate::vec3d::Vec3d;
 use crate::hit::Hittable;
 use crate::hit::HitRecord;
+use crate::light::SphereLight;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::lerp_vec3d;
 
 pub(crate) struct Sphere{
     center: Vec3d,
@@ -20,34 +24,134 @@ impl Sphere{
             material
         }
     }
-}
 
-impl Hittable for Sphere{
-    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        //let a = ray.direction_no_unit.dot(ray.direction_no_unit);
-        let a = ray.direction_no_unit.length_squared();
-        //let b = 2. * oc.dot(ray.direction_no_unit);
-        let half_b = oc.dot(&ray.direction_no_unit);
-        //let c = oc.dot(oc) - radius * radius;
-        let c = oc.length_squared() - self.radius * self.radius;
-        //let discriminant = b*b - 4.*a*c;
-        let discriminant = half_b*half_b - a*c;
-        if (discriminant < 0.) {
+    //light for next event estimation if the material emits
+    pub(crate) fn light(&self) -> Option<SphereLight> {
+        let radiance = self.material.emitted();
+        if radiance == Vec3d::zero() {
             return None;
         }
+        return Some(SphereLight::new(self.center, self.radius, radiance));
+    }
+}
+
+//longitude and latitude of the unit outward normal, u = 0 at -x going around through +z, v = 0 at the bottom pole
+pub(crate) fn sphere_uv(unit_normal: Vec3d) -> (f64, f64) {
+    let theta = f64::acos((-unit_normal.y).clamp(-1., 1.));
+    let phi = f64::atan2(-unit_normal.z, unit_normal.x) + PI;
+    return (phi / (2. * PI), theta / PI);
+}
+
+//shared by still and moving spheres, the material is only cloned for a hit
+fn hit_sphere(center: Vec3d, radius: f64, material: &Arc<Material>, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+    let oc = ray.origin - center;
+    //let a = ray.direction_no_unit.dot(ray.direction_no_unit);
+    let a = ray.direction_no_unit.length_squared();
+    //let b = 2. * oc.dot(ray.direction_no_unit);
+    let half_b = oc.dot(&ray.direction_no_unit);
+    //let c = oc.dot(oc) - radius * radius;
+    let c = oc.length_squared() - radius * radius;
+    //let discriminant = b*b - 4.*a*c;
+    let discriminant = half_b*half_b - a*c;
+    if (discriminant < 0.) {
+        return None;
+    }
 
-        let sqrt_discriminant = f64::sqrt(discriminant);
-        let mut root = (-half_b -sqrt_discriminant) / a;
+    let sqrt_discriminant = f64::sqrt(discriminant);
+    let mut root = (-half_b -sqrt_discriminant) / a;
+    if(!interval.contains(&root)){
+        root = (-half_b + sqrt_discriminant) / a;
         if(!interval.contains(&root)){
-            root = (-half_b + sqrt_discriminant) / a;
-            if(!interval.contains(&root)){
-                return None;
-            }
+            return None;
         }
-        let pos = ray.at(root);
-        let out_dir = (pos - self.center) / self.radius;
-        let hit_record = HitRecord::with_unit_normal(pos, out_dir, root, ray.direction_no_unit, self.material.clone());
-        return Some(hit_record);
     }
-}
\ No newline at end of file
+    let pos = ray.at(root);
+    let out_dir = (pos - center) / radius;
+    let hit_record = HitRecord::with_unit_normal(pos, out_dir, root, ray.direction_no_unit, material.clone())
+        .with_uv(sphere_uv(out_dir * radius.signum()));
+    return Some(hit_record);
+}
+
+impl Hittable for Sphere{
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        return hit_sphere(self.center, self.radius, &self.material, ray, interval);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        //radius can be negative for hollow spheres
+        let r = self.radius.abs();
+        let r_vec = Vec3d::new(r, r, r);
+        return Aabb::new(self.center - r_vec, self.center + r_vec);
+    }
+}
+
+//sphere moving in a straight line from center at time 0 to center_end at time 1, for motion blur
+//it is not sampled as a light, emission is only found by bsdf sampling
+pub(crate) struct MovingSphere{
+    center: Vec3d,
+    center_end: Vec3d,
+    radius: f64,
+    material: Arc<Material>,
+}
+
+impl MovingSphere{
+    pub(crate) fn new(center: Vec3d, center_end: Vec3d, radius: f64, material: Arc<Material>) -> Self{
+        Self{
+            center,
+            center_end,
+            radius,
+            material
+        }
+    }
+
+    //times outside of 0..1 keep the sphere at the ends of its motion
+    fn center_at(&self, time: f64) -> Vec3d {
+        return lerp_vec3d(self.center, self.center_end, time.clamp(0., 1.));
+    }
+}
+
+impl Hittable for MovingSphere{
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        return hit_sphere(self.center_at(ray.time), self.radius, &self.material, ray, interval);
+    }
+
+    //encloses the whole motion
+    fn bounding_box(&self) -> Aabb {
+        let r = self.radius.abs();
+        let r_vec = Vec3d::new(r, r, r);
+        let start = Aabb::new(self.center - r_vec, self.center + r_vec);
+        let end = Aabb::new(self.center_end - r_vec, self.center_end + r_vec);
+        return start.surrounding(&end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uv(){
+        let material = Arc::new(Material::builder().build());
+        let sphere = Sphere::new(Vec3d::new(0., 0., -2.), 1., material);
+        //front of the sphere, facing +z
+        let rec = sphere.hit(&Ray::new(Vec3d::zero(), Vec3d::forward()), 0.001..f64::INFINITY).unwrap();
+        assert!((rec.uv.0 - 0.25).abs() < 1e-12 && (rec.uv.1 - 0.5).abs() < 1e-12);
+        assert_eq!((0.5, 1.), sphere_uv(Vec3d::up()));
+        assert_eq!(0., sphere_uv(Vec3d::down()).1);
+        assert!((sphere_uv(Vec3d::right()).0 - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_moving_sphere(){
+        let material = Arc::new(Material::builder().build());
+        let sphere = MovingSphere::new(Vec3d::new(-2., 0., -5.), Vec3d::new(2., 0., -5.), 1., material);
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        assert!(sphere.hit(&ray, 0.001..f64::INFINITY).is_none());
+        let rec = sphere.hit(&ray.with_time(0.5), 0.001..f64::INFINITY).unwrap();
+        assert_eq!((4., Vec3d::backward()), (rec.t, rec.normal));
+        let side = Ray::new(Vec3d::new(2., 0., 0.), Vec3d::forward());
+        assert!(sphere.hit(&side.with_time(1.), 0.001..f64::INFINITY).is_some());
+        assert!(sphere.hit(&side.with_time(2.), 0.001..f64::INFINITY).is_some());
+        assert_eq!(Aabb::new(Vec3d::new(-3., -1., -6.), Vec3d::new(3., 1., -4.)), sphere.bounding_box());
+    }
+}
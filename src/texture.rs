@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use rand::{Rng, RngCore};
+use crate::image::Image;
+use crate::vec3d::Vec3d;
+
+//colors varying over a surface, looked up with the texture coordinates and the position of a hit
+
+pub(crate) trait Texture {
+    fn value(&self, uv: (f64, f64), pos: Vec3d) -> Vec3d;
+}
+
+pub(crate) struct Constant {
+    color: Vec3d,
+}
+
+impl Constant {
+    pub(crate) fn new(color: Vec3d) -> Self {
+        Self{
+            color,
+        }
+    }
+}
+
+impl Texture for Constant {
+    fn value(&self, _uv: (f64, f64), _pos: Vec3d) -> Vec3d {
+        return self.color;
+    }
+}
+
+//alternates between two textures, in texture space or as a solid pattern in world space
+pub(crate) struct Checker {
+    even: Arc<dyn Texture + Send + Sync>,
+    odd: Arc<dyn Texture + Send + Sync>,
+    //squares per unit of uv or per unit of length
+    frequency: f64,
+    solid: bool,
+}
+
+impl Checker {
+    pub(crate) fn new(even: Arc<dyn Texture + Send + Sync>, odd: Arc<dyn Texture + Send + Sync>, frequency: f64) -> Self {
+        Self{
+            even,
+            odd,
+            frequency,
+            solid: false,
+        }
+    }
+
+    pub(crate) fn solid(mut self, solid: bool) -> Self {
+        self.solid = solid;
+        self
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, uv: (f64, f64), pos: Vec3d) -> Vec3d {
+        let cells = if self.solid {
+            [pos.x, pos.y, pos.z].map(|c| (c * self.frequency).floor() as i64).iter().sum::<i64>()
+        } else {
+            [uv.0, uv.1].map(|c| (c * self.frequency).floor() as i64).iter().sum::<i64>()
+        };
+        return if cells.rem_euclid(2) == 0 { self.even.value(uv, pos) } else { self.odd.value(uv, pos) };
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+//gradient noise with random unit vectors on the lattice
+pub(crate) struct Perlin {
+    gradients: Vec<Vec3d>,
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub(crate) fn new(rng: &mut dyn RngCore) -> Self {
+        let gradients = (0..PERLIN_POINTS).map(|_| {
+            loop {
+                let v = Vec3d::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                let length_squared = v.length_squared();
+                if length_squared > 1e-6 && length_squared <= 1. {
+                    return v.unit();
+                }
+            }
+        }).collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..PERLIN_POINTS).collect();
+            for i in (1..PERLIN_POINTS).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        let permutations = [permutation(), permutation(), permutation()];
+        Self{
+            gradients,
+            permutations,
+        }
+    }
+
+    //roughly in -1..1
+    pub(crate) fn noise(&self, p: Vec3d) -> f64 {
+        let floor = [p.x.floor(), p.y.floor(), p.z.floor()];
+        let fraction = [p.x - floor[0], p.y - floor[1], p.z - floor[2]];
+        let cell = floor.map(|f| f as i64);
+        //hermite smoothing of the weights
+        let smooth = fraction.map(|f| f * f * (3. - 2. * f));
+        let mut sum = 0.;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let index = (0..3).fold(0, |acc, axis| {
+                acc ^ self.permutations[axis][((cell[axis] + offset[axis] as i64) & (PERLIN_POINTS as i64 - 1)) as usize]
+            });
+            let to_point = Vec3d::new(fraction[0] - offset[0] as f64, fraction[1] - offset[1] as f64, fraction[2] - offset[2] as f64);
+            let weight = (0..3).map(|axis| if offset[axis] == 1 { smooth[axis] } else { 1. - smooth[axis] }).product::<f64>();
+            sum += weight * self.gradients[index].dot(&to_point);
+        }
+        return sum;
+    }
+
+    //fractal brownian motion, octaves of noise with doubling frequency and halving amplitude
+    pub(crate) fn fbm(&self, p: Vec3d, octaves: usize) -> f64 {
+        let mut sum = 0.;
+        let mut amplitude = 1.;
+        let mut point = p;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(point);
+            amplitude *= 0.5;
+            point = point * 2.;
+        }
+        return sum;
+    }
+
+    //fbm of the absolute noise, used to distort marble veins and wood rings
+    pub(crate) fn turbulence(&self, p: Vec3d, octaves: usize) -> f64 {
+        let mut sum = 0.;
+        let mut amplitude = 1.;
+        let mut point = p;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(point).abs();
+            amplitude *= 0.5;
+            point = point * 2.;
+        }
+        return sum;
+    }
+}
+
+//solid textures built from noise at the world position
+pub(crate) enum NoisePattern {
+    //fbm mapped to 0..1
+    Fbm,
+    //veins along the x axis
+    Marble,
+    //rings around the y axis
+    Wood,
+}
+
+pub(crate) struct NoiseTexture {
+    perlin: Perlin,
+    pattern: NoisePattern,
+    //blended from the first to the second color
+    colors: (Vec3d, Vec3d),
+    scale: f64,
+    octaves: usize,
+}
+
+impl NoiseTexture {
+    pub(crate) fn new(perlin: Perlin, pattern: NoisePattern, colors: (Vec3d, Vec3d), scale: f64, octaves: usize) -> Self {
+        Self{
+            perlin,
+            pattern,
+            colors,
+            scale,
+            octaves: octaves.max(1),
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _uv: (f64, f64), pos: Vec3d) -> Vec3d {
+        let p = pos * self.scale;
+        let t = match self.pattern {
+            NoisePattern::Fbm => 0.5 * (1. + self.perlin.fbm(p, self.octaves)),
+            NoisePattern::Marble => 0.5 * (1. + f64::sin(p.x + 10. * self.perlin.turbulence(p, self.octaves))),
+            NoisePattern::Wood => {
+                let rings = f64::sqrt(p.x * p.x + p.z * p.z) + 2. * self.perlin.turbulence(p, self.octaves);
+                rings - rings.floor()
+            }
+        };
+        let t = t.clamp(0., 1.);
+        return (1. - t) * self.colors.0 + t * self.colors.1;
+    }
+}
+
+//how texture coordinates outside 0..1 are mapped back onto the image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    pub(crate) const NAMES: [&'static str; 3] = ["repeat", "clamp", "mirror"];
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "clamp" => Some(WrapMode::Clamp),
+            "mirror" => Some(WrapMode::Mirror),
+            _ => None,
+        };
+    }
+
+    //texel index inside 0..size
+    fn texel(&self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::Clamp => index.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let period = index.rem_euclid(2 * size);
+                if period < size { period } else { 2 * size - 1 - period }
+            }
+        };
+        return wrapped as usize;
+    }
+}
+
+//bilinearly filtered image in linear color, v = 0 is the bottom row
+pub(crate) struct ImageTexture {
+    image: Arc<Image>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub(crate) fn new(image: Arc<Image>, wrap: WrapMode) -> Self {
+        Self{
+            image,
+            wrap,
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: (f64, f64), _pos: Vec3d) -> Vec3d {
+        let (width, height) = (self.image.width, self.image.height);
+        //texel centers are at half integer coordinates
+        let x = uv.0 * width as f64 - 0.5;
+        let y = (1. - uv.1) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |col: i64, row: i64| {
+            self.image.pixel_color(self.wrap.texel(row, height), self.wrap.texel(col, width))
+        };
+        let top = (1. - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+        let bottom = (1. - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+        return (1. - fy) * top + fy * bottom;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn constant(color: Vec3d) -> Arc<dyn Texture + Send + Sync> {
+        return Arc::new(Constant::new(color));
+    }
+
+    #[test]
+    fn test_checker(){
+        let checker = Checker::new(constant(Vec3d::zero()), constant(Vec3d::new(1., 1., 1.)), 2.);
+        assert_eq!(Vec3d::zero(), checker.value((0.1, 0.1), Vec3d::zero()));
+        assert_eq!(Vec3d::new(1., 1., 1.), checker.value((0.6, 0.1), Vec3d::zero()));
+        assert_eq!(Vec3d::zero(), checker.value((0.6, 0.6), Vec3d::zero()));
+        let solid = Checker::new(constant(Vec3d::zero()), constant(Vec3d::new(1., 1., 1.)), 1.).solid(true);
+        assert_eq!(Vec3d::new(1., 1., 1.), solid.value((0., 0.), Vec3d::new(-0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn test_perlin(){
+        let perlin = Perlin::new(&mut StdRng::from_seed([3; 32]));
+        //zero on the lattice, continuous and bounded in between
+        assert!(perlin.noise(Vec3d::new(2., -3., 5.)).abs() < 1e-12);
+        let p = Vec3d::new(0.3, 1.7, -2.2);
+        assert!((perlin.noise(p) - perlin.noise(p + Vec3d::new(1e-6, 0., 0.))).abs() < 1e-4);
+        let mut rng = StdRng::from_seed([4; 32]);
+        let values: Vec<f64> = (0..1000).map(|_| perlin.fbm(Vec3d::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0)), 4)).collect();
+        assert!(values.iter().all(|v| v.abs() < 2.));
+        assert!(values.iter().any(|v| *v > 0.2) && values.iter().any(|v| *v < -0.2));
+    }
+
+    #[test]
+    fn test_wrap_modes(){
+        assert_eq!([3, 0, 1], [-1, 4, 5].map(|i| WrapMode::Repeat.texel(i, 4)));
+        assert_eq!([0, 3, 3], [-1, 4, 5].map(|i| WrapMode::Clamp.texel(i, 4)));
+        assert_eq!([0, 3, 2, 2], [-1, 4, 5, -3].map(|i| WrapMode::Mirror.texel(i, 4)));
+        assert_eq!(Some(WrapMode::Mirror), WrapMode::from_name("mirror"));
+    }
+
+    #[test]
+    fn test_image_bilinear(){
+        //black left column, white right column
+        let mut image = Image::new(1, 2);
+        image.set_pixel_color(0, 0, Vec3d::zero());
+        image.set_pixel_color(0, 1, Vec3d::new(1., 1., 1.));
+        let texture = ImageTexture::new(Arc::new(image), WrapMode::Clamp);
+        assert_eq!(0., texture.value((0.25, 0.5), Vec3d::zero()).x);
+        assert_eq!(0.5, texture.value((0.5, 0.5), Vec3d::zero()).x);
+        assert_eq!(1., texture.value((1., 0.5), Vec3d::zero()).x);
+        let repeat = ImageTexture { image: texture.image.clone(), wrap: WrapMode::Repeat };
+        assert_eq!(0.5, repeat.value((1., 0.5), Vec3d::zero()).x);
+    }
+}
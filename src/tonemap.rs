@@ -0,0 +1,202 @@
+use crate::vec3d::Vec3d;
+
+//conversion of linear radiance to display values: exposure, tone mapping and the srgb transfer function
+
+pub(crate) const DEFAULT_WHITE_POINT: f64 = 4.;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ToneMapping {
+    //clips everything above 1
+    Linear,
+    Reinhard,
+    //luminance at the white point maps to 1
+    ExtendedReinhard{ white_point: f64 },
+    //filmic fit of the aces reference rendering and output transforms
+    Aces,
+    Agx,
+}
+
+impl ToneMapping {
+    pub(crate) const NAMES: [&'static str; 5] = ["linear", "reinhard", "extended_reinhard", "aces", "agx"];
+
+    pub(crate) fn from_name(name: &str) -> Option<ToneMapping> {
+        return match name.to_ascii_lowercase().as_str() {
+            "linear" => Some(ToneMapping::Linear),
+            "reinhard" => Some(ToneMapping::Reinhard),
+            "extended_reinhard" => Some(ToneMapping::ExtendedReinhard{ white_point: DEFAULT_WHITE_POINT }),
+            "aces" => Some(ToneMapping::Aces),
+            "agx" => Some(ToneMapping::Agx),
+            _ => None,
+        };
+    }
+
+    //linear display values, not yet clipped
+    pub(crate) fn map(&self, color: Vec3d) -> Vec3d {
+        return match self {
+            ToneMapping::Linear => color,
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1. + l)),
+            ToneMapping::ExtendedReinhard{ white_point } => {
+                let white_squared = white_point * white_point;
+                scale_luminance(color, |l| l * (1. + l / white_squared) / (1. + l))
+            }
+            ToneMapping::Aces => aces(color),
+            ToneMapping::Agx => agx(color),
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct DisplayTransform {
+    //in stops, every stop doubles the brightness
+    pub(crate) exposure: f64,
+    pub(crate) tone_mapping: ToneMapping,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self{
+            exposure: 0.,
+            tone_mapping: ToneMapping::Linear,
+        }
+    }
+}
+
+impl DisplayTransform {
+    //srgb encoded values in [0, 1]
+    pub(crate) fn apply(&self, linear: Vec3d) -> Vec3d {
+        let exposed = linear * 2f64.powf(self.exposure);
+        let mapped = self.tone_mapping.map(exposed.max(&Vec3d::zero()));
+        let channel = |c: f64| linear_to_srgb(c.clamp(0., 1.));
+        return Vec3d::new(channel(mapped.x), channel(mapped.y), channel(mapped.z));
+    }
+}
+
+//IEC 61966-2-1 transfer function
+pub(crate) fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        return 12.92 * linear;
+    }
+    return 1.055 * linear.powf(1. / 2.4) - 0.055;
+}
+
+//inverse of linear_to_srgb
+pub(crate) fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        return encoded / 12.92;
+    }
+    return ((encoded + 0.055) / 1.055).powf(2.4);
+}
+
+//rec. 709 luminance
+pub(crate) fn luminance(color: Vec3d) -> f64 {
+    return 0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z;
+}
+
+//maps the luminance and keeps the ratios between the channels
+fn scale_luminance<F>(color: Vec3d, curve: F) -> Vec3d where F: Fn(f64) -> f64 {
+    let l = luminance(color);
+    if l <= 0. {
+        return Vec3d::zero();
+    }
+    return color * (curve(l) / l);
+}
+
+//row-major 3x3 matrix times column vector
+fn mul(matrix: &[[f64; 3]; 3], v: Vec3d) -> Vec3d {
+    let row = |r: &[f64; 3]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+    return Vec3d::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]));
+}
+
+//Stephen Hill's fit: srgb to the aces working space, rrt + odt curve, back to srgb
+fn aces(color: Vec3d) -> Vec3d {
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let curve = |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    let v = mul(&INPUT, color);
+    return mul(&OUTPUT, Vec3d::new(curve(v.x), curve(v.y), curve(v.z)));
+}
+
+//minimal agx with the default look: inset, log2 encoding, sigmoid fit, outset and decoding with gamma 2.2
+fn agx(color: Vec3d) -> Vec3d {
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+    let encode = |c: f64| {
+        let log = c.max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        let x = (log - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    };
+    let v = mul(&INSET, color);
+    let v = mul(&OUTSET, Vec3d::new(encode(v.x), encode(v.y), encode(v.z)));
+    let decode = |c: f64| c.max(0.).powf(2.2);
+    return Vec3d::new(decode(v.x), decode(v.y), decode(v.z));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f64) -> Vec3d {
+        return Vec3d::new(value, value, value);
+    }
+
+    #[test]
+    fn test_srgb(){
+        assert_eq!(0., linear_to_srgb(0.));
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-12);
+        assert!((linear_to_srgb(0.18) - 0.4614).abs() < 1e-4);
+        assert_eq!(12.92 * 0.001, linear_to_srgb(0.001));
+        for value in [0., 0.001, 0.18, 0.5, 1.] {
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_reinhard(){
+        assert!((ToneMapping::Reinhard.map(gray(1.)).y - 0.5).abs() < 1e-12);
+        let extended = ToneMapping::ExtendedReinhard{ white_point: 4. };
+        assert!((extended.map(gray(4.)).x - 1.).abs() < 1e-12);
+        //hue is kept
+        let mapped = ToneMapping::Reinhard.map(Vec3d::new(4., 2., 0.));
+        assert!((mapped.x / mapped.y - 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_operators_are_monotonic_and_bounded(){
+        for name in ToneMapping::NAMES {
+            let display = DisplayTransform{ exposure: 0., tone_mapping: ToneMapping::from_name(name).unwrap() };
+            assert!(display.apply(gray(0.)).x < 0.01, "{}", name);
+            let mut previous = -1.;
+            for i in 0..100 {
+                let value = display.apply(gray(i as f64 * 0.25)).y;
+                assert!(value >= previous - 1e-9 && value <= 1., "{} at {}", name, i);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn test_exposure(){
+        let brighter = DisplayTransform{ exposure: 1., tone_mapping: ToneMapping::Linear };
+        assert_eq!(DisplayTransform::default().apply(gray(0.5)), brighter.apply(gray(0.25)));
+    }
+}
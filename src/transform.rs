@@ -0,0 +1,386 @@
+use std::ops::Mul;
+use crate::aabb::Aabb;
+use crate::lerp_vec3d;
+use crate::vec3d::Vec3d;
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+//affine transform as a 4x4 matrix acting on column vectors, the inverse is kept next to it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Transform {
+    pub(crate) fn identity() -> Self {
+        Self{
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+
+    //rows of the matrix, None if it cannot be inverted
+    pub(crate) fn from_matrix(matrix: Matrix) -> Option<Self> {
+        let inverse = invert(&matrix)?;
+        return Some(Self{
+            matrix,
+            inverse,
+        });
+    }
+
+    pub(crate) fn translation(offset: Vec3d) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][3] = offset[axis];
+            inverse[axis][3] = -offset[axis];
+        }
+        Self{
+            matrix,
+            inverse,
+        }
+    }
+
+    //factors must not be 0
+    pub(crate) fn scaling(factors: Vec3d) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][axis] = factors[axis];
+            inverse[axis][axis] = 1. / factors[axis];
+        }
+        Self{
+            matrix,
+            inverse,
+        }
+    }
+
+    //counterclockwise by the angle in degrees when looking against the axis
+    pub(crate) fn rotation(axis: Vec3d, degrees: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut matrix = IDENTITY;
+        let rows = [
+            [cos + a.x * a.x * (1. - cos), a.x * a.y * (1. - cos) - a.z * sin, a.x * a.z * (1. - cos) + a.y * sin],
+            [a.y * a.x * (1. - cos) + a.z * sin, cos + a.y * a.y * (1. - cos), a.y * a.z * (1. - cos) - a.x * sin],
+            [a.z * a.x * (1. - cos) - a.y * sin, a.z * a.y * (1. - cos) + a.x * sin, cos + a.z * a.z * (1. - cos)],
+        ];
+        for row in 0..3 {
+            matrix[row][..3].copy_from_slice(&rows[row]);
+        }
+        //rotations are orthogonal, the inverse is the transpose
+        Self{
+            matrix,
+            inverse: transpose(&matrix),
+        }
+    }
+
+    pub(crate) fn inverse(&self) -> Transform {
+        Self{
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub(crate) fn point(&self, p: Vec3d) -> Vec3d {
+        let m = &self.matrix;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1. {
+            return Vec3d::new(x, y, z);
+        }
+        return Vec3d::new(x, y, z) / w;
+    }
+
+    //directions ignore the translation
+    pub(crate) fn vector(&self, v: Vec3d) -> Vec3d {
+        let m = &self.matrix;
+        return Vec3d::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        );
+    }
+
+    //normals are transformed by the inverse transpose to stay perpendicular to the surface, the result is not normalized
+    pub(crate) fn normal(&self, n: Vec3d) -> Vec3d {
+        let m = &self.inverse;
+        return Vec3d::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        );
+    }
+
+    //box around the transformed corners
+    pub(crate) fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        if bbox.is_empty() || !bbox.is_finite() {
+            return *bbox;
+        }
+        return (0..8).fold(Aabb::empty(), |acc, corner| {
+            let x = if corner & 1 == 0 { bbox.min.x } else { bbox.max.x };
+            let y = if corner & 2 == 0 { bbox.min.y } else { bbox.max.y };
+            let z = if corner & 4 == 0 { bbox.min.z } else { bbox.max.z };
+            acc.expand(&self.point(Vec3d::new(x, y, z)))
+        });
+    }
+}
+
+//scale, then rotation around an axis through the origin, then translation
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Keyframe {
+    pub(crate) translation: Vec3d,
+    pub(crate) rotation_axis: Vec3d,
+    //in degrees
+    pub(crate) rotation_angle: f64,
+    pub(crate) scale: Vec3d,
+}
+
+impl Keyframe {
+    pub(crate) fn identity() -> Self {
+        Self{
+            translation: Vec3d::zero(),
+            rotation_axis: Vec3d::up(),
+            rotation_angle: 0.,
+            scale: Vec3d::new(1., 1., 1.),
+        }
+    }
+
+    pub(crate) fn transform(&self) -> Transform {
+        return Transform::translation(self.translation) * Transform::rotation(self.rotation_axis, self.rotation_angle) * Transform::scaling(self.scale);
+    }
+}
+
+//number of pieces the motion is split into when bounding it
+const MOTION_STEPS: usize = 32;
+
+//transform moving from start at time 0 to end at time 1, translation and scale are interpolated linearly
+//and the rotation along the shortest arc, so objects do not shear or shrink halfway
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct AnimatedTransform {
+    translation: (Vec3d, Vec3d),
+    scale: (Vec3d, Vec3d),
+    //unit quaternions (w, x, y, z) with a non-negative dot product
+    rotation: ([f64; 4], [f64; 4]),
+}
+
+impl AnimatedTransform {
+    pub(crate) fn new(start: Keyframe, end: Keyframe) -> Self {
+        let q0 = quaternion(start.rotation_axis, start.rotation_angle);
+        let mut q1 = quaternion(end.rotation_axis, end.rotation_angle);
+        if dot(&q0, &q1) < 0. {
+            q1 = q1.map(|c| -c);
+        }
+        Self{
+            translation: (start.translation, end.translation),
+            scale: (start.scale, end.scale),
+            rotation: (q0, q1),
+        }
+    }
+
+    //times outside of 0..1 keep the ends of the motion
+    pub(crate) fn at(&self, time: f64) -> Transform {
+        let t = time.clamp(0., 1.);
+        let translation = lerp_vec3d(self.translation.0, self.translation.1, t);
+        let scale = lerp_vec3d(self.scale.0, self.scale.1, t);
+        let q = slerp(&self.rotation.0, &self.rotation.1, t);
+        let axis = Vec3d::new(q[1], q[2], q[3]);
+        let rotation = if axis.length_squared() > 0. {
+            Transform::rotation(axis, 2. * axis.length().atan2(q[0]).to_degrees())
+        } else {
+            Transform::identity()
+        };
+        return Transform::translation(translation) * rotation * Transform::scaling(scale);
+    }
+
+    //union of the boxes at evenly spaced times, padded by how far any point of the box can move in between
+    pub(crate) fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        if bbox.is_empty() || !bbox.is_finite() {
+            return *bbox;
+        }
+        //farthest corner from the origin, where the rotation happens
+        let radius = bbox.min.abs().max(&bbox.max.abs()).length();
+        let largest_scale = self.scale.0.abs().max(&self.scale.1.abs());
+        let largest_scale = largest_scale.x.max(largest_scale.y).max(largest_scale.z);
+        let scale_change = (self.scale.1 - self.scale.0).abs();
+        let scale_change = scale_change.x.max(scale_change.y).max(scale_change.z);
+        let angle = 2. * dot(&self.rotation.0, &self.rotation.1).clamp(-1., 1.).acos();
+        //bound on the length of the path of any point over the whole motion
+        let path_length = (self.translation.1 - self.translation.0).length() + (angle * largest_scale + scale_change) * radius;
+        let padding = path_length / MOTION_STEPS as f64 / 2.;
+        let padding = Vec3d::new(padding, padding, padding);
+        return (0..=MOTION_STEPS).fold(Aabb::empty(), |acc, step| {
+            let step_box = self.at(step as f64 / MOTION_STEPS as f64).bounding_box(bbox);
+            acc.surrounding(&Aabb::new(step_box.min - padding, step_box.max + padding))
+        });
+    }
+}
+
+fn quaternion(axis: Vec3d, degrees: f64) -> [f64; 4] {
+    let a = axis.unit();
+    let (sin, cos) = (degrees.to_radians() / 2.).sin_cos();
+    return [cos, a.x * sin, a.y * sin, a.z * sin];
+}
+
+fn dot(a: &[f64; 4], b: &[f64; 4]) -> f64 {
+    return (0..4).map(|i| a[i] * b[i]).sum();
+}
+
+fn slerp(a: &[f64; 4], b: &[f64; 4], t: f64) -> [f64; 4] {
+    let cos = dot(a, b).clamp(-1., 1.);
+    //nearly equal rotations, the normalized linear interpolation is accurate enough
+    let (wa, wb) = if cos > 0.9995 {
+        (1. - t, t)
+    } else {
+        let angle = cos.acos();
+        let sin = angle.sin();
+        (((1. - t) * angle).sin() / sin, (t * angle).sin() / sin)
+    };
+    let q = [0, 1, 2, 3].map(|i| wa * a[i] + wb * b[i]);
+    let length = dot(&q, &q).sqrt();
+    return q.map(|c| c / length);
+}
+
+//a * b applies b first, then a
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform{
+            matrix: multiply(&self.matrix, &other.matrix),
+            inverse: multiply(&other.inverse, &self.inverse),
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.; 4]; 4];
+    for row in 0..4 {
+        for col in 0..4 {
+            result[row][col] = (0..4).map(|k| a[row][k] * b[k][col]).sum();
+        }
+    }
+    return result;
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut result = [[0.; 4]; 4];
+    for row in 0..4 {
+        for col in 0..4 {
+            result[row][col] = m[col][row];
+        }
+    }
+    return result;
+}
+
+//gauss-jordan elimination with partial pivoting
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let mut a = *m;
+    let mut inverse = IDENTITY;
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let scale = 1. / a[col][col];
+        for k in 0..4 {
+            a[col][k] *= scale;
+            inverse[col][k] *= scale;
+        }
+        for row in 0..4 {
+            if row != col && a[row][col] != 0. {
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inverse[row][k] -= factor * inverse[col][k];
+                }
+            }
+        }
+    }
+    return Some(inverse);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(expected: Vec3d, actual: Vec3d) {
+        assert!((expected - actual).length() < 1e-12, "expected {:?}, found {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_compose_and_invert(){
+        //scale, then rotate a quarter turn around y, then move
+        let transform = Transform::translation(Vec3d::new(1., 2., 3.)) * Transform::rotation(Vec3d::up(), 90.) * Transform::scaling(Vec3d::new(2., 2., 2.));
+        assert_near(Vec3d::new(1., 2., 1.), transform.point(Vec3d::right()));
+        assert_near(Vec3d::new(0., 0., -2.), transform.vector(Vec3d::right()));
+        assert_near(Vec3d::right(), transform.inverse().point(Vec3d::new(1., 2., 1.)));
+        let general = Transform::from_matrix(transform.matrix).unwrap();
+        for row in 0..4 {
+            for col in 0..4 {
+                assert!((general.inverse[row][col] - transform.inverse[row][col]).abs() < 1e-12);
+            }
+        }
+        assert!(Transform::from_matrix([[1., 0., 0., 0.], [2., 0., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]]).is_none());
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular(){
+        let transform = Transform::rotation(Vec3d::new(1., 1., 0.), 30.) * Transform::scaling(Vec3d::new(1., 4., 0.5));
+        let tangent = Vec3d::new(1., 1., 0.);
+        let normal = Vec3d::new(1., -1., 0.3);
+        assert_eq!(0., tangent.dot(&normal));
+        assert!(transform.vector(tangent).dot(&transform.normal(normal)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bounding_box(){
+        let bbox = Aabb::new(Vec3d::new(-1., -1., -1.), Vec3d::new(1., 1., 1.));
+        let rotated = Transform::rotation(Vec3d::forward(), 45.).bounding_box(&bbox);
+        assert!((rotated.max.x - 2f64.sqrt()).abs() < 1e-12 && (rotated.max.z - 1.).abs() < 1e-12);
+        assert_eq!(Aabb::infinite(), Transform::translation(Vec3d::up()).bounding_box(&Aabb::infinite()));
+    }
+
+    #[test]
+    fn test_animated_transform(){
+        let start = Keyframe{
+            translation: Vec3d::new(1., 0., 0.),
+            ..Keyframe::identity()
+        };
+        let end = Keyframe{
+            translation: Vec3d::new(3., 0., 0.),
+            rotation_axis: Vec3d::up(),
+            rotation_angle: 90.,
+            scale: Vec3d::new(2., 2., 2.),
+        };
+        let motion = AnimatedTransform::new(start, end);
+        assert_near(Vec3d::new(2., 0., 0.), motion.at(0.).point(Vec3d::right()));
+        assert_near(Vec3d::new(3., 0., -2.), motion.at(1.).point(Vec3d::right()));
+        assert_near(Vec3d::new(3., 0., -2.), motion.at(7.).point(Vec3d::right()));
+        //halfway the point is rotated by 45 degrees at 1.5 times the distance, not dragged along the chord
+        let halfway = motion.at(0.5).point(Vec3d::right()) - Vec3d::new(2., 0., 0.);
+        assert!((halfway.length() - 1.5).abs() < 1e-12 && (halfway.x + halfway.z).abs() < 1e-12);
+        assert_eq!(start.transform(), AnimatedTransform::new(start, start).at(0.3));
+
+        let bbox = Aabb::new(Vec3d::new(-1., -1., -1.), Vec3d::new(1., 2., 1.));
+        let bounds = motion.bounding_box(&bbox);
+        for step in 0..=1000 {
+            let moved = motion.at(step as f64 / 1000.).bounding_box(&bbox);
+            assert_eq!(bounds, bounds.surrounding(&moved));
+        }
+        //not much larger than needed
+        assert!(bounds.max.y < 4.5 && bounds.max.x < 5.5);
+    }
+}
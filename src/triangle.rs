@@ -0,0 +1,207 @@
+use std::ops::Range;
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+//flat triangles have no extent along one axis, pad them so the slab test stays robust
+const BBOX_PADDING: f64 = 1e-6;
+
+pub(crate) struct Triangle {
+    vertices: [Vec3d; 3],
+    material: Arc<Material>,
+}
+
+impl Triangle {
+    pub(crate) fn new(v0: Vec3d, v1: Vec3d, v2: Vec3d, material: Arc<Material>) -> Self {
+        Self{
+            vertices: [v0, v1, v2],
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let [v0, v1, v2] = self.vertices;
+        let (t, u, v) = intersect(ray, interval, v0, v1, v2)?;
+        let attributes = VertexAttributes{
+            positions: self.vertices,
+            normals: None,
+            uvs: None,
+        };
+        return Some(triangle_hit_record(ray, t, u, v, &attributes, self.material.clone()));
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return triangle_bounding_box(&self.vertices);
+    }
+}
+
+//vertex buffers shared by all triangles of the mesh, triangles reference them by index
+pub(crate) struct TriangleMesh {
+    positions: Vec<Vec3d>,
+    normals: Option<Vec<Vec3d>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<Material>,
+}
+
+impl TriangleMesh {
+    pub(crate) fn new(positions: Vec<Vec3d>, normals: Option<Vec<Vec3d>>, uvs: Option<Vec<(f64, f64)>>, indices: Vec<[usize; 3]>, material: Arc<Material>) -> Self {
+        if let Some(normals) = &normals {
+            assert_eq!(positions.len(), normals.len());
+        }
+        if let Some(uvs) = &uvs {
+            assert_eq!(positions.len(), uvs.len());
+        }
+        assert!(indices.iter().flatten().all(|&i| i < positions.len()));
+        Self{
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+        }
+    }
+
+    pub(crate) fn triangle_count(&self) -> usize {
+        return self.indices.len();
+    }
+
+    //one hittable per triangle, all referencing the same mesh
+    pub(crate) fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        return (0..mesh.triangle_count())
+            .map(|index| Box::new(MeshTriangle{ mesh: mesh.clone(), index }) as Box<dyn Hittable + Send + Sync>)
+            .collect();
+    }
+}
+
+pub(crate) struct MeshTriangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [Vec3d; 3] {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        return [self.mesh.positions[i0], self.mesh.positions[i1], self.mesh.positions[i2]];
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        let vertices = self.vertices();
+        let (t, u, v) = intersect(ray, interval, vertices[0], vertices[1], vertices[2])?;
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let attributes = VertexAttributes{
+            positions: vertices,
+            normals: self.mesh.normals.as_ref().map(|normals| [normals[i0], normals[i1], normals[i2]]),
+            uvs: self.mesh.uvs.as_ref().map(|uvs| [uvs[i0], uvs[i1], uvs[i2]]),
+        };
+        return Some(triangle_hit_record(ray, t, u, v, &attributes, self.mesh.material.clone()));
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        return triangle_bounding_box(&self.vertices());
+    }
+}
+
+//Möller–Trumbore, returns the ray distance and the barycentric coordinates of v1 and v2
+fn intersect(ray: &Ray, interval: Range<f64>, v0: Vec3d, v1: Vec3d, v2: Vec3d) -> Option<(f64, f64, f64)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction_no_unit.cross(edge2);
+    let det = edge1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let s = ray.origin - v0;
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction_no_unit.dot(&q) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+    let t = edge2.dot(&q) * inv_det;
+    if !interval.contains(&t) {
+        return None;
+    }
+    return Some((t, u, v));
+}
+
+//the three corners of a hit triangle, per-vertex normals are interpolated for smooth shading
+struct VertexAttributes {
+    positions: [Vec3d; 3],
+    normals: Option<[Vec3d; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+}
+
+//without vertex uvs the barycentric coordinates are used as texture coordinates
+fn triangle_hit_record(ray: &Ray, t: f64, u: f64, v: f64, attributes: &VertexAttributes, material: Arc<Material>) -> HitRecord {
+    let vertices = &attributes.positions;
+    let geometric_normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).unit();
+    let shading_normal = match &attributes.normals {
+        Some(n) => ((1. - u - v) * n[0] + u * n[1] + v * n[2]).unit(),
+        None => geometric_normal,
+    };
+    let uv = match &attributes.uvs {
+        Some(uvs) => (
+            (1. - u - v) * uvs[0].0 + u * uvs[1].0 + v * uvs[2].0,
+            (1. - u - v) * uvs[0].1 + u * uvs[1].1 + v * uvs[2].1,
+        ),
+        None => (u, v),
+    };
+    return HitRecord::with_shading_normal(ray.at(t), geometric_normal, shading_normal, t, ray.direction_no_unit, material).with_uv(uv);
+}
+
+fn triangle_bounding_box(vertices: &[Vec3d; 3]) -> Aabb {
+    let padding = Vec3d::new(BBOX_PADDING, BBOX_PADDING, BBOX_PADDING);
+    let bbox = Aabb::new(vertices[0], vertices[1]).expand(&vertices[2]);
+    return Aabb::new(bbox.min - padding, bbox.max + padding);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_barycentric(){
+        let material = Arc::new(Material::builder().build());
+        let triangle = Triangle::new(Vec3d::new(0.,0.,-1.), Vec3d::new(1.,0.,-1.), Vec3d::new(0.,1.,-1.), material);
+        let ray = Ray::new(Vec3d::new(0.25,0.5,0.), Vec3d::forward());
+        let (t, u, v) = intersect(&ray, 0.001..f64::INFINITY, triangle.vertices[0], triangle.vertices[1], triangle.vertices[2]).unwrap();
+        assert_eq!((1., 0.25, 0.5), (t, u, v));
+
+        let rec = triangle.hit(&ray, 0.001..f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+        assert_eq!(Vec3d::backward(), rec.normal);
+        assert!(triangle.hit(&Ray::new(Vec3d::new(0.75,0.75,0.), Vec3d::forward()), 0.001..f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_mesh_shares_vertices(){
+        let material = Arc::new(Material::builder().build());
+        let positions = vec![Vec3d::new(0.,0.,-1.), Vec3d::new(1.,0.,-1.), Vec3d::new(1.,1.,-1.), Vec3d::new(0.,1.,-1.)];
+        let normals = vec![Vec3d::backward(); 4];
+        let uvs = vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+        let mesh = Arc::new(TriangleMesh::new(positions, Some(normals), Some(uvs), vec![[0, 1, 2], [0, 2, 3]], material));
+        let triangles = TriangleMesh::triangles(&mesh);
+        assert_eq!(2, triangles.len());
+        assert_eq!(3, Arc::strong_count(&mesh));
+
+        let ray = Ray::new(Vec3d::new(0.25,0.75,0.), Vec3d::forward());
+        assert!(triangles[0].hit(&ray, 0.001..f64::INFINITY).is_none());
+        let rec = triangles[1].hit(&ray, 0.001..f64::INFINITY).unwrap();
+        assert_eq!(1., rec.t);
+        assert_eq!(Vec3d::backward(), rec.normal);
+        assert!((rec.uv.0 - 0.25).abs() < 1e-12 && (rec.uv.1 - 0.75).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::medium::{HenyeyGreenstein, Medium, MediumRegion};
+use crate::quad::Cuboid;
+use crate::vec3d::Vec3d;
+
+//heterogeneous media from dense voxel grids, like clouds and explosions exported from simulations
+
+//dense grid of values at the centers of the voxels, x varies fastest then y then z
+pub(crate) struct VoxelGrid {
+    resolution: [usize; 3],
+    values: Vec<f32>,
+    max: f64,
+    //placement stored in .vol files
+    bounds: Option<Aabb>,
+}
+
+impl VoxelGrid {
+    pub(crate) fn new(resolution: [usize; 3], values: Vec<f32>) -> Self {
+        assert_eq!(resolution[0] * resolution[1] * resolution[2], values.len());
+        let max = values.iter().fold(0f64, |max, &value| max.max(value as f64));
+        Self{
+            resolution,
+            values,
+            max,
+            bounds: None,
+        }
+    }
+
+    //a mitsuba .vol file, or raw little-endian 32 bit floats which need the resolution
+    pub(crate) fn read_from_file(path: &str, resolution: Option<[usize; 3]>) -> std::io::Result<VoxelGrid> {
+        let data = std::fs::read(path)?;
+        return Self::decode(&data, resolution).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)));
+    }
+
+    pub(crate) fn decode(data: &[u8], resolution: Option<[usize; 3]>) -> std::io::Result<VoxelGrid> {
+        if data.starts_with(b"VOL") {
+            return decode_vol(data);
+        }
+        let resolution = resolution.ok_or(invalid("raw grid files need a resolution"))?;
+        let size = voxel_count(resolution)?.checked_mul(4).ok_or(invalid("grid is too large"))?;
+        if data.len() != size {
+            return Err(invalid(&format!("expected {} bytes for a {}x{}x{} grid, found {}", size, resolution[0], resolution[1], resolution[2], data.len())));
+        }
+        return Self::checked(resolution, read_floats(data, 0, size / 4)?);
+    }
+
+    //grid of file data, densities have to be finite and not negative
+    fn checked(resolution: [usize; 3], values: Vec<f32>) -> std::io::Result<VoxelGrid> {
+        if let Some(value) = values.iter().find(|value| !(value.is_finite() && **value >= 0.)) {
+            return Err(invalid(&format!("voxel values must be finite and not negative, found {}", value)));
+        }
+        return Ok(Self::new(resolution, values));
+    }
+
+    pub(crate) fn bounds(&self) -> Option<Aabb> {
+        return self.bounds;
+    }
+
+    pub(crate) fn max(&self) -> f64 {
+        return self.max;
+    }
+
+    //trilinear interpolation at uvw in 0..1 over the whole grid, 0 outside
+    pub(crate) fn lookup(&self, uvw: Vec3d) -> f64 {
+        if !(0. ..=1.).contains(&uvw.x) || !(0. ..=1.).contains(&uvw.y) || !(0. ..=1.).contains(&uvw.z) {
+            return 0.;
+        }
+        //values sit at the voxel centers, the outer half voxels repeat the border
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        let mut fraction = [0.; 3];
+        for axis in 0..3 {
+            let position = (uvw[axis] * self.resolution[axis] as f64 - 0.5).max(0.);
+            let cell = (position.floor() as usize).min(self.resolution[axis] - 1);
+            low[axis] = cell;
+            high[axis] = (cell + 1).min(self.resolution[axis] - 1);
+            fraction[axis] = position - cell as f64;
+        }
+        let value = |x: usize, y: usize, z: usize| self.values[(z * self.resolution[1] + y) * self.resolution[0] + x] as f64;
+        let mut result = 0.;
+        for (z, wz) in [(low[2], 1. - fraction[2]), (high[2], fraction[2])] {
+            for (y, wy) in [(low[1], 1. - fraction[1]), (high[1], fraction[1])] {
+                for (x, wx) in [(low[0], 1. - fraction[0]), (high[0], fraction[0])] {
+                    result += wx * wy * wz * value(x, y, z);
+                }
+            }
+        }
+        return result;
+    }
+}
+
+//header "VOL", version 3, encoding 1 for 32 bit floats, resolution, channels and the bounding box, then the data
+fn decode_vol(data: &[u8]) -> std::io::Result<VoxelGrid> {
+    if data.get(3) != Some(&3) {
+        return Err(invalid("unsupported .vol version"));
+    }
+    let int = |pos: usize| -> std::io::Result<i32> {
+        let bytes = data.get(pos..pos + 4).ok_or(invalid("grid file is truncated"))?;
+        return Ok(i32::from_le_bytes(bytes.try_into().unwrap()));
+    };
+    if int(4)? != 1 {
+        return Err(invalid("only 32 bit float .vol files are supported"));
+    }
+    let (x, y, z) = (int(8)?, int(12)?, int(16)?);
+    if x <= 0 || y <= 0 || z <= 0 {
+        return Err(invalid("invalid grid resolution"));
+    }
+    if int(20)? != 1 {
+        return Err(invalid("only single channel .vol files are supported"));
+    }
+    let bbox = read_floats(data, 24, 6)?;
+    let resolution = [x as usize, y as usize, z as usize];
+    let values = read_floats(data, 48, voxel_count(resolution)?)?;
+    let mut grid = VoxelGrid::checked(resolution, values)?;
+    let min = Vec3d::new(bbox[0] as f64, bbox[1] as f64, bbox[2] as f64);
+    let max = Vec3d::new(bbox[3] as f64, bbox[4] as f64, bbox[5] as f64);
+    if min.x < max.x && min.y < max.y && min.z < max.z {
+        grid.bounds = Some(Aabb::new(min, max));
+    }
+    return Ok(grid);
+}
+
+fn voxel_count(resolution: [usize; 3]) -> std::io::Result<usize> {
+    return resolution[0].checked_mul(resolution[1]).and_then(|count| count.checked_mul(resolution[2])).ok_or(invalid("grid is too large"));
+}
+
+fn read_floats(data: &[u8], pos: usize, count: usize) -> std::io::Result<Vec<f32>> {
+    let end = count.checked_mul(4).and_then(|size| size.checked_add(pos)).ok_or(invalid("grid is too large"))?;
+    let bytes = data.get(pos..end).ok_or(invalid("grid file is truncated"))?;
+    return Ok(bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap())).collect());
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+}
+
+//medium with the coefficients scaled by a density grid stretched over the bounds
+pub(crate) struct GridMedium {
+    density: VoxelGrid,
+    bounds: Aabb,
+    absorption: Vec3d,
+    scattering: Vec3d,
+    phase: HenyeyGreenstein,
+    //grid and color of the emitted radiance, scaled by the absorption like in pbrt
+    emission: Option<(VoxelGrid, Vec3d)>,
+}
+
+impl GridMedium {
+    pub(crate) fn new(density: VoxelGrid, bounds: Aabb, absorption: Vec3d, scattering: Vec3d, g: f64) -> Self {
+        Self{
+            density,
+            bounds,
+            absorption,
+            scattering,
+            phase: HenyeyGreenstein::new(g),
+            emission: None,
+        }
+    }
+
+    //the emission grid covers the same bounds and may have its own resolution
+    pub(crate) fn with_emission(mut self, grid: VoxelGrid, color: Vec3d) -> Self {
+        self.emission = Some((grid, color));
+        self
+    }
+
+    //the medium fills its bounds
+    pub(crate) fn into_region(self) -> MediumRegion {
+        let boundary = Cuboid::new(self.bounds.min, self.bounds.max, Arc::new(Material::builder().build()));
+        return MediumRegion::bounded(Arc::new(boundary), Box::new(self));
+    }
+
+    fn uvw(&self, point: Vec3d) -> Vec3d {
+        let extent = self.bounds.extent();
+        let local = point - self.bounds.min;
+        return Vec3d::new(local.x / extent.x, local.y / extent.y, local.z / extent.z);
+    }
+}
+
+impl Medium for GridMedium {
+    fn coefficients(&self, point: Vec3d) -> (Vec3d, Vec3d) {
+        let density = self.density.lookup(self.uvw(point));
+        return (density * self.absorption, density * self.scattering);
+    }
+
+    fn emission(&self, point: Vec3d) -> Vec3d {
+        return match &self.emission {
+            Some((grid, color)) => {
+                let uvw = self.uvw(point);
+                (self.density.lookup(uvw) * grid.lookup(uvw)) * self.absorption.comp_vise(*color)
+            }
+            None => Vec3d::zero(),
+        };
+    }
+
+    fn majorant(&self) -> f64 {
+        let extinction = self.density.max() * (self.absorption + self.scattering);
+        return extinction.x.max(extinction.y).max(extinction.z);
+    }
+
+    fn phase(&self) -> HenyeyGreenstein {
+        return self.phase;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::medium::{HomogeneousMedium, MediumList};
+    use crate::ray::Ray;
+
+    fn vol_file(resolution: [i32; 3], bbox: [f32; 6], values: &[f32]) -> Vec<u8> {
+        let mut data = b"VOL\x03".to_vec();
+        for int in [1, resolution[0], resolution[1], resolution[2], 1] {
+            data.extend(int.to_le_bytes());
+        }
+        for float in bbox.iter().chain(values) {
+            data.extend(float.to_le_bytes());
+        }
+        return data;
+    }
+
+    #[test]
+    fn test_trilinear_lookup(){
+        //x grows from 0 to 1 and z doubles the value
+        let grid = VoxelGrid::new([2, 1, 2], vec![0., 1., 0., 2.]);
+        assert_eq!(2., grid.max());
+        assert_eq!(0., grid.lookup(Vec3d::new(0.25, 0.5, 0.25)));
+        assert_eq!(1., grid.lookup(Vec3d::new(0.75, 0.5, 0.25)));
+        assert!((grid.lookup(Vec3d::new(0.5, 0.5, 0.25)) - 0.5).abs() < 1e-12);
+        assert!((grid.lookup(Vec3d::new(0.5, 0.5, 0.5)) - 0.75).abs() < 1e-12);
+        //the border voxels extend to the edges
+        assert_eq!(2., grid.lookup(Vec3d::new(1., 0., 1.)));
+        assert_eq!(0., grid.lookup(Vec3d::new(1.01, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn test_decode(){
+        let grid = VoxelGrid::decode(&vol_file([2, 1, 1], [-1., -2., -3., 1., 2., 3.], &[0.5, 1.5]), None).unwrap();
+        assert_eq!([2, 1, 1], grid.resolution);
+        assert_eq!(vec![0.5, 1.5], grid.values);
+        let bounds = grid.bounds().unwrap();
+        assert_eq!((Vec3d::new(-1., -2., -3.), Vec3d::new(1., 2., 3.)), (bounds.min, bounds.max));
+
+        let raw: Vec<u8> = [1f32, 2., 3., 4.].iter().flat_map(|value| value.to_le_bytes()).collect();
+        let grid = VoxelGrid::decode(&raw, Some([1, 2, 2])).unwrap();
+        assert_eq!(4., grid.max());
+        assert!(grid.bounds().is_none());
+
+        assert_eq!("raw grid files need a resolution", VoxelGrid::decode(&raw, None).err().unwrap().to_string());
+        assert_eq!("expected 32 bytes for a 2x2x2 grid, found 16", VoxelGrid::decode(&raw, Some([2, 2, 2])).err().unwrap().to_string());
+        let truncated = vol_file([2, 2, 2], [0., 0., 0., 1., 1., 1.], &[1.; 7]);
+        assert_eq!("grid file is truncated", VoxelGrid::decode(&truncated, None).err().unwrap().to_string());
+
+        //sizes that overflow
+        let huge = vol_file([i32::MAX, i32::MAX, i32::MAX], [0., 0., 0., 1., 1., 1.], &[]);
+        assert!(VoxelGrid::decode(&huge, None).is_err());
+        assert_eq!("grid is too large", VoxelGrid::decode(&[], Some([1 << 32, 1 << 32, 1])).err().unwrap().to_string());
+        assert_eq!("grid is too large", VoxelGrid::decode(&[], Some([1 << 62, 4, 1])).err().unwrap().to_string());
+        //negative densities would let the transmittance exceed 1
+        let negative = vol_file([1, 1, 1], [0., 0., 0., 1., 1., 1.], &[-1.]);
+        assert_eq!("voxel values must be finite and not negative, found -1", VoxelGrid::decode(&negative, None).err().unwrap().to_string());
+        let nan: Vec<u8> = f32::NAN.to_le_bytes().to_vec();
+        assert!(VoxelGrid::decode(&nan, Some([1, 1, 1])).is_err());
+    }
+
+    #[test]
+    fn test_constant_grid_matches_homogeneous_medium(){
+        let mut rng = StdRng::from_seed([8; 32]);
+        let absorption = Vec3d::new(0.2, 0.5, 0.);
+        let scattering = Vec3d::new(0.3, 0.5, 1.5);
+        let bounds = Aabb::new(Vec3d::new(-1., -1., -4.), Vec3d::new(1., 1., -2.));
+        //half the density in the grid is made up by twice the coefficients
+        let grid = GridMedium::new(VoxelGrid::new([2, 2, 2], vec![0.5; 8]), bounds, 2. * absorption, 2. * scattering, 0.3);
+        let media = MediumList::new(vec![grid.into_region()]);
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        let extinction = absorption + scattering;
+        let expected = Vec3d::new(f64::exp(-2. * extinction.x), f64::exp(-2. * extinction.y), f64::exp(-2. * extinction.z));
+        let n = 100000;
+        //ratio tracking
+        let transmittance = (0..n).fold(Vec3d::zero(), |sum, _| sum + media.transmittance(&ray, f64::INFINITY, &mut rng) / n as f64);
+        assert!((transmittance - expected).length() < 0.01, "transmittance {:?}, expected {:?}", transmittance, expected);
+        //delta tracking
+        let (mut passed, mut scattered) = (Vec3d::zero(), Vec3d::zero());
+        for _ in 0..n {
+            let sample = media.sample(&ray, f64::INFINITY, &mut rng);
+            match sample.scatter {
+                Some((t, phase)) => {
+                    assert!((2. ..=4.).contains(&t));
+                    assert_eq!(HenyeyGreenstein::new(0.3), phase);
+                    scattered = scattered + sample.weight / n as f64;
+                }
+                None => passed = passed + sample.weight / n as f64,
+            }
+        }
+        for c in 0..3 {
+            let expected_scattered = scattering[c] / extinction[c] * (1. - expected[c]);
+            assert!((passed[c] - expected[c]).abs() < 0.01, "channel {}: passed {:?}, expected {:?}", c, passed, expected);
+            assert!((scattered[c] - expected_scattered).abs() < 0.01, "channel {}: scattered {:?}", c, scattered);
+        }
+
+        //overlapping a homogeneous medium both are tracked together
+        let bounds = Aabb::new(Vec3d::new(-1., -1., -4.), Vec3d::new(1., 1., -2.));
+        let media = MediumList::new(vec![
+            GridMedium::new(VoxelGrid::new([1, 1, 1], vec![1.]), bounds, absorption, Vec3d::zero(), 0.).into_region(),
+            MediumRegion::everywhere(Box::new(HomogeneousMedium::new(Vec3d::new(0.1, 0.1, 0.1), Vec3d::zero(), 0.))),
+        ]);
+        let transmittance = (0..n).fold(Vec3d::zero(), |sum, _| sum + media.transmittance(&ray, 5., &mut rng) / n as f64);
+        let expected = Vec3d::new(f64::exp(-0.9), f64::exp(-1.5), f64::exp(-0.5));
+        assert!((transmittance - expected).length() < 0.01, "transmittance {:?}, expected {:?}", transmittance, expected);
+    }
+
+    #[test]
+    fn test_emission(){
+        let mut rng = StdRng::from_seed([9; 32]);
+        //an absorbing slab glowing twice as bright in its far half
+        let bounds = Aabb::new(Vec3d::new(-1., -1., -4.), Vec3d::new(1., 1., -2.));
+        let absorption = Vec3d::new(0.5, 0.5, 0.5);
+        let color = Vec3d::new(1., 0.5, 0.);
+        let fire = GridMedium::new(VoxelGrid::new([1, 1, 1], vec![1.]), bounds, absorption, Vec3d::zero(), 0.)
+            .with_emission(VoxelGrid::new([1, 1, 2], vec![2., 1.]), color);
+        let media = MediumList::new(vec![fire.into_region()]);
+        let ray = Ray::new(Vec3d::zero(), Vec3d::forward());
+        let n = 100000;
+        let emitted = (0..n).fold(Vec3d::zero(), |sum, _| {
+            let sample = media.sample(&ray, f64::INFINITY, &mut rng);
+            assert!(sample.scatter.is_none());
+            sum + sample.emitted / n as f64
+        });
+        //integral of 0.5 * le(t) * exp(-0.5 t) over the two units, le ramps from 1 to 2 over the middle unit
+        let steps = 100000;
+        let integral = (0..steps).map(|i| {
+            let t = (i as f64 + 0.5) / steps as f64 * 2.;
+            let le = (t - 0.5).clamp(0., 1.) + 1.;
+            0.5 * le * f64::exp(-0.5 * t) * 2. / steps as f64
+        }).sum::<f64>();
+        assert!((emitted - integral * color).length() < 0.01, "emitted {:?}, expected {}", emitted, integral);
+    }
+}
@@ -0,0 +1,47 @@
+use std::ops::Range;
+use crate::bvh::Bvh;
+use crate::hit::{HitRecord, Hittable};
+use crate::light::LightList;
+use crate::medium::{MediumList, MediumRegion};
+use crate::ray::Ray;
+use crate::vec3d::Vec3d;
+
+//offset along rays leaving a surface to avoid hitting the surface itself
+pub(crate) const SHADOW_EPSILON: f64 = 0.001;
+
+//everything the integrator needs from a scene
+pub(crate) struct World {
+    pub(crate) objects: Bvh,
+    pub(crate) lights: LightList,
+    pub(crate) media: MediumList,
+}
+
+impl World {
+    pub(crate) fn new(objects: Bvh, lights: LightList) -> Self {
+        Self{
+            objects,
+            lights,
+            media: MediumList::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn with_media(mut self, media: Vec<MediumRegion>) -> Self {
+        self.media = MediumList::new(media);
+        self
+    }
+
+    pub(crate) fn hit(&self, ray: &Ray, interval: Range<f64>) -> Option<HitRecord> {
+        return self.objects.hit(ray, interval);
+    }
+
+    //fraction of the light reaching origin from the point at distance along the unit direction, at the time of the path
+    //0 if a surface is in between, otherwise the attenuation by the media, estimated with rng through heterogeneous media
+    pub(crate) fn transmittance(&self, origin: Vec3d, direction: Vec3d, distance: f64, time: f64, rng: &mut dyn rand::RngCore) -> Vec3d {
+        let ray = Ray::new(origin, direction).with_time(time);
+        let end = distance - SHADOW_EPSILON;
+        if end > SHADOW_EPSILON && self.objects.hit(&ray, SHADOW_EPSILON..end).is_some() {
+            return Vec3d::zero();
+        }
+        return self.media.transmittance(&ray, distance, rng);
+    }
+}
@@ -0,0 +1,432 @@
+//zlib (RFC 1950) streams with deflate (RFC 1951) compression, as used by PNG and ZIP compressed OpenEXR
+
+const WINDOW_SIZE: usize = 1 << 15;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+//limits the search along the hash chain, trades compression for speed
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    //5552 is the largest block for which b cannot overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    return (b << 16) | a;
+}
+
+//complete zlib stream: header, deflate data and adler32 checksum
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    //deflate with 32k window, default compression level, no preset dictionary
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    return out;
+}
+
+//raw deflate data in a single block with the fixed huffman codes and greedy lz77 matching
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    //final block, fixed huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = find_match(data, pos, &head, &prev);
+        let advance = if length >= MIN_MATCH {
+            write_length(&mut writer, length);
+            write_distance(&mut writer, distance);
+            length
+        } else {
+            write_literal(&mut writer, data[pos] as u16);
+            1
+        };
+        for p in pos..pos + advance {
+            if p + MIN_MATCH <= data.len() {
+                let h = hash(data, p);
+                prev[p % WINDOW_SIZE] = head[h];
+                head[h] = p;
+            }
+        }
+        pos += advance;
+    }
+    write_literal(&mut writer, 256);
+    return writer.finish();
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    return (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1);
+}
+
+fn find_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+    let mut chain = 0;
+    while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+        let length = data[candidate..].iter().zip(&data[pos..pos + max_length]).take_while(|(a, b)| a == b).count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        let next = prev[candidate % WINDOW_SIZE];
+        //the slot may have been reused by a newer position
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+        chain += 1;
+    }
+    return best;
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    let (code, bits) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    writer.write_code(code as u32, bits);
+}
+
+fn write_length(writer: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_literal(writer, 257 + index as u16);
+    writer.write_bits((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+}
+
+fn write_distance(writer: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    writer.write_code(index as u32, 5);
+    writer.write_bits((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+}
+
+//inverse of compress, the checksum is verified
+pub(crate) fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    if data.len() < 6 {
+        return Err(corrupt("zlib stream is truncated"));
+    }
+    let (cmf, flags) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || !((cmf as u16) << 8 | flags as u16).is_multiple_of(31) {
+        return Err(corrupt("invalid zlib header"));
+    }
+    if flags & 0x20 != 0 {
+        return Err(corrupt("zlib streams with a preset dictionary are not supported"));
+    }
+    let (out, end) = inflate(&data[2..])?;
+    let checksum = data.get(2 + end..2 + end + 4).ok_or(corrupt("zlib checksum is missing"))?;
+    if adler32(&out).to_be_bytes() != checksum {
+        return Err(corrupt("zlib checksum does not match"));
+    }
+    return Ok(out);
+}
+
+//raw deflate data, returns the decoded bytes and the number of bytes consumed
+pub(crate) fn inflate(data: &[u8]) -> std::io::Result<(Vec<u8>, usize)> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                //stored block, starts at the next byte
+                reader.align();
+                let length = reader.bits(16)?;
+                let complement = reader.bits(16)?;
+                if length != !complement & 0xffff {
+                    return Err(corrupt("stored block length does not match its complement"));
+                }
+                for _ in 0..length {
+                    out.push(reader.bits(8)? as u8);
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(corrupt("invalid deflate block type")),
+        }
+        if last {
+            break;
+        }
+    }
+    return Ok((out, reader.bytes_consumed()));
+}
+
+fn corrupt(message: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> std::io::Result<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(corrupt("invalid length symbol"));
+        }
+        let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distances.decode(reader)? as usize;
+        if index >= DISTANCE_BASE.len() {
+            return Err(corrupt("invalid distance symbol"));
+        }
+        let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > out.len() {
+            return Err(corrupt("distance reaches before the start of the data"));
+        }
+        //the copy may overlap the bytes it produces
+        let start = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    return (Huffman::new(&lengths), Huffman::new(&[5; 30]));
+}
+
+//code lengths of the block, themselves huffman coded
+fn dynamic_codes(reader: &mut BitReader) -> std::io::Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &index in &ORDER[..code_length_count] {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + reader.bits(2)?),
+                None => return Err(corrupt("repeated code length without a previous one")),
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(corrupt("code lengths overflow the alphabets"));
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("missing end of block code"));
+    }
+    return Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])));
+}
+
+//canonical huffman code, decoded one bit at a time
+struct Huffman {
+    //number of codes of each length
+    counts: [u16; 16],
+    //symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|&s| lengths[s as usize] != 0).collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Self{
+            counts,
+            symbols,
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> std::io::Result<u16> {
+        //first code of the current length and index of its symbol
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err(corrupt("invalid huffman code"));
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self{
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, bits: u32) -> std::io::Result<u32> {
+        while self.count < bits {
+            let byte = *self.data.get(self.pos).ok_or(corrupt("deflate data is truncated"))?;
+            self.buffer |= (byte as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1u64 << bits) - 1)) as u32;
+        self.buffer >>= bits;
+        self.count -= bits;
+        return Ok(value);
+    }
+
+    //drops the bits left in the current byte
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.buffer >>= skip;
+        self.count -= skip;
+    }
+
+    //whole bytes, the buffer never holds more than the partial byte after a read
+    fn bytes_consumed(&self) -> usize {
+        return self.pos - (self.count / 8) as usize;
+    }
+}
+
+//deflate packs bits starting at the least significant bit
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self{
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    //huffman codes are stored starting with the most significant bit
+    fn write_code(&mut self, code: u32, bits: u32) {
+        let reversed = code.reverse_bits() >> (32 - bits);
+        self.write_bits(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        return self.bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adler32(){
+        assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+        assert_eq!(1, adler32(&[]));
+    }
+
+    #[test]
+    fn test_empty_stream(){
+        //final fixed block with only the end of block symbol
+        assert_eq!(vec![0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01], compress(&[]));
+    }
+
+    #[test]
+    fn test_repetitions_are_compressed(){
+        let data = vec![7u8; 10000];
+        assert!(compress(&data).len() < 100);
+    }
+
+    #[test]
+    fn test_round_trip(){
+        let data: Vec<u8> = (0..20000u32).map(|i| (((i * i) % 251) ^ (i / 100)) as u8).collect();
+        assert_eq!(data, decompress(&compress(&data)).unwrap());
+        assert_eq!(Vec::<u8>::new(), decompress(&compress(&[])).unwrap());
+        let mut broken = compress(&data);
+        let last = broken.len() - 1;
+        broken[last] ^= 1;
+        assert!(decompress(&broken).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stored_and_dynamic_blocks(){
+        //stored block with "abc"
+        assert_eq!(b"abc".to_vec(), inflate(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c']).unwrap().0);
+        //300 random letters compressed by zlib at level 9, a single block with dynamic codes
+        let dynamic = [0x78, 0xda, 0x35, 0x90, 0x61, 0x1a, 0x00, 0x30, 0x04, 0x42, 0xcf, 0xea, 0xd5, 0xfd, 0xcf, 0x30, 0x61, 0xfb, 0x81, 0x2f, 0x49, 0x56, 0x48, 0xaa, 0x7e, 0x1d, 0xa8, 0xa9, 0x9c, 0xb2, 0x41, 0x92, 0x3a, 0x3a, 0x20, 0xc9, 0x41, 0x96, 0x45, 0x1d, 0xbd, 0x9b, 0x32, 0x4e, 0x87, 0xc1, 0x0d, 0x34, 0x53, 0x1e, 0x8a, 0x4b, 0x44, 0x65, 0xa4, 0x66, 0xde, 0x08, 0x56, 0x50, 0xd7, 0x19, 0xfd, 0x46, 0xd9, 0x05, 0xc1, 0xad, 0x29, 0x13, 0x5b, 0x2c, 0xad, 0x6a, 0x9f, 0xce, 0x0e, 0xc4, 0xfa, 0x5d, 0xbe, 0xd6, 0x1d, 0x37, 0x10, 0x83, 0x49, 0xca, 0x5d, 0x31, 0x77, 0x94, 0x3b, 0x72, 0x46, 0x6b, 0xcf, 0x80, 0xdb, 0x5d, 0x77, 0x7f, 0xc5, 0xfc, 0xff, 0x85, 0x75, 0xcb, 0xb8, 0x5f, 0xcb, 0x3c, 0x66, 0x44, 0x72, 0x90];
+        assert_eq!(2, (dynamic[2] >> 1) & 3);
+        //the checksum confirms the content
+        let out = decompress(&dynamic).unwrap();
+        assert_eq!(300, out.len());
+        assert!(out.starts_with(b"abcccaaaacaabacaaaadcaab"));
+    }
+}